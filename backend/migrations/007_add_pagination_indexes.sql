@@ -0,0 +1,5 @@
+-- Keyset pagination indexes (created_at + id cursors)
+DROP INDEX IF EXISTS idx_messages_created;
+CREATE INDEX idx_messages_chat_created_id ON messages(chat_id, created_at, id);
+CREATE INDEX idx_sessions_profile_created_id ON study_sessions(profile_id, created_at, id);
+CREATE INDEX idx_documents_session_created_id ON documents(session_id, created_at, id);
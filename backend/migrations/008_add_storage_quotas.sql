@@ -0,0 +1,5 @@
+-- Uploaded file sizes (NULL for documents created before sizes were tracked)
+ALTER TABLE documents ADD COLUMN file_size BIGINT;
+
+-- Account tier selecting the storage quota ("free", "pro")
+ALTER TABLE profiles ADD COLUMN tier VARCHAR(32) NOT NULL DEFAULT 'free';
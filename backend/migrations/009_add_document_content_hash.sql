@@ -0,0 +1,4 @@
+-- SHA-256 of the uploaded file (hex), used to detect duplicates and reuse extractions
+ALTER TABLE documents ADD COLUMN content_hash CHAR(64);
+
+CREATE INDEX idx_documents_content_hash ON documents(content_hash);
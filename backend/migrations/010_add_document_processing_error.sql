@@ -0,0 +1,2 @@
+-- Reason shown to the user when processing fails (e.g. encrypted or too many pages)
+ALTER TABLE documents ADD COLUMN processing_error TEXT;
@@ -0,0 +1,21 @@
+-- Extracted text per page (documents.content_text is rebuilt from these)
+CREATE TABLE document_pages (
+    document_id UUID NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page_number INTEGER NOT NULL,
+    content_text TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (document_id, page_number)
+);
+
+-- Corrections made by students to extracted text (kept across reprocessing)
+CREATE TABLE document_page_edits (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    document_id UUID NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page_number INTEGER NOT NULL,
+    profile_id UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    previous_text TEXT NOT NULL,
+    new_text TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_document_page_edits_document ON document_page_edits(document_id, page_number, created_at);
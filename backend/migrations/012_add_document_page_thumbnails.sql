@@ -0,0 +1,2 @@
+-- Storage path of the rendered page image (NULL when rendering or upload failed)
+ALTER TABLE document_pages ADD COLUMN thumbnail_path VARCHAR(255);
@@ -0,0 +1,8 @@
+-- Kind of study material, detected during ingestion
+CREATE TYPE document_type AS ENUM (
+    'SLIDES', 'NOTES', 'PAST_EXAM', 'PROBLEM_SET', 'TEXTBOOK_CHAPTER', 'SYLLABUS', 'OTHER'
+);
+
+ALTER TABLE documents ADD COLUMN document_type document_type;
+ALTER TABLE documents ADD COLUMN course_name VARCHAR(255);
+ALTER TABLE documents ADD COLUMN year INTEGER;
@@ -0,0 +1,17 @@
+-- Individual problems extracted from past exams and problem sets
+CREATE TABLE problems (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    document_id UUID NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    topic_id UUID REFERENCES topics(id) ON DELETE SET NULL,
+    position INTEGER NOT NULL,
+    label VARCHAR(50),
+    statement TEXT NOT NULL,
+    sub_parts TEXT[] NOT NULL DEFAULT '{}',
+    solution TEXT,
+    points DOUBLE PRECISION,
+    page_number INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_problems_document ON problems(document_id, position);
+CREATE INDEX idx_problems_topic ON problems(topic_id);
@@ -0,0 +1,11 @@
+-- Sources an assistant message cites (numbered as they appear in its content)
+CREATE TABLE message_citations (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    marker INTEGER NOT NULL,
+    document_id UUID NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page_start INTEGER,
+    page_end INTEGER,
+    PRIMARY KEY (message_id, marker)
+);
+
+CREATE INDEX idx_message_citations_document ON message_citations(document_id);
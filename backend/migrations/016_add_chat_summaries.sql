@@ -0,0 +1,6 @@
+-- Rolling summary of the messages that no longer fit in the chat history window
+ALTER TABLE chats ADD COLUMN summary TEXT;
+
+-- Newest message covered by the summary (created_at + id keyset)
+ALTER TABLE chats ADD COLUMN summary_through_at TIMESTAMPTZ;
+ALTER TABLE chats ADD COLUMN summary_through_id UUID;
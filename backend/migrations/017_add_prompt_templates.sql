@@ -0,0 +1,15 @@
+-- Prompt template overrides, editable without a redeploy
+-- (the newest active version per name and locale is used; '' is the default locale)
+CREATE TABLE prompt_templates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(100) NOT NULL,
+    locale VARCHAR(10) NOT NULL DEFAULT '',
+    version INTEGER NOT NULL,
+    body TEXT NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (name, locale, version)
+);
+
+-- Template version that produced an assistant message (e.g. topic_chat.pt@db:v3)
+ALTER TABLE messages ADD COLUMN template_version VARCHAR(150);
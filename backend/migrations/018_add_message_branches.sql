@@ -0,0 +1,17 @@
+-- Conversation tree: each message answers or follows its parent (NULL for the first message)
+ALTER TABLE messages ADD COLUMN parent_id UUID REFERENCES messages(id) ON DELETE CASCADE;
+
+-- Messages on the chat's current branch; regenerated and edited messages stay as inactive alternatives
+ALTER TABLE messages ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Existing chats are a single branch: link every message to the one before it
+UPDATE messages m
+SET parent_id = p.previous_id
+FROM (
+    SELECT id, LAG(id) OVER (PARTITION BY chat_id ORDER BY created_at, id) AS previous_id
+    FROM messages
+) p
+WHERE m.id = p.id;
+
+CREATE INDEX idx_messages_parent ON messages(parent_id);
+CREATE INDEX idx_messages_active ON messages(chat_id, created_at) WHERE is_active;
@@ -0,0 +1,14 @@
+-- Model and prompt context that produced an assistant message
+ALTER TABLE messages ADD COLUMN model VARCHAR(100);
+ALTER TABLE messages ADD COLUMN generation_context JSONB;
+
+-- Student ratings of assistant messages (one per message, updated on re-rating)
+CREATE TABLE message_feedback (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    rating VARCHAR(10) NOT NULL CHECK (rating IN ('up', 'down')),
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_message_feedback_updated ON message_feedback(updated_at);
@@ -0,0 +1,15 @@
+-- Images attached to chat messages (stored in the documents bucket)
+CREATE TABLE message_attachments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    -- Unset until the message it was uploaded for is sent
+    message_id UUID REFERENCES messages(id) ON DELETE CASCADE,
+    file_path TEXT NOT NULL,
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(100) NOT NULL,
+    file_size BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_message_attachments_message ON message_attachments(message_id);
+CREATE INDEX idx_message_attachments_chat ON message_attachments(chat_id);
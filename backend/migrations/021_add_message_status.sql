@@ -0,0 +1,9 @@
+-- Assistant replies are stored as a pending placeholder before the model is called
+CREATE TYPE message_status AS ENUM ('pending', 'complete', 'failed');
+
+ALTER TABLE messages ADD COLUMN status message_status NOT NULL DEFAULT 'complete';
+-- Pending replies left behind by a restart are failed once this is old enough
+ALTER TABLE messages ADD COLUMN status_updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Only one reply can be generated at a time in a chat
+CREATE UNIQUE INDEX idx_messages_one_pending ON messages(chat_id) WHERE status = 'pending';
@@ -0,0 +1,2 @@
+-- Each stored file belongs to exactly one document (completing an upload twice must not duplicate it)
+CREATE UNIQUE INDEX idx_documents_file_path ON documents(file_path);
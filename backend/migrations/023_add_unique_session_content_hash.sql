@@ -0,0 +1,13 @@
+-- A session holds each file at most once, even when identical uploads race.
+-- Later duplicates keep their rows but lose their hash, so the index can be built.
+UPDATE documents d
+SET content_hash = NULL
+WHERE d.content_hash IS NOT NULL
+  AND EXISTS (
+      SELECT 1 FROM documents o
+      WHERE o.session_id = d.session_id
+        AND o.content_hash = d.content_hash
+        AND (o.created_at, o.id) < (d.created_at, d.id)
+  );
+
+CREATE UNIQUE INDEX idx_documents_session_content_hash ON documents(session_id, content_hash);
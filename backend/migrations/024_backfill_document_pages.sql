@@ -0,0 +1,13 @@
+-- Split the text of documents extracted before per-page storage into page rows,
+-- so their pages can be listed and corrected like those of new documents.
+-- Extraction joined pages as "--- Page N ---\n<text>", separated by blank lines.
+INSERT INTO document_pages (document_id, page_number, content_text)
+SELECT d.id,
+       COALESCE(substring(chunk.text FROM '^--- Page ([0-9]+) ---')::int, chunk.ordinal::int),
+       regexp_replace(chunk.text, E'^--- Page [0-9]+ ---\n', '')
+FROM documents d
+CROSS JOIN LATERAL regexp_split_to_table(d.content_text, E'\n\n(?=--- Page [0-9]+ ---\n)')
+    WITH ORDINALITY AS chunk(text, ordinal)
+WHERE d.content_text IS NOT NULL
+  AND NOT EXISTS (SELECT 1 FROM document_pages p WHERE p.document_id = d.id)
+ON CONFLICT (document_id, page_number) DO NOTHING;
@@ -0,0 +1,5 @@
+-- When problem linking looked at a problem, set even if no topic fit,
+-- so problems already judged are not sent to the model again
+ALTER TABLE problems ADD COLUMN topic_checked_at TIMESTAMPTZ;
+
+CREATE INDEX idx_problems_unchecked ON problems(document_id) WHERE topic_checked_at IS NULL;
@@ -0,0 +1,3 @@
+-- Bumped whenever messages leave a chat's history (cleared, or replaced by an
+-- edit or regeneration), so work started on the old history can tell it is stale
+ALTER TABLE chats ADD COLUMN history_generation INTEGER NOT NULL DEFAULT 0;
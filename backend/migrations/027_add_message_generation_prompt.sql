@@ -0,0 +1,3 @@
+-- Messages sent to the model for an assistant message (system prompt, history and
+-- the student's turn, without images), so rated answers can be reproduced exactly
+ALTER TABLE messages ADD COLUMN generation_prompt JSONB;
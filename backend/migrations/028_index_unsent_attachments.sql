@@ -0,0 +1,2 @@
+-- Attachments uploaded but never sent are cleaned up by age
+CREATE INDEX idx_message_attachments_unsent ON message_attachments(created_at) WHERE message_id IS NULL;
@@ -0,0 +1,9 @@
+-- Storage paths issued by createUploadUrl; completeUpload only accepts these,
+-- so a client can't register an object that belongs to something else
+CREATE TABLE upload_tickets (
+    file_path TEXT PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES study_sessions(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_upload_tickets_session ON upload_tickets(session_id);
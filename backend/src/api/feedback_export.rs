@@ -0,0 +1,152 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+};
+use chrono::{DateTime, Utc};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use uuid::Uuid;
+
+use crate::{
+    graphql::AppState,
+    storage::{message_feedback, pagination::Cursor, profiles},
+};
+
+use super::upload::{extract_profile_id, ErrorResponse};
+
+/// Rows fetched per query while streaming the export
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    /// Only ratings given or changed at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// "up" or "down"
+    pub rating: Option<String>,
+}
+
+/// One line of the export
+#[derive(Debug, Serialize)]
+struct ExportedExchange {
+    message_id: Uuid,
+    chat_id: Uuid,
+    session_id: Uuid,
+    topic: Option<String>,
+    rating: String,
+    reason: Option<String>,
+    rated_at: DateTime<Utc>,
+    /// Student message answered (absent for welcome messages)
+    prompt: Option<String>,
+    response: String,
+    template_version: Option<String>,
+    model: Option<String>,
+    /// Documents, history and other sections that were in the prompt
+    context: Option<JsonValue>,
+    /// Messages sent to the model (`role` and `content`), system prompt first
+    rendered_prompt: Option<JsonValue>,
+    created_at: DateTime<Utc>,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.into(),
+            code: None,
+        }),
+    )
+}
+
+/// GET /api/admin/feedback.jsonl
+///
+/// Exports rated assistant messages as JSON lines for offline evaluation, streamed
+/// in batches.
+/// Optional query parameters: `since` (RFC 3339) and `rating` (`up`/`down`).
+///
+/// Requires a Bearer token of an account listed in `ADMIN_EMAILS`.
+pub async fn export_feedback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let profile_id = extract_profile_id(&headers, &state.config)
+        .map_err(|e| error(StatusCode::UNAUTHORIZED, e))?;
+
+    let profile = profiles::get_profile_by_id(&state.db_pool, profile_id)
+        .await
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.message))?;
+    if !profile.is_some_and(|p| state.config.is_admin(&p.email)) {
+        return Err(error(StatusCode::FORBIDDEN, "Admin access required"));
+    }
+
+    if let Some(rating) = params.rating.as_deref() {
+        if rating != "up" && rating != "down" {
+            return Err(error(StatusCode::BAD_REQUEST, "rating must be 'up' or 'down'"));
+        }
+    }
+
+    // Stream the rows in batches rather than holding the whole export in memory
+    // (a rating changed while exporting may appear again at its new position)
+    let pool = state.db_pool.clone();
+    let rating = params.rating;
+    let since = params.since;
+    let lines = stream::try_unfold((None, 0usize, false), move |(after, exported, done)| {
+        let pool = pool.clone();
+        let rating = rating.clone();
+        async move {
+            if done {
+                tracing::info!("Profile {} exported {} rated messages", profile_id, exported);
+                return Ok(None);
+            }
+
+            let rows = message_feedback::get_feedback_export(&pool, since, rating.as_deref(), after, EXPORT_BATCH_SIZE)
+                .await
+                .map_err(|e| std::io::Error::other(e.message))?;
+
+            let done = (rows.len() as i64) < EXPORT_BATCH_SIZE;
+            let after = rows.last().map(|row| Cursor {
+                created_at: row.rated_at,
+                id: row.message_id,
+            });
+            let exported = exported + rows.len();
+
+            let mut chunk = String::new();
+            for row in rows {
+                let line = serde_json::to_string(&ExportedExchange {
+                    message_id: row.message_id,
+                    chat_id: row.chat_id,
+                    session_id: row.session_id,
+                    topic: row.topic_title,
+                    rating: row.rating,
+                    reason: row.reason,
+                    rated_at: row.rated_at,
+                    prompt: row.prompt,
+                    response: row.response,
+                    template_version: row.template_version,
+                    model: row.model,
+                    context: row.generation_context,
+                    rendered_prompt: row.generation_prompt,
+                    created_at: row.created_at,
+                })
+                .map_err(std::io::Error::other)?;
+                chunk.push_str(&line);
+                chunk.push('\n');
+            }
+
+            Ok::<_, std::io::Error>(Some((chunk, (after, exported, done))))
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"feedback.jsonl\""),
+        ],
+        Body::from_stream(lines),
+    ))
+}
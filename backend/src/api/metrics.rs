@@ -0,0 +1,13 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::graphql::AppState;
+
+/// GET /metrics
+///
+/// Exposes application metrics in the Prometheus text format
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(&state.db_pool),
+    )
+}
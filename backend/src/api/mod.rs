@@ -1,4 +1,7 @@
+pub mod feedback_export;
+pub mod metrics;
 pub mod upload;
 
+pub use feedback_export::export_feedback;
+pub use metrics::metrics;
 pub use upload::upload_file;
-
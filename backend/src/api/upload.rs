@@ -1,9 +1,12 @@
 use axum::{
-    extract::{Multipart, State},
+    extract::{multipart::Field, Multipart, State},
     http::{HeaderMap, StatusCode},
     response::Json,
 };
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 use crate::{
@@ -11,13 +14,15 @@ use crate::{
     graphql::AppState,
     services::{
         auth::jwt::verify_jwt,
-        documents::{ingestion, storage_client},
+        documents::{
+            pdf_tools, quota, scanner,
+            storage_client::{self, StorageClient},
+            uploads::{self, ProcessingJob},
+        },
     },
-    storage::documents,
+    storage::documents::{self, NewDocument},
 };
 
-const MAX_FILE_SIZE: usize = 50 * 1024 * 1024; // 50MB
-
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UploadResponse {
@@ -31,6 +36,9 @@ pub struct UploadResponse {
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
+    /// Machine-readable error code (e.g. `QUOTA_EXCEEDED`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
 }
 
 /// POST /api/upload
@@ -40,6 +48,9 @@ pub struct ErrorResponse {
 /// - `sessionId`: The study session ID
 /// 
 /// Requires Authorization header with Bearer token
+///
+/// The file is spooled to a temp file chunk by chunk (never held in memory),
+/// streamed from there to storage, and reused for text extraction.
 pub async fn upload_file(
     State(state): State<AppState>,
     headers: HeaderMap,
@@ -49,12 +60,12 @@ pub async fn upload_file(
     let profile_id = extract_profile_id(&headers, &state.config).map_err(|e| {
         (
             StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse { error: e }),
+            Json(ErrorResponse { error: e, code: None }),
         )
     })?;
 
     // 2. Parse multipart form
-    let mut file_data: Option<Vec<u8>> = None;
+    let mut file_data: Option<SpooledFile> = None;
     let mut file_name: Option<String> = None;
     let mut session_id: Option<Uuid> = None;
 
@@ -63,6 +74,7 @@ pub async fn upload_file(
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: format!("Failed to read multipart: {}", e),
+                code: None,
             }),
         )
     })? {
@@ -71,15 +83,7 @@ pub async fn upload_file(
         match name.as_str() {
             "file" => {
                 file_name = field.file_name().map(|s| s.to_string());
-                let data = field.bytes().await.map_err(|e| {
-                    (
-                        StatusCode::BAD_REQUEST,
-                        Json(ErrorResponse {
-                            error: format!("Failed to read file: {}", e),
-                        }),
-                    )
-                })?;
-                file_data = Some(data.to_vec());
+                file_data = Some(spool_to_temp_file(field, &state.config).await?);
             }
             "sessionId" => {
                 let text = field.text().await.map_err(|e| {
@@ -87,6 +91,7 @@ pub async fn upload_file(
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
                             error: format!("Failed to read sessionId: {}", e),
+                            code: None,
                         }),
                     )
                 })?;
@@ -95,6 +100,7 @@ pub async fn upload_file(
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
                             error: "Invalid sessionId format".to_string(),
+                            code: None,
                         }),
                     )
                 })?);
@@ -104,11 +110,16 @@ pub async fn upload_file(
     }
 
     // 3. Validate required fields
-    let file_data = file_data.ok_or_else(|| {
+    let SpooledFile {
+        temp_file,
+        size: file_size,
+        content_hash,
+    } = file_data.ok_or_else(|| {
         (
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "Missing file field".to_string(),
+                code: None,
             }),
         )
     })?;
@@ -118,6 +129,7 @@ pub async fn upload_file(
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "Missing file name".to_string(),
+                code: None,
             }),
         )
     })?;
@@ -127,25 +139,63 @@ pub async fn upload_file(
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "Missing sessionId field".to_string(),
+                code: None,
             }),
         )
     })?;
 
-    // 4. Validate file
-    if file_data.len() > MAX_FILE_SIZE {
+    // 4. Validate and scan the file (size is enforced while spooling)
+    if !uploads::is_pdf_file_name(&file_name) {
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: "File size exceeds 50MB limit".to_string(),
+                error: "Only PDF files are supported".to_string(),
+                code: None,
             }),
         ));
     }
 
-    if !file_name.to_lowercase().ends_with(".pdf") {
+    if let Err(e) = pdf_tools::validate_pdf(temp_file.path(), &state.config).await {
+        if let pdf_tools::PdfRejection::Tool(reason) = &e {
+            tracing::error!("PDF validation failed: {}", reason);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Failed to inspect PDF".to_string(),
+                    code: None,
+                }),
+            ));
+        }
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: "Only PDF files are supported".to_string(),
+                error: e.to_string(),
+                code: Some(pdf_tools::INVALID_PDF),
+            }),
+        ));
+    }
+
+    // Scan before storing, so no unscanned file ever sits in storage
+    let verdict = scanner::from_config(&state.config)
+        .scan(temp_file.path())
+        .await
+        .map_err(|e| {
+            tracing::error!("Malware scan failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Failed to scan file".to_string(),
+                    code: None,
+                }),
+            )
+        })?;
+    if let Some(reason) = scanner::rejection_message(&verdict) {
+        tracing::warn!("Scanner rejected upload {}: {:?}", file_name, verdict);
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: reason,
+                code: None,
             }),
         ));
     }
@@ -162,6 +212,7 @@ pub async fn upload_file(
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
                 error: "Database error".to_string(),
+                code: None,
             }),
         )
     })?
@@ -170,6 +221,7 @@ pub async fn upload_file(
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
                 error: "Session not found".to_string(),
+                code: None,
             }),
         )
     })?;
@@ -179,79 +231,147 @@ pub async fn upload_file(
             StatusCode::FORBIDDEN,
             Json(ErrorResponse {
                 error: "You don't have access to this session".to_string(),
+                code: None,
             }),
         ));
     }
 
-    // 6. Upload file to Supabase Storage
-    let timestamp = chrono::Utc::now().timestamp();
-    let safe_name = file_name.replace(|c: char| !c.is_alphanumeric() && c != '.' && c != '-', "_");
-    let storage_path = format!("{}/{}-{}", session_id, timestamp, safe_name);
-
-    storage_client::upload_file(
-        &state.config.supabase_url,
-        &state.config.supabase_service_key,
-        &storage_path,
-        &file_data,
+    // 6. Reject files already uploaded to this session
+    let duplicate = documents::find_session_document_by_hash(
+        &state.db_pool,
+        profile_id,
+        session_id,
+        &content_hash,
+        None,
     )
     .await
     .map_err(|e| {
-        tracing::error!("Storage upload failed: {:?}", e);
+        tracing::error!("Duplicate check failed: {:?}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "Failed to upload file to storage".to_string(),
+                error: "Database error".to_string(),
+                code: None,
             }),
         )
     })?;
 
-    tracing::info!("File uploaded to storage: {}", storage_path);
+    if let Some(existing) = duplicate {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: format!("This file was already uploaded as \"{}\"", existing.file_name),
+                code: Some(uploads::DUPLICATE_DOCUMENT),
+            }),
+        ));
+    }
+
+    // 7. Reject uploads over a quota early, before anything is sent to storage
+    let usage = quota::get_usage(&state.db_pool, &state.config, profile_id, Some(session_id))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to load storage usage: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Database error".to_string(),
+                    code: None,
+                }),
+            )
+        })?;
 
-    // 7. Create document record with pending status
-    let doc = documents::create_document(
-        &state.db_pool,
-        session_id,
-        &file_name,
+    usage.check(file_size as i64).map_err(|e| {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                error: e.message(),
+                code: Some(quota::QUOTA_EXCEEDED),
+            }),
+        )
+    })?;
+
+    // 8. Upload file to Supabase Storage
+    let storage_path = uploads::storage_path_for(session_id, &file_name);
+
+    storage_client::upload_file(
+        &state.config.supabase_url,
+        &state.config.supabase_service_key,
         &storage_path,
+        temp_file.path(),
     )
     .await
     .map_err(|e| {
-        tracing::error!("Failed to create document record: {:?}", e);
+        tracing::error!("Storage upload failed: {:?}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "Failed to create document record".to_string(),
+                error: "Failed to upload file to storage".to_string(),
+                code: None,
             }),
         )
     })?;
 
-    // 8. Spawn background task to process the document
-    let pool = state.db_pool.clone();
-    let config = state.config.clone();
-    let doc_id = doc.id;
-    let path = storage_path.clone();
-
-    tokio::spawn(async move {
-        tracing::info!("Starting document processing for: {}", doc_id);
-        
-        match ingestion::process_document(&pool, &config, doc_id, &path).await {
-            Ok(_) => {
-                tracing::info!("Document processing completed: {}", doc_id);
-            }
-            Err(e) => {
-                tracing::error!("Document processing failed for {}: {:?}", doc_id, e);
-                // Update status to failed
-                let _ = sqlx::query(
-                    "UPDATE documents SET processing_status = 'FAILED' WHERE id = $1"
-                )
-                .bind(doc_id)
-                .execute(&pool)
-                .await;
+    tracing::info!("File uploaded to storage: {} ({} bytes)", storage_path, file_size);
+
+    // 9. Create document record with pending status, checking the quotas again
+    //    atomically so concurrent uploads can't exceed them together
+    let new_document = NewDocument {
+        session_id,
+        file_name: &file_name,
+        file_path: &storage_path,
+        file_size: file_size as i64,
+        content_hash: Some(&content_hash),
+    };
+    let created = quota::create_document(&state.db_pool, &state.config, profile_id, &new_document).await;
+    let outcome = match created {
+        Ok(Ok(doc)) => Ok(doc),
+        Ok(Err(e)) => Err((StatusCode::PAYLOAD_TOO_LARGE, e.message(), quota::QUOTA_EXCEEDED)),
+        // An identical upload to the same session finished after the check in step 6
+        Err(e) if e.message == documents::DUPLICATE_CONTENT => {
+            Err((StatusCode::CONFLICT, e.message, uploads::DUPLICATE_DOCUMENT))
+        }
+        Err(e) => {
+            tracing::error!("Failed to create document record: {:?}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Failed to create document record".to_string(),
+                    code: None,
+                }),
+            ));
+        }
+    };
+    let doc = match outcome {
+        Ok(doc) => doc,
+        Err((status, error, code)) => {
+            // Don't keep objects we refuse to process
+            let storage = StorageClient::new(&state.config);
+            if let Err(e) = storage.delete(&format!("documents/{}", storage_path)).await {
+                tracing::warn!("Failed to delete rejected upload: {:?}", e);
             }
+            return Err((
+                status,
+                Json(ErrorResponse {
+                    error,
+                    code: Some(code),
+                }),
+            ));
         }
-    });
+    };
 
-    // 9. Return response
+    // 10. Spawn background task to process the document
+    uploads::spawn_processing(
+        state.db_pool.clone(),
+        state.config.clone(),
+        state.progress.clone(),
+        ProcessingJob {
+            local_pdf: Some(temp_file),
+            scanned: true,
+            ..ProcessingJob::new(doc.id, session_id, storage_path.clone())
+        },
+    );
+
+    // 11. Return response
     Ok(Json(UploadResponse {
         id: doc.id.to_string(),
         file_name: doc.file_name,
@@ -261,7 +381,70 @@ pub async fn upload_file(
     }))
 }
 
-fn extract_profile_id(headers: &HeaderMap, config: &Config) -> Result<Uuid, String> {
+/// An uploaded file spooled to disk
+struct SpooledFile {
+    temp_file: NamedTempFile,
+    size: usize,
+    /// Hex-encoded SHA-256 of the contents
+    content_hash: String,
+}
+
+/// Write a multipart field to a temp file, rejecting it as soon as it exceeds
+/// `uploads::max_file_size`
+async fn spool_to_temp_file(
+    mut field: Field<'_>,
+    config: &Config,
+) -> Result<SpooledFile, (StatusCode, Json<ErrorResponse>)> {
+    let io_error = |e: std::io::Error| {
+        tracing::error!("Failed to spool upload to disk: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Failed to buffer upload".to_string(),
+                code: None,
+            }),
+        )
+    };
+
+    let temp_file = NamedTempFile::new().map_err(io_error)?;
+    let mut out = tokio::fs::File::create(temp_file.path()).await.map_err(io_error)?;
+    let mut size = 0usize;
+    let mut hasher = Sha256::new();
+    let max_size = uploads::max_file_size(config);
+
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Failed to read file: {}", e),
+                code: None,
+            }),
+        )
+    })? {
+        size += chunk.len();
+        if size > max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ErrorResponse {
+                    error: uploads::file_too_large_message(config),
+                    code: None,
+                }),
+            ));
+        }
+        hasher.update(&chunk);
+        out.write_all(&chunk).await.map_err(io_error)?;
+    }
+
+    out.flush().await.map_err(io_error)?;
+
+    Ok(SpooledFile {
+        temp_file,
+        size,
+        content_hash: hex::encode(hasher.finalize()),
+    })
+}
+
+pub(crate) fn extract_profile_id(headers: &HeaderMap, config: &Config) -> Result<Uuid, String> {
     let auth_header = headers
         .get("authorization")
         .ok_or_else(|| "Missing Authorization header".to_string())?
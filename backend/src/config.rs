@@ -1,4 +1,14 @@
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
+
+/// Storage limits for one account tier, in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct StorageQuota {
+    pub max_file_bytes: i64,
+    pub max_session_bytes: i64,
+    pub max_profile_bytes: i64,
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -8,17 +18,83 @@ pub struct Config {
     pub jwt_secret: String,
     pub openrouter_api_key: String,
     pub server_port: u16,
+    /// Port of the separate listener serving `GET /metrics` (0 disables it)
+    pub metrics_port: u16,
     pub allowed_origins: Vec<String>,
+    /// Emit logs as JSON lines instead of human-readable text
+    pub log_json: bool,
+    /// OTLP/HTTP collector base URL; trace export is disabled when unset
+    pub otlp_endpoint: Option<String>,
+    /// Deployment environment ("development", "production", ...)
+    pub environment: String,
+    pub graphql_max_depth: usize,
+    pub graphql_max_complexity: usize,
+    /// Allow schema introspection (defaults to on only in development)
+    pub graphql_introspection: bool,
+    /// Serve the GraphQL playground on GET /graphql (defaults to on only in development)
+    pub graphql_playground: bool,
+    pub quota_free: StorageQuota,
+    pub quota_pro: StorageQuota,
+    /// Uploads with more pages are rejected
+    pub max_pdf_pages: u32,
+    /// Wall-clock limit for each `pdfinfo`/`pdftoppm` run
+    pub pdf_tool_timeout_secs: u64,
+    /// Address-space limit for each `pdfinfo`/`pdftoppm` run
+    pub pdf_tool_max_memory_mb: u64,
+    /// clamd socket path or `host:port`; malware scanning is skipped when unset
+    pub clamav_address: Option<String>,
+    /// Largest file sent to clamd; must not exceed its `StreamMaxLength` (25MB by
+    /// default), and caps the upload size while scanning is on
+    pub clamav_stream_max_bytes: u64,
+    /// Prompt token budget for models without their own entry
+    pub default_prompt_token_budget: usize,
+    /// Prompt token budget per model, from `PROMPT_TOKEN_BUDGETS` (`model=tokens,...`)
+    pub prompt_token_budgets: HashMap<String, usize>,
+    /// Directory with prompt template overrides (`<name>[.<locale>].tmpl`)
+    pub prompt_templates_dir: Option<String>,
+    /// How often template overrides are reloaded (0 loads them once at startup)
+    pub prompt_templates_reload_secs: u64,
+    /// Emails (lowercase) of accounts allowed to use admin endpoints
+    pub admin_emails: Vec<String>,
+    /// Retries per model on rate limits, server errors and timeouts
+    pub ai_max_retries: u32,
+    /// Limit for all attempts of one AI call, across retries and fallback models
+    /// (capped below the window after which a pending reply counts as failed)
+    pub ai_call_deadline_secs: u64,
+    /// Models tried in order when a task's model keeps failing, by `AiTask::as_str`,
+    /// from `AI_FALLBACK_MODELS` (`task=model|model,...`)
+    pub ai_fallback_models: HashMap<String, Vec<String>>,
+    /// HTTP client for AI requests, built once at startup and shared by every
+    /// `OpenRouterClient` (with the `AI_REQUEST_TIMEOUT_SECS` limit for a whole
+    /// request and `AI_CONNECT_TIMEOUT_SECS` for connecting)
+    pub ai_http_client: reqwest::Client,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
         let allowed_origins = env::var("ALLOWED_ORIGINS")
             .unwrap_or_else(|_| "http://localhost:5173".to_string())
             .split(',')
             .map(|s| s.trim().to_string())
             .collect();
 
+        let environment = env::var("APP_ENV").unwrap_or_else(|_| "development".to_string());
+        let is_development = environment == "development";
+
+        let ai_request_timeout_secs = env::var("AI_REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(120);
+        let ai_connect_timeout_secs = env::var("AI_CONNECT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        let ai_http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(ai_request_timeout_secs))
+            .connect_timeout(Duration::from_secs(ai_connect_timeout_secs))
+            .build()
+            .map_err(|e| format!("Failed to build the AI HTTP client: {}", e))?;
+
         Ok(Self {
             database_url: env::var("DATABASE_URL")?,
             supabase_url: env::var("SUPABASE_URL")?,
@@ -29,8 +105,177 @@ impl Config {
                 .unwrap_or_else(|_| "8080".to_string())
                 .parse()
                 .unwrap_or(8080),
+            metrics_port: env::var("METRICS_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(9090),
             allowed_origins,
+            log_json: env::var("LOG_FORMAT")
+                .map(|v| v.eq_ignore_ascii_case("json"))
+                .unwrap_or(false),
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            graphql_max_depth: env::var("GRAPHQL_MAX_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(12),
+            graphql_max_complexity: env::var("GRAPHQL_MAX_COMPLEXITY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(500),
+            graphql_introspection: env_flag("GRAPHQL_INTROSPECTION").unwrap_or(is_development),
+            graphql_playground: env_flag("GRAPHQL_PLAYGROUND").unwrap_or(is_development),
+            quota_free: quota_from_env("FREE", 25, 150, 500),
+            quota_pro: quota_from_env("PRO", 50, 500, 5000),
+            max_pdf_pages: env::var("MAX_PDF_PAGES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(200),
+            pdf_tool_timeout_secs: env::var("PDF_TOOL_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(120),
+            pdf_tool_max_memory_mb: env::var("PDF_TOOL_MAX_MEMORY_MB")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1024),
+            clamav_address: env::var("CLAMAV_ADDRESS").ok().filter(|v| !v.is_empty()),
+            clamav_stream_max_bytes: env::var("CLAMAV_STREAM_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(25 * 1024 * 1024),
+            default_prompt_token_budget: env::var("DEFAULT_PROMPT_TOKEN_BUDGET")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(120_000),
+            prompt_token_budgets: env::var("PROMPT_TOKEN_BUDGETS")
+                .map(|v| parse_token_budgets(&v))
+                .unwrap_or_default(),
+            prompt_templates_dir: env::var("PROMPT_TEMPLATES_DIR").ok().filter(|v| !v.is_empty()),
+            prompt_templates_reload_secs: env::var("PROMPT_TEMPLATES_RELOAD_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            admin_emails: env::var("ADMIN_EMAILS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|email| email.trim().to_lowercase())
+                        .filter(|email| !email.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ai_max_retries: env::var("AI_MAX_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(2),
+            ai_call_deadline_secs: env::var("AI_CALL_DEADLINE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(480),
+            ai_fallback_models: env::var("AI_FALLBACK_MODELS")
+                .map(|v| parse_fallback_models(&v))
+                .unwrap_or_default(),
+            ai_http_client,
+            environment,
+        })
+    }
+
+    /// Storage quota for a profile tier (unknown tiers get the free quota)
+    pub fn storage_quota(&self, tier: &str) -> StorageQuota {
+        match tier {
+            "pro" => self.quota_pro,
+            _ => self.quota_free,
+        }
+    }
+
+    /// Whether an account may use admin endpoints
+    pub fn is_admin(&self, email: &str) -> bool {
+        self.admin_emails.contains(&email.to_lowercase())
+    }
+
+    /// Token budget for a prompt sent to `model` (leave room for the response)
+    pub fn prompt_token_budget(&self, model: &str) -> usize {
+        self.prompt_token_budgets
+            .get(model)
+            .copied()
+            .unwrap_or(self.default_prompt_token_budget)
+    }
+}
+
+/// Parse `model=tokens` pairs separated by commas, skipping malformed entries
+fn parse_token_budgets(value: &str) -> HashMap<String, usize> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (model, tokens) = entry.split_once('=')?;
+            Some((model.trim().to_string(), tokens.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Parse `task=model|model` entries separated by commas, skipping malformed entries
+fn parse_fallback_models(value: &str) -> HashMap<String, Vec<String>> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (task, models) = entry.split_once('=')?;
+            let models: Vec<String> = models
+                .split('|')
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty())
+                .collect();
+            (!models.is_empty()).then(|| (task.trim().to_string(), models))
         })
+        .collect()
+}
+
+/// Read `QUOTA_{tier}_{FILE,SESSION,PROFILE}_MB`, falling back to the given defaults
+fn quota_from_env(tier: &str, file_mb: i64, session_mb: i64, profile_mb: i64) -> StorageQuota {
+    let mb = |kind: &str, default: i64| {
+        env::var(format!("QUOTA_{}_{}_MB", tier, kind))
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(default)
+            * 1024
+            * 1024
+    };
+
+    StorageQuota {
+        max_file_bytes: mb("FILE", file_mb),
+        max_session_bytes: mb("SESSION", session_mb),
+        max_profile_bytes: mb("PROFILE", profile_mb),
+    }
+}
+
+/// Parse a boolean env var ("true"/"1" or "false"/"0"); `None` when unset or invalid
+fn env_flag(name: &str) -> Option<bool> {
+    match env::var(name).ok()?.to_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fallback_models_per_task() {
+        let models = parse_fallback_models(" chat = openai/gpt-4o | anthropic/claude ,vision=google/gemini ");
+
+        assert_eq!(models.len(), 2);
+        assert_eq!(models["chat"], vec!["openai/gpt-4o", "anthropic/claude"]);
+        assert_eq!(models["vision"], vec!["google/gemini"]);
+    }
+
+    #[test]
+    fn skips_malformed_fallback_entries() {
+        let models = parse_fallback_models("chat,vision=,summary=| ,plan=a/b||");
+
+        assert_eq!(models.len(), 1);
+        assert_eq!(models["plan"], vec!["a/b"]);
+        assert!(parse_fallback_models("").is_empty());
+    }
+}
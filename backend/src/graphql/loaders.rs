@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::Request;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::storage::chats::{self, ChatRow};
+use crate::storage::document_pages::{self, DocumentPageEditRow, DocumentPageRow};
+use crate::storage::documents::{self, DocumentRow};
+use crate::storage::message_attachments::{self, AttachmentRow};
+use crate::storage::message_citations::{self, CitationRow};
+use crate::storage::message_feedback::{self, FeedbackRow};
+use crate::storage::messages::{self, MessageBranchRow, MessageRow};
+use crate::storage::problems::{self, ProblemRow};
+use crate::storage::topics::{self, TopicRow};
+
+/// Group rows by a key, keeping their query order within each group
+fn group_by<T>(rows: Vec<T>, key: impl Fn(&T) -> Uuid) -> HashMap<Uuid, Vec<T>> {
+    let mut grouped: HashMap<Uuid, Vec<T>> = HashMap::new();
+    for row in rows {
+        grouped.entry(key(&row)).or_default().push(row);
+    }
+    grouped
+}
+
+/// Topics of a session, keyed by session ID
+pub struct SessionTopicsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for SessionTopicsLoader {
+    type Value = Vec<TopicRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = topics::get_topics_by_session_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |t| t.session_id))
+    }
+}
+
+/// Documents of a session, keyed by session ID
+pub struct SessionDocumentsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for SessionDocumentsLoader {
+    type Value = Vec<DocumentRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = documents::get_documents_by_session_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |d| d.session_id))
+    }
+}
+
+/// Pages of a document, keyed by document ID
+pub struct DocumentPagesLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for DocumentPagesLoader {
+    type Value = Vec<DocumentPageRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = document_pages::get_pages_by_document_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |p| p.document_id))
+    }
+}
+
+/// Page edit history of a document, keyed by document ID
+pub struct DocumentPageEditsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for DocumentPageEditsLoader {
+    type Value = Vec<DocumentPageEditRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = document_pages::get_edits_by_document_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |e| e.document_id))
+    }
+}
+
+/// A single topic, keyed by topic ID
+pub struct TopicLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for TopicLoader {
+    type Value = TopicRow;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = topics::get_topics_by_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(rows.into_iter().map(|t| (t.id, t)).collect())
+    }
+}
+
+/// The chat of a topic, keyed by topic ID
+pub struct TopicChatLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for TopicChatLoader {
+    type Value = ChatRow;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = chats::get_chats_by_topic_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|c| c.topic_id.map(|topic_id| (topic_id, c)))
+            .collect())
+    }
+}
+
+/// Problems linked to a topic, keyed by topic ID
+pub struct TopicProblemsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for TopicProblemsLoader {
+    type Value = Vec<ProblemRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = problems::get_problems_by_topic_ids(&self.pool, self.profile_id, keys).await?;
+        // Rows are selected by topic, so every one has a topic ID
+        Ok(group_by(rows, |p| p.topic_id.unwrap_or_default()))
+    }
+}
+
+/// Messages of a chat, keyed by chat ID
+pub struct ChatMessagesLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for ChatMessagesLoader {
+    type Value = Vec<MessageRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = messages::get_messages_by_chat_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |m| m.chat_id))
+    }
+}
+
+/// Citations of a message, keyed by message ID
+pub struct MessageCitationsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for MessageCitationsLoader {
+    type Value = Vec<CitationRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = message_citations::get_citations_by_message_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |c| c.message_id))
+    }
+}
+
+/// Versions of a message (those sharing its parent), keyed by message ID
+pub struct MessageBranchesLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for MessageBranchesLoader {
+    type Value = Vec<MessageBranchRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = messages::get_branches_by_message_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(group_by(rows, |b| b.of_message_id))
+    }
+}
+
+/// Images attached to a message, keyed by message ID
+pub struct MessageAttachmentsLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for MessageAttachmentsLoader {
+    type Value = Vec<AttachmentRow>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = message_attachments::get_attachments_by_message_ids(&self.pool, self.profile_id, keys).await?;
+        // Rows are selected by message, so every one has a message ID
+        Ok(group_by(rows, |a| a.message_id.unwrap_or_default()))
+    }
+}
+
+/// Feedback on a message, keyed by message ID
+pub struct MessageFeedbackLoader {
+    pool: PgPool,
+    profile_id: Uuid,
+}
+
+impl Loader<Uuid> for MessageFeedbackLoader {
+    type Value = FeedbackRow;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rows = message_feedback::get_feedback_by_message_ids(&self.pool, self.profile_id, keys).await?;
+        Ok(rows.into_iter().map(|f| (f.message_id, f)).collect())
+    }
+}
+
+/// Attach per-request loaders scoped to the authenticated profile
+/// (every batch query filters by `profile_id`, so loaders never leak other users' rows)
+pub fn attach(request: Request, pool: &PgPool, profile_id: Uuid) -> Request {
+    let pool = pool.clone();
+
+    request
+        .data(DataLoader::new(SessionTopicsLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(SessionDocumentsLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(DocumentPagesLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(DocumentPageEditsLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(TopicLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(TopicChatLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(TopicProblemsLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(ChatMessagesLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(MessageCitationsLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(MessageBranchesLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(MessageFeedbackLoader { pool: pool.clone(), profile_id }, tokio::spawn))
+        .data(DataLoader::new(MessageAttachmentsLoader { pool, profile_id }, tokio::spawn))
+}
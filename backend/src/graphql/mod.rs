@@ -1,7 +1,10 @@
-mod context;
+pub(crate) mod context;
+pub(crate) mod loaders;
+mod operation_label;
+mod pagination;
 mod schema;
 pub mod resolvers;
 pub mod types;
 
-pub use schema::{create_schema, graphql_handler, graphql_playground, AppState};
+pub use schema::{create_schema, graphql_handler, graphql_playground, graphql_ws_handler, AppState};
 
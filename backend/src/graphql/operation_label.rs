@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextPrepareRequest, NextValidation,
+};
+use async_graphql::parser::types::{ExecutableDocument, Selection};
+use async_graphql::{Request, ServerError, ServerResult, ValidationResult, Variables};
+
+/// Root field of a request's operation, used to label its metrics
+///
+/// Set only once the query has been validated, so the label is always a field
+/// of the schema and clients can't create unbounded time series.
+#[derive(Clone, Default)]
+pub struct OperationLabel(Arc<Mutex<Option<String>>>);
+
+impl OperationLabel {
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().ok().and_then(|label| label.clone())
+    }
+}
+
+/// Extension filling in the request's `OperationLabel`
+pub struct OperationLabels;
+
+impl ExtensionFactory for OperationLabels {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OperationLabelsExtension::default())
+    }
+}
+
+#[derive(Default)]
+struct OperationLabelsExtension {
+    operation_name: Mutex<Option<String>>,
+    root_field: Mutex<Option<String>>,
+}
+
+#[async_trait::async_trait]
+impl Extension for OperationLabelsExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        if let Ok(mut operation_name) = self.operation_name.lock() {
+            operation_name.clone_from(&request.operation_name);
+        }
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        if let (Ok(operation_name), Ok(mut root_field)) = (self.operation_name.lock(), self.root_field.lock()) {
+            *root_field = first_root_field(&document, operation_name.as_deref());
+        }
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+        if let (Some(label), Ok(root_field)) = (ctx.data_opt::<OperationLabel>(), self.root_field.lock()) {
+            if let Ok(mut label) = label.0.lock() {
+                label.clone_from(&root_field);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// First field selected at the root of the operation that will run (an operation
+/// selecting several is labelled by its first one)
+fn first_root_field(document: &ExecutableDocument, operation_name: Option<&str>) -> Option<String> {
+    let (_, operation) = document
+        .operations
+        .iter()
+        .find(|(name, _)| operation_name.is_none() || name.map(|n| n.as_str()) == operation_name)?;
+    operation.node.selection_set.node.items.iter().find_map(|selection| match &selection.node {
+        Selection::Field(field) => Some(field.node.name.node.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::parser::parse_query;
+
+    #[test]
+    fn labels_by_the_first_root_field() {
+        let document = parse_query("mutation Send { sendMessage(chatId: \"1\", content: \"hi\") { id } me { id } }").unwrap();
+
+        assert_eq!(first_root_field(&document, None).as_deref(), Some("sendMessage"));
+    }
+
+    #[test]
+    fn labels_the_operation_that_runs() {
+        let document = parse_query("query A { sessions { id } } query B { me { id } }").unwrap();
+
+        assert_eq!(first_root_field(&document, Some("B")).as_deref(), Some("me"));
+        assert_eq!(first_root_field(&document, Some("C")), None);
+    }
+
+    #[test]
+    fn ignores_aliases() {
+        let document = parse_query("{ mine: sessions { id } }").unwrap();
+
+        assert_eq!(first_root_field(&document, None).as_deref(), Some("sessions"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::future::Future;
+
+use async_graphql::connection::{self, Connection, CursorType, Edge};
+use async_graphql::{OutputType, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine as _};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::storage::pagination::{Cursor, Keyed, Page, PageRequest};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Opaque Relay cursor wrapping a `created_at` + `id` keyset position
+pub struct PageCursor(pub Cursor);
+
+impl CursorType for PageCursor {
+    type Error = String;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        let raw = BASE64
+            .decode(s)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| "Invalid cursor".to_string())?;
+
+        let (created_at, id) = raw.split_once('|').ok_or_else(|| "Invalid cursor".to_string())?;
+
+        Ok(PageCursor(Cursor {
+            created_at: DateTime::parse_from_rfc3339(created_at)
+                .map_err(|_| "Invalid cursor".to_string())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| "Invalid cursor".to_string())?,
+        }))
+    }
+
+    fn encode_cursor(&self) -> String {
+        BASE64.encode(format!("{}|{}", self.0.created_at.to_rfc3339(), self.0.id))
+    }
+}
+
+/// Resolve a Relay connection (`first`/`after`/`last`/`before`) with a keyset-paginated fetch
+pub async fn paginate<Row, Node, F, Fut>(
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+    fetch: F,
+) -> Result<Connection<PageCursor, Node>>
+where
+    Row: Keyed + Into<Node>,
+    Node: OutputType,
+    F: FnOnce(PageRequest) -> Fut,
+    Fut: Future<Output = Result<Page<Row>>>,
+{
+    connection::query(after, before, first, last, |after, before, first, last| async move {
+        let from_end = first.is_none() && last.is_some();
+        let limit = first.or(last).unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+        let page = fetch(PageRequest {
+            after: after.map(|c: PageCursor| c.0),
+            before: before.map(|c: PageCursor| c.0),
+            limit: limit as i64,
+            from_end,
+        })
+        .await?;
+
+        let mut connection = Connection::new(page.has_previous, page.has_next);
+        connection.edges.extend(
+            page.rows
+                .into_iter()
+                .map(|row| Edge::new(PageCursor(row.cursor()), row.into())),
+        );
+
+        Ok::<_, async_graphql::Error>(connection)
+    })
+    .await
+}
@@ -1,15 +1,46 @@
-use async_graphql::{Context, Result, ID};
+use async_graphql::connection::Connection;
+use futures::Stream;
+use tokio::sync::broadcast::error::RecvError;
+use async_graphql::{Context, Result, SimpleObject, ID};
 use sqlx::PgPool;
-use tempfile::NamedTempFile;
-use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 use crate::config::Config;
 use crate::graphql::context::GraphQLContext;
-use crate::graphql::types::Document;
-use crate::services::documents::{ingestion, storage_client::{self, StorageClient}};
-use crate::storage::{documents, sessions};
-use crate::storage::documents::ProcessingStatus;
+use crate::graphql::pagination::{paginate, PageCursor};
+use crate::graphql::types::{Document, DocumentProgress};
+use crate::services::documents::progress::ProgressBus;
+use crate::services::documents::{quota, storage_client::{self, StorageClient}, uploads};
+use crate::services::documents::uploads::ProcessingJob;
+use crate::storage::{document_pages, documents, sessions, upload_tickets};
+use crate::storage::documents::NewDocument;
+
+/// A signed destination for uploading a file directly to storage
+#[derive(SimpleObject)]
+#[graphql(rename_fields = "camelCase")]
+pub struct UploadTicket {
+    /// Storage path to pass back to `completeUpload`
+    pub file_path: String,
+    /// URL accepting a single PUT with the file body
+    pub upload_url: String,
+    /// TUS endpoint for resumable uploads
+    pub resumable_url: String,
+    /// Token to send as `x-signature` to the resumable endpoint
+    pub token: String,
+}
+
+/// Storage used by the authenticated profile against its tier's quota
+#[derive(SimpleObject)]
+#[graphql(rename_fields = "camelCase")]
+pub struct StorageUsage {
+    pub tier: String,
+    pub used_bytes: i64,
+    pub limit_bytes: i64,
+    /// Bytes used by the requested session (0 when no session was given)
+    pub session_used_bytes: i64,
+    pub session_limit_bytes: i64,
+    pub max_file_bytes: i64,
+}
 
 /// Get all documents for a session
 pub async fn get_documents(ctx: &Context<'_>, session_id: ID) -> Result<Vec<Document>> {
@@ -29,14 +60,84 @@ pub async fn get_documents(ctx: &Context<'_>, session_id: ID) -> Result<Vec<Docu
     Ok(docs.into_iter().map(Into::into).collect())
 }
 
+/// Get a page of documents for a session (newest first)
+pub async fn get_documents_connection(
+    ctx: &Context<'_>,
+    session_id: ID,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+) -> Result<Connection<PageCursor, Document>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let session_uuid = Uuid::parse_str(&session_id).map_err(|_| "Invalid session ID")?;
+
+    // Verify session exists and belongs to user
+    let session = sessions::get_session_by_id(pool, profile_id, session_uuid).await?;
+    if session.is_none() {
+        return Err("Session not found".into());
+    }
+
+    paginate(after, before, first, last, |page| async move {
+        documents::get_session_documents_page(pool, profile_id, session_uuid, &page).await
+    })
+    .await
+}
+
 /// Add a document to a session
-/// file_path should be the path in Supabase Storage (e.g., "documents/profile_id/file.pdf")
+///
+/// Deprecated and due for removal: older clients passed any path they had uploaded
+/// to, which could name objects belonging to other documents or messages, so only
+/// paths issued by `createUploadUrl` are accepted now, exactly like `completeUpload`.
 pub async fn add_document(
     ctx: &Context<'_>,
     session_id: ID,
     file_path: String,
     file_name: String,
 ) -> Result<Document> {
+    let session_uuid = Uuid::parse_str(&session_id).map_err(|_| "Invalid session ID")?;
+    if !uploads::is_upload_path(&file_path, session_uuid) {
+        return Err("addDocument only accepts files uploaded with createUploadUrl; use completeUpload".into());
+    }
+
+    complete_upload(ctx, session_id, file_path, file_name).await
+}
+
+/// Get storage usage for the authenticated profile (optionally for one session)
+pub async fn get_storage_usage(ctx: &Context<'_>, session_id: Option<ID>) -> Result<StorageUsage> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let session_uuid = session_id
+        .map(|id| Uuid::parse_str(&id).map_err(|_| "Invalid session ID"))
+        .transpose()?;
+
+    let usage = quota::get_usage(pool, config, profile_id, session_uuid).await?;
+
+    Ok(StorageUsage {
+        tier: usage.tier,
+        used_bytes: usage.profile_bytes,
+        limit_bytes: usage.quota.max_profile_bytes,
+        session_used_bytes: usage.session_bytes,
+        session_limit_bytes: usage.quota.max_session_bytes,
+        max_file_bytes: usage.quota.max_file_bytes.min(uploads::max_file_size(config) as i64),
+    })
+}
+
+/// Issue a signed URL so the client can upload a PDF directly to storage
+/// `file_size` is checked against the storage quotas before a URL is issued (an early
+/// rejection only: `completeUpload` checks the stored size again when recording it)
+pub async fn create_upload_url(
+    ctx: &Context<'_>,
+    session_id: ID,
+    file_name: String,
+    file_size: i64,
+) -> Result<UploadTicket> {
     let gql_ctx = ctx.data::<GraphQLContext>()?;
     let profile_id = gql_ctx.require_auth()?;
     let pool = ctx.data::<PgPool>()?;
@@ -50,97 +151,191 @@ pub async fn add_document(
         return Err("Session not found".into());
     }
 
-    tracing::info!("Processing document: {} for session {}", file_name, session_uuid);
+    if !uploads::is_pdf_file_name(&file_name) {
+        return Err("Only PDF files are supported".into());
+    }
+
+    if file_size <= 0 {
+        return Err("Invalid file size".into());
+    }
+    if file_size as usize > uploads::max_file_size(config) {
+        return Err(uploads::file_too_large_message(config).into());
+    }
+    quota::check_upload(pool, config, profile_id, session_uuid, file_size).await?;
+
+    let file_path = uploads::storage_path_for(session_uuid, &file_name);
 
-    // Create document record with pending status
-    let document = documents::create_document(
-        pool,
-        session_uuid,
-        &file_name,
+    let signed = storage_client::create_signed_upload_url(
+        &config.supabase_url,
+        &config.supabase_service_key,
         &file_path,
     )
-    .await?;
-
-    let document_id = document.id;
-    let pool_clone = pool.clone();
-    let config_clone = config.clone();
-    let file_path_clone = file_path.clone();
-
-    // Spawn background task for processing
-    tokio::spawn(async move {
-        if let Err(e) = process_document_background(
-            &pool_clone,
-            &config_clone,
-            document_id,
-            &file_path_clone,
-        )
-        .await
-        {
-            tracing::error!("Background document processing failed: {:?}", e);
-            // Update status to failed
-            let _ = documents::update_document_status(&pool_clone, document_id, ProcessingStatus::Failed).await;
-        }
-    });
-
-    Ok(document.into())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create signed upload URL: {}", e);
+        async_graphql::Error::new("Failed to create upload URL")
+    })?;
+    upload_tickets::create_upload_ticket(pool, session_uuid, &file_path).await?;
+
+    Ok(UploadTicket {
+        file_path,
+        upload_url: signed.url,
+        resumable_url: storage_client::resumable_upload_endpoint(&config.supabase_url),
+        token: signed.token,
+    })
 }
 
-/// Background task to process a document
-async fn process_document_background(
-    pool: &PgPool,
-    config: &Config,
-    document_id: Uuid,
-    file_path: &str,
-) -> Result<(), async_graphql::Error> {
-    // Update status to processing
-    documents::update_document_status(pool, document_id, ProcessingStatus::Processing).await?;
+/// Verify a direct upload landed in storage, then record it and start processing
+pub async fn complete_upload(
+    ctx: &Context<'_>,
+    session_id: ID,
+    file_path: String,
+    file_name: String,
+) -> Result<Document> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let session_uuid = Uuid::parse_str(&session_id).map_err(|_| "Invalid session ID")?;
 
-    // Download file from Supabase Storage
-    let storage = StorageClient::new(config);
-    let file_bytes = storage.download(file_path).await?;
+    // Verify session exists and belongs to user
+    let session = sessions::get_session_by_id(pool, profile_id, session_uuid).await?;
+    if session.is_none() {
+        return Err("Session not found".into());
+    }
 
-    tracing::info!("Downloaded {} bytes from storage", file_bytes.len());
+    if !uploads::is_pdf_file_name(&file_name) {
+        return Err("Only PDF files are supported".into());
+    }
 
-    // Write to temporary file for processing
-    let temp_file = NamedTempFile::new()
-        .map_err(|e| async_graphql::Error::new(format!("Failed to create temp file: {}", e)))?;
+    // Only paths issued for this session may be claimed, and only once
+    if documents::document_exists_for_path(pool, &file_path).await? {
+        return Err("This upload was already completed".into());
+    }
+    if !uploads::is_upload_path(&file_path, session_uuid)
+        || !upload_tickets::upload_ticket_exists(pool, session_uuid, &file_path).await?
+    {
+        return Err("Invalid file path for this session".into());
+    }
 
-    let temp_path = temp_file.path().to_owned();
+    let size = storage_client::get_file_size(
+        &config.supabase_url,
+        &config.supabase_service_key,
+        &file_path,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to verify uploaded file: {}", e);
+        async_graphql::Error::new("Failed to verify uploaded file")
+    })?
+    .ok_or("Uploaded file not found in storage")?;
+
+    // The declared size was checked when the URL was issued; check what actually landed,
+    // recording the document only if it still fits (atomically with concurrent uploads)
+    let size = size as i64;
+    let created = if size as usize > uploads::max_file_size(config) {
+        Err(async_graphql::Error::new(uploads::file_too_large_message(config)))
+    } else {
+        let new_document = NewDocument {
+            session_id: session_uuid,
+            file_name: &file_name,
+            file_path: &file_path,
+            file_size: size,
+            content_hash: None,
+        };
+        quota::create_document(pool, config, profile_id, &new_document)
+            .await?
+            .map_err(Into::into)
+    };
+
+    // The path is now either recorded or about to be deleted
+    upload_tickets::delete_upload_ticket(pool, &file_path).await?;
+
+    let document = match created {
+        Ok(document) => document,
+        Err(error) => {
+            // Don't keep objects we refuse to process
+            let storage = StorageClient::new(config);
+            if let Err(e) = storage.delete(&format!("documents/{}", file_path)).await {
+                tracing::warn!("Failed to delete rejected upload: {:?}", e);
+            }
+            return Err(error);
+        }
+    };
 
-    // Write bytes to temp file
-    let mut file = tokio::fs::File::create(&temp_path)
-        .await
-        .map_err(|e| async_graphql::Error::new(format!("Failed to write temp file: {}", e)))?;
+    tracing::info!("Direct upload completed: {} ({} bytes)", file_path, size);
 
-    file.write_all(&file_bytes)
-        .await
-        .map_err(|e| async_graphql::Error::new(format!("Failed to write temp file: {}", e)))?;
+    let progress = ctx.data::<ProgressBus>()?;
+    uploads::spawn_processing(
+        pool.clone(),
+        config.clone(),
+        progress.clone(),
+        ProcessingJob::new(document.id, session_uuid, file_path),
+    );
 
-    file.flush()
-        .await
-        .map_err(|e| async_graphql::Error::new(format!("Failed to flush temp file: {}", e)))?;
+    Ok(document.into())
+}
 
-    drop(file); // Close the file handle
+/// Run text extraction again from the stored file (e.g. after a failure)
+pub async fn reprocess_document(ctx: &Context<'_>, id: ID) -> Result<Document> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+    let progress = ctx.data::<ProgressBus>()?;
 
-    // Process PDF with vision extraction
-    let processed = ingestion::process_pdf(&temp_path, config).await?;
+    let document_id = Uuid::parse_str(&id).map_err(|_| "Invalid document ID")?;
 
-    tracing::info!(
-        "Extracted {} characters from {} pages",
-        processed.extracted_text.len(),
-        processed.page_count
+    let document = match documents::reset_document_for_reprocessing(pool, profile_id, document_id).await? {
+        Some(document) => document,
+        None => {
+            return match documents::get_document_by_id(pool, profile_id, document_id).await? {
+                Some(_) => Err("Document is already being processed".into()),
+                None => Err("Document not found".into()),
+            };
+        }
+    };
+
+    tracing::info!("Reprocessing document {}", document_id);
+
+    // Extract again rather than copying an identical document's (possibly bad) result
+    uploads::spawn_processing(
+        pool.clone(),
+        config.clone(),
+        progress.clone(),
+        ProcessingJob {
+            reuse_extraction: false,
+            ..ProcessingJob::new(document.id, document.session_id, document.file_path.clone())
+        },
     );
 
-    // Update document with extracted content
-    documents::update_document_content(
-        pool,
-        document_id,
-        &processed.extracted_text,
-        ProcessingStatus::Completed,
-    )
-    .await?;
+    Ok(document.into())
+}
 
-    Ok(())
+/// Correct the extracted text of one page (the previous text is kept in the edit history)
+pub async fn update_document_content(
+    ctx: &Context<'_>,
+    id: ID,
+    page_number: i32,
+    text: String,
+) -> Result<Document> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let document_id = Uuid::parse_str(&id).map_err(|_| "Invalid document ID")?;
+
+    let updated =
+        document_pages::update_page_text(pool, profile_id, document_id, page_number, &text).await?;
+    if !updated {
+        return Err("Page not found".into());
+    }
+
+    documents::get_document_by_id(pool, profile_id, document_id)
+        .await?
+        .map(Into::into)
+        .ok_or_else(|| "Document not found".into())
 }
 
 /// Delete a document
@@ -152,15 +347,24 @@ pub async fn delete_document(ctx: &Context<'_>, id: ID) -> Result<bool> {
 
     let document_id = Uuid::parse_str(&id).map_err(|_| "Invalid document ID")?;
 
-    // Delete from database (returns file_path if successful)
-    let file_path = documents::delete_document(pool, profile_id, document_id).await?;
+    // Delete from database (returns the session and file_path if successful)
+    let deleted = documents::delete_document(pool, profile_id, document_id).await?;
 
-    if let Some(path) = file_path {
-        // Also delete from storage
+    if let Some((session_id, path)) = deleted {
+        // Also delete the file and its page thumbnails from storage
+        // (don't fail the operation if storage delete fails)
         let storage = StorageClient::new(config);
-        if let Err(e) = storage.delete(&path).await {
+        if let Err(e) = storage.delete(&format!("documents/{}", path)).await {
             tracing::warn!("Failed to delete file from storage: {:?}", e);
-            // Don't fail the operation if storage delete fails
+        }
+        if let Err(e) = storage_client::delete_folder(
+            &config.supabase_url,
+            &config.supabase_service_key,
+            &uploads::page_images_prefix(session_id, document_id),
+        )
+        .await
+        {
+            tracing::warn!("Failed to delete page thumbnails from storage: {}", e);
         }
         Ok(true)
     } else {
@@ -203,3 +407,39 @@ pub async fn get_document_url(ctx: &Context<'_>, id: ID) -> Result<String> {
     tracing::info!("Signed URL created successfully");
     Ok(signed_url)
 }
+
+/// Stream status transitions and page progress for the documents of a session
+pub async fn document_progress(
+    ctx: &Context<'_>,
+    session_id: ID,
+) -> Result<impl Stream<Item = DocumentProgress>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let progress = ctx.data::<ProgressBus>()?;
+
+    let session_uuid = Uuid::parse_str(&session_id).map_err(|_| "Invalid session ID")?;
+
+    // Verify session exists and belongs to user
+    let session = sessions::get_session_by_id(pool, profile_id, session_uuid).await?;
+    if session.is_none() {
+        return Err("Session not found".into());
+    }
+
+    let receiver = progress.subscribe();
+
+    Ok(futures::stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if event.session_id == session_uuid => {
+                    return Some((event.into(), receiver));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Progress subscriber lagged, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
@@ -1,12 +1,24 @@
-use async_graphql::{Context, Result, ID};
+use async_graphql::connection::Connection;
+use async_graphql::{Context, Result, SimpleObject, ID};
 use sqlx::PgPool;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::config::Config;
 use crate::graphql::context::GraphQLContext;
-use crate::graphql::types::Message;
-use crate::services::messages::chat;
-use crate::storage::{messages, chats, topics};
+use crate::graphql::pagination::{paginate, PageCursor};
+use crate::graphql::types::{Message, MessageRating};
+use crate::metrics;
+use crate::services::documents::{quota, storage_client};
+use crate::services::messages::chat::{self, ChatScope};
+use crate::services::messages::{attachments, replies};
+use crate::storage::chats::ChatRow;
+use crate::storage::messages::{MessageRow, NewUserMessage, ReplyStart};
+use crate::storage::pagination::Keyed;
+use crate::storage::{message_attachments, message_feedback, messages, chats, topics};
+
+/// Longest accepted feedback reason
+const MAX_FEEDBACK_REASON_CHARS: usize = 2_000;
 
 /// Get all messages for a chat
 pub async fn get_messages(ctx: &Context<'_>, chat_id: ID) -> Result<Vec<Message>> {
@@ -26,11 +38,183 @@ pub async fn get_messages(ctx: &Context<'_>, chat_id: ID) -> Result<Vec<Message>
     Ok(msgs.into_iter().map(Into::into).collect())
 }
 
+/// Get a page of messages for a chat (oldest first)
+pub async fn get_messages_connection(
+    ctx: &Context<'_>,
+    chat_id: ID,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+) -> Result<Connection<PageCursor, Message>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let chat_uuid = Uuid::parse_str(&chat_id).map_err(|_| "Invalid chat ID")?;
+
+    // Verify chat exists and belongs to user
+    let chat_row = chats::get_chat_by_id(pool, profile_id, chat_uuid).await?;
+    if chat_row.is_none() {
+        return Err("Chat not found".into());
+    }
+
+    paginate(after, before, first, last, |page| async move {
+        messages::get_chat_messages_page(pool, profile_id, chat_uuid, &page).await
+    })
+    .await
+}
+
+/// A signed destination for uploading an image to attach to a message
+#[derive(SimpleObject)]
+#[graphql(rename_fields = "camelCase")]
+pub struct AttachmentUpload {
+    /// Pass to `sendMessage` in `attachmentIds` once the upload is done
+    pub attachment_id: ID,
+    /// URL accepting a single PUT with the file body
+    pub upload_url: String,
+    pub token: String,
+}
+
+/// Title of the topic a chat is about, if it is a topic chat
+async fn chat_topic_title(pool: &PgPool, profile_id: Uuid, chat_row: &ChatRow) -> Result<Option<String>> {
+    match chat_row.topic_id {
+        Some(topic_id) => Ok(topics::get_topic_by_id(pool, profile_id, topic_id).await?.map(|t| t.title)),
+        None => Ok(None),
+    }
+}
+
+/// Fold messages that left the history window into the chat summary
+fn spawn_summary_refresh(pool: &PgPool, config: &Config, profile_id: Uuid, chat_id: Uuid) {
+    let task_guard = metrics::BackgroundTaskGuard::new("chat_summary");
+    let (summary_pool, summary_config) = (pool.clone(), config.clone());
+    tokio::spawn(async move {
+        let _task_guard = task_guard;
+        match chat::refresh_summary(&summary_pool, &summary_config, profile_id, chat_id).await {
+            Ok(true) => tracing::info!("Updated summary of chat {}", chat_id),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to update summary of chat {}: {:?}", chat_id, e),
+        }
+    }.instrument(tracing::info_span!("refresh_chat_summary", chat_id = %chat_id)));
+}
+
+/// Generate a pending reply and wait for it
+///
+/// Generation runs in its own task, so the reply is still finished (or marked
+/// failed) if the client disconnects; the summary is refreshed once it is saved.
+async fn generate_reply(
+    pool: &PgPool,
+    config: &Config,
+    scope: ChatScope<'_>,
+    reply: MessageRow,
+    images: Option<Vec<String>>,
+) -> Result<Message> {
+    let task_guard = metrics::BackgroundTaskGuard::new("chat_reply");
+    let (reply_pool, reply_config) = (pool.clone(), config.clone());
+    let (profile_id, session_id, chat_id, reply_id) = (scope.profile_id, scope.session_id, scope.chat_id, reply.id);
+    let (topic_name, language) = (scope.topic_name.map(str::to_string), scope.language.to_string());
+    let task = tokio::spawn(async move {
+        let _task_guard = task_guard;
+        let scope = ChatScope {
+            profile_id,
+            session_id,
+            chat_id,
+            topic_name: topic_name.as_deref(),
+            language: &language,
+        };
+        // Failures are logged and recorded on the reply by `run_reply`
+        let saved = replies::run_reply(&reply_pool, &reply_config, scope, &reply, images).await?;
+        tracing::info!("Reply {} saved for chat {}", reply_id, chat_id);
+        spawn_summary_refresh(&reply_pool, &reply_config, profile_id, chat_id);
+        Ok::<_, async_graphql::Error>(saved)
+    }.instrument(tracing::info_span!("generate_reply", %chat_id, message_id = %reply_id)));
+
+    let saved = task.await.map_err(|e| {
+        tracing::error!("Reply task for {} ended abnormally: {}", reply_id, e);
+        async_graphql::Error::new("Failed to generate a reply")
+    })??;
+    Ok(saved.into())
+}
+
+/// Load a message on its chat's current branch, with its chat
+async fn get_active_message(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_id: &ID,
+) -> Result<(MessageRow, ChatRow)> {
+    let message_uuid = Uuid::parse_str(message_id).map_err(|_| "Invalid message ID")?;
+
+    let message = messages::get_message_by_id(pool, profile_id, message_uuid)
+        .await?
+        .ok_or("Message not found")?;
+    if !message.is_active {
+        return Err("Message is not on the chat's current branch".into());
+    }
+
+    let chat_row = chats::get_chat_by_id(pool, profile_id, message.chat_id)
+        .await?
+        .ok_or("Chat not found")?;
+
+    Ok((message, chat_row))
+}
+
+/// Issue a signed URL so the client can upload an image for its next message
+pub async fn create_attachment_upload(
+    ctx: &Context<'_>,
+    chat_id: ID,
+    file_name: String,
+    file_size: i64,
+) -> Result<AttachmentUpload> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let chat_uuid = Uuid::parse_str(&chat_id).map_err(|_| "Invalid chat ID")?;
+
+    // Verify chat exists and belongs to user
+    let chat_row = chats::get_chat_by_id(pool, profile_id, chat_uuid).await?;
+    let chat_row = chat_row.ok_or("Chat not found")?;
+
+    let content_type = attachments::image_content_type(&file_name)
+        .ok_or("Only PNG, JPEG, WebP and GIF images can be attached")?;
+    if file_size <= 0 {
+        return Err("Invalid file size".into());
+    }
+    if file_size > attachments::MAX_ATTACHMENT_SIZE {
+        return Err("Image size exceeds 10MB limit".into());
+    }
+    quota::check_upload(pool, config, profile_id, chat_row.session_id, file_size).await?;
+
+    let attachment_id = Uuid::new_v4();
+    let file_path = attachments::storage_path_for(chat_row.session_id, chat_uuid, attachment_id, &file_name);
+
+    let signed = storage_client::create_signed_upload_url(
+        &config.supabase_url,
+        &config.supabase_service_key,
+        &file_path,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create signed upload URL: {}", e);
+        async_graphql::Error::new("Failed to create upload URL")
+    })?;
+
+    message_attachments::create_attachment(pool, attachment_id, chat_uuid, &file_path, &file_name, content_type).await?;
+
+    Ok(AttachmentUpload {
+        attachment_id: attachment_id.into(),
+        upload_url: signed.url,
+        token: signed.token,
+    })
+}
+
 /// Send a message and get AI response
 pub async fn send_message(
     ctx: &Context<'_>,
     chat_id: ID,
     content: String,
+    attachment_ids: Vec<ID>,
 ) -> Result<Message> {
     let gql_ctx = ctx.data::<GraphQLContext>()?;
     let profile_id = gql_ctx.require_auth()?;
@@ -43,48 +227,230 @@ pub async fn send_message(
     let chat_row = chats::get_chat_by_id(pool, profile_id, chat_uuid).await?;
     let chat_row = chat_row.ok_or("Chat not found")?;
 
+    // Load the attached images (uploaded beforehand with `createAttachmentUpload`)
+    if attachment_ids.len() > attachments::MAX_ATTACHMENTS_PER_MESSAGE {
+        return Err(format!(
+            "At most {} images can be attached to a message",
+            attachments::MAX_ATTACHMENTS_PER_MESSAGE
+        )
+        .into());
+    }
+    let attachment_uuids = attachment_ids
+        .iter()
+        .map(|id| Uuid::parse_str(id))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Invalid attachment ID")?;
+    let pending = message_attachments::get_pending_attachments(pool, profile_id, chat_uuid, &attachment_uuids).await?;
+    if pending.len() != attachment_uuids.len() {
+        return Err("Attachment not found".into());
+    }
+    let images = attachments::load_images(config, &pending).await?;
+    let sizes: Vec<i64> = images.iter().map(|i| i.size).collect();
+    quota::check_attachments(pool, config, profile_id, chat_row.session_id, &sizes).await?;
+
+    // Store the message and a pending reply before calling the model, so neither is lost if it fails
+    let sent: Vec<(Uuid, i64)> = images.iter().map(|i| (i.attachment_id, i.size)).collect();
+    let reply = messages::begin_reply(
+        pool,
+        profile_id,
+        chat_uuid,
+        ReplyStart::Send(NewUserMessage { content: &content, attachments: &sent, copy_attachments_from: None }),
+    )
+    .await?;
+
+    // Mark chat as started if not already
+    if !chat_row.is_started {
+        chats::mark_chat_started(pool, profile_id, chat_uuid).await?;
+    }
+
     // Get topic info if this is a topic-specific chat
-    let topic_info = if let Some(topic_id) = chat_row.topic_id {
-        topics::get_topic_by_id(pool, profile_id, topic_id).await?.map(|t| (t.title, t.description))
-    } else {
-        None
-    };
+    let topic_title = chat_topic_title(pool, profile_id, &chat_row).await?;
 
-    tracing::info!("Processing message for chat {}", chat_uuid);
+    tracing::info!("Processing message for chat {} with {} images", chat_uuid, images.len());
 
     // Get AI response
-    let ai_response = chat::process_message(
+    let scope = ChatScope {
+        profile_id,
+        session_id: chat_row.session_id,
+        chat_id: reply.chat_id,
+        topic_name: topic_title.as_deref(),
+        language: &gql_ctx.language,
+    };
+    generate_reply(pool, config, scope, reply, Some(images.into_iter().map(|i| i.data_url).collect())).await
+}
+
+/// Replace an assistant reply with a new one
+///
+/// The old reply and everything after it are kept as an alternative branch.
+pub async fn regenerate_message(ctx: &Context<'_>, message_id: ID) -> Result<Message> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let (message, chat_row) = get_active_message(pool, profile_id, &message_id).await?;
+    if message.role != "assistant" {
+        return Err("Only assistant messages can be regenerated".into());
+    }
+
+    let parent = match message.parent_id {
+        Some(parent_id) => messages::get_message_by_id(pool, profile_id, parent_id).await?,
+        None => None,
+    };
+    // A reply to the student gets a new answer; the opening message a new welcome
+    if parent.as_ref().is_some_and(|p| p.role != "user") {
+        return Err("Only replies to the student or welcome messages can be regenerated".into());
+    }
+
+    tracing::info!("Regenerating message {} in chat {}", message.id, message.chat_id);
+
+    let reply = messages::begin_reply(
         pool,
-        config,
         profile_id,
-        chat_row.session_id,
-        chat_uuid,
-        &content,
-        topic_info.as_ref().map(|(title, _)| title.as_str()),
-        &gql_ctx.language,
+        message.chat_id,
+        ReplyStart::Regenerate { after: parent.map(|p| p.cursor()), parent_id: message.parent_id },
     )
     .await?;
 
-    // Save user message
-    messages::create_message(pool, chat_uuid, "user", &content).await?;
+    let topic_title = chat_topic_title(pool, profile_id, &chat_row).await?;
+    let scope = ChatScope {
+        profile_id,
+        session_id: chat_row.session_id,
+        chat_id: reply.chat_id,
+        topic_name: topic_title.as_deref(),
+        language: &gql_ctx.language,
+    };
+    generate_reply(pool, config, scope, reply, None).await
+}
+
+/// Edit a student message and get a new AI response to it
+///
+/// The original message and everything after it are kept as an alternative branch.
+pub async fn edit_message(ctx: &Context<'_>, message_id: ID, content: String) -> Result<Message> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let (message, chat_row) = get_active_message(pool, profile_id, &message_id).await?;
+    if message.role != "user" {
+        return Err("Only the student's messages can be edited".into());
+    }
+
+    tracing::info!("Editing message {} in chat {}", message.id, message.chat_id);
 
-    // Save AI response
-    let assistant_message = messages::create_message(
+    // The new version follows the same message and keeps the original's images
+    let parent = match message.parent_id {
+        Some(parent_id) => messages::get_message_by_id(pool, profile_id, parent_id).await?,
+        None => None,
+    };
+    let reply = messages::begin_reply(
         pool,
-        chat_uuid,
-        "assistant",
-        &ai_response,
+        profile_id,
+        message.chat_id,
+        ReplyStart::Edit {
+            after: parent.map(|p| p.cursor()),
+            parent_id: message.parent_id,
+            message: NewUserMessage { content: &content, attachments: &[], copy_attachments_from: Some(message.id) },
+        },
     )
     .await?;
 
-    // Mark chat as started if not already
-    if !chat_row.is_started {
-        chats::mark_chat_started(pool, profile_id, chat_uuid).await?;
+    // Answer with the history that led up to the original message
+    let topic_title = chat_topic_title(pool, profile_id, &chat_row).await?;
+    let scope = ChatScope {
+        profile_id,
+        session_id: chat_row.session_id,
+        chat_id: reply.chat_id,
+        topic_name: topic_title.as_deref(),
+        language: &gql_ctx.language,
+    };
+    generate_reply(pool, config, scope, reply, None).await
+}
+
+/// Generate a reply again after it failed (or was left pending by a restart)
+pub async fn retry_message(ctx: &Context<'_>, message_id: ID) -> Result<Message> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
+
+    let (message, chat_row) = get_active_message(pool, profile_id, &message_id).await?;
+    if message.role != "assistant" {
+        return Err("Only assistant messages can be retried".into());
+    }
+
+    let reply = messages::restart_reply(pool, profile_id, message.chat_id, message.id)
+        .await?
+        .ok_or("Only failed replies can be retried")?;
+
+    tracing::info!("Retrying message {} in chat {}", message.id, message.chat_id);
+
+    let topic_title = chat_topic_title(pool, profile_id, &chat_row).await?;
+    let scope = ChatScope {
+        profile_id,
+        session_id: chat_row.session_id,
+        chat_id: reply.chat_id,
+        topic_name: topic_title.as_deref(),
+        language: &gql_ctx.language,
+    };
+    generate_reply(pool, config, scope, reply, None).await
+}
+
+/// Switch the chat to another version of a message (and its newest replies),
+/// returning the chat's messages on the new branch
+pub async fn select_branch(ctx: &Context<'_>, message_id: ID) -> Result<Vec<Message>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let message_uuid = Uuid::parse_str(&message_id).map_err(|_| "Invalid message ID")?;
+    let message = messages::get_message_by_id(pool, profile_id, message_uuid)
+        .await?
+        .ok_or("Message not found")?;
+
+    if !message.is_active {
+        let parent = match message.parent_id {
+            Some(parent_id) => messages::get_message_by_id(pool, profile_id, parent_id).await?,
+            None => None,
+        };
+        if parent.as_ref().is_some_and(|p| !p.is_active) {
+            return Err("Select the earlier message's branch first".into());
+        }
+        messages::select_branch(pool, profile_id, &message, parent.map(|p| p.cursor())).await?;
+    }
+
+    let msgs = messages::get_chat_messages(pool, profile_id, message.chat_id).await?;
+    Ok(msgs.into_iter().map(Into::into).collect())
+}
+
+/// Rate an assistant message (rating again replaces the earlier rating)
+pub async fn rate_message(
+    ctx: &Context<'_>,
+    message_id: ID,
+    rating: MessageRating,
+    reason: Option<String>,
+) -> Result<Message> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let message_uuid = Uuid::parse_str(&message_id).map_err(|_| "Invalid message ID")?;
+
+    let reason = reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+    if reason.as_ref().is_some_and(|r| r.chars().count() > MAX_FEEDBACK_REASON_CHARS) {
+        return Err(format!("Reason must be at most {} characters", MAX_FEEDBACK_REASON_CHARS).into());
     }
 
-    tracing::info!("AI response saved for chat {}", chat_uuid);
+    message_feedback::upsert_feedback(pool, profile_id, message_uuid, rating.as_str(), reason.as_deref())
+        .await?
+        .ok_or("Assistant message not found")?;
+
+    let message = messages::get_message_by_id(pool, profile_id, message_uuid)
+        .await?
+        .ok_or("Message not found")?;
 
-    Ok(assistant_message.into())
+    Ok(message.into())
 }
 
 /// Clear chat history for a chat
@@ -92,10 +458,12 @@ pub async fn clear_messages(ctx: &Context<'_>, chat_id: ID) -> Result<bool> {
     let gql_ctx = ctx.data::<GraphQLContext>()?;
     let profile_id = gql_ctx.require_auth()?;
     let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
 
     let chat_uuid = Uuid::parse_str(&chat_id).map_err(|_| "Invalid chat ID")?;
 
-    let deleted = messages::clear_chat_messages(pool, profile_id, chat_uuid).await?;
+    let (deleted, attachment_paths) = messages::clear_chat_messages(pool, profile_id, chat_uuid).await?;
+    attachments::delete_files(config, &attachment_paths).await;
 
     Ok(deleted > 0)
 }
@@ -114,47 +482,26 @@ pub async fn generate_welcome(ctx: &Context<'_>, chat_id: ID) -> Result<Message>
     let chat_row = chats::get_chat_by_id(pool, profile_id, chat_uuid).await?;
     let chat_row = chat_row.ok_or("Chat not found")?;
 
-    // Check if there are already messages (don't regenerate welcome if chat started)
-    let has_messages = messages::chat_has_messages(pool, profile_id, chat_uuid).await?;
-    if has_messages {
-        return Err("Chat already has messages. Welcome message can only be generated for empty chats.".into());
+    // Store a pending welcome (only for empty chats, don't regenerate welcome if chat started)
+    let reply = messages::begin_reply(pool, profile_id, chat_uuid, ReplyStart::Welcome).await?;
+
+    // Mark chat as started
+    if !chat_row.is_started {
+        chats::mark_chat_started(pool, profile_id, chat_uuid).await?;
     }
 
     // Get topic info if this is a topic-specific chat
-    let topic_info = if let Some(topic_id) = chat_row.topic_id {
-        topics::get_topic_by_id(pool, profile_id, topic_id).await?.map(|t| (t.title, t.description))
-    } else {
-        None
-    };
+    let topic_title = chat_topic_title(pool, profile_id, &chat_row).await?;
 
     tracing::info!("Generating welcome message for chat {}", chat_uuid);
 
     // Generate welcome message from AI
-    let welcome_content = chat::generate_welcome_message(
-        pool,
-        config,
+    let scope = ChatScope {
         profile_id,
-        chat_row.session_id,
-        topic_info.as_ref().map(|(title, _)| title.as_str()),
-        &gql_ctx.language,
-    )
-    .await?;
-
-    // Save the welcome message as an assistant message
-    let welcome_message = messages::create_message(
-        pool,
-        chat_uuid,
-        "assistant",
-        &welcome_content,
-    )
-    .await?;
-
-    // Mark chat as started
-    if !chat_row.is_started {
-        chats::mark_chat_started(pool, profile_id, chat_uuid).await?;
-    }
-
-    tracing::info!("Welcome message saved for chat {}", chat_uuid);
-
-    Ok(welcome_message.into())
+        session_id: chat_row.session_id,
+        chat_id: reply.chat_id,
+        topic_name: topic_title.as_deref(),
+        language: &gql_ctx.language,
+    };
+    generate_reply(pool, config, scope, reply, None).await
 }
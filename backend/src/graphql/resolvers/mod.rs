@@ -6,10 +6,13 @@ pub mod planning;
 pub mod topic;
 pub mod chat;
 
-use async_graphql::{Context, Object, Result, ID};
+use async_graphql::connection::Connection;
+use async_graphql::{Context, Object, Result, Subscription, ID};
+use futures::Stream;
 
 use super::context::GraphQLContext;
-use super::types::{Chat, Document, Message, Session, Topic, User};
+use super::pagination::PageCursor;
+use super::types::{Chat, Document, DocumentProgress, Message, MessageRating, Problem, Session, Topic, User};
 
 pub struct QueryRoot;
 
@@ -34,25 +37,61 @@ impl QueryRoot {
     }
 
     /// Get all study sessions for the authenticated user
+    #[graphql(deprecation = "Use `sessionsConnection` for paginated results")]
     async fn sessions(&self, ctx: &Context<'_>) -> Result<Vec<Session>> {
         session::get_sessions(ctx).await
     }
 
+    /// Get study sessions for the authenticated user as a Relay connection (newest first)
+    async fn sessions_connection(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<PageCursor, Session>> {
+        session::get_sessions_connection(ctx, after, before, first, last).await
+    }
+
     /// Get a specific study session by ID
     async fn session(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Session>> {
         session::get_session(ctx, id).await
     }
 
     /// Get all documents for a session
+    #[graphql(deprecation = "Use `documentsConnection` for paginated results")]
     async fn documents(&self, ctx: &Context<'_>, session_id: ID) -> Result<Vec<Document>> {
         document::get_documents(ctx, session_id).await
     }
 
+    /// Get documents for a session as a Relay connection (newest first)
+    async fn documents_connection(
+        &self,
+        ctx: &Context<'_>,
+        session_id: ID,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<PageCursor, Document>> {
+        document::get_documents_connection(ctx, session_id, after, before, first, last).await
+    }
+
     /// Get a signed URL to view a document
     async fn document_url(&self, ctx: &Context<'_>, id: ID) -> Result<String> {
         document::get_document_url(ctx, id).await
     }
 
+    /// Get storage usage and quota for the authenticated user (optionally for one session)
+    async fn storage_usage(
+        &self,
+        ctx: &Context<'_>,
+        session_id: Option<ID>,
+    ) -> Result<document::StorageUsage> {
+        document::get_storage_usage(ctx, session_id).await
+    }
+
     /// Get all topics for a session (only available after starting studying)
     async fn topics(&self, ctx: &Context<'_>, session_id: ID) -> Result<Vec<Topic>> {
         topic::get_topics(ctx, session_id).await
@@ -63,6 +102,11 @@ impl QueryRoot {
         topic::get_topic(ctx, id).await
     }
 
+    /// Get the exam problems linked to a topic
+    async fn problems(&self, ctx: &Context<'_>, topic_id: ID) -> Result<Vec<Problem>> {
+        topic::get_topic_problems(ctx, topic_id).await
+    }
+
     /// Get all chats for a session (only available after starting studying)
     async fn chats(&self, ctx: &Context<'_>, session_id: ID) -> Result<Vec<Chat>> {
         chat::get_chats(ctx, session_id).await
@@ -84,9 +128,23 @@ impl QueryRoot {
     }
 
     /// Get all messages for a chat
+    #[graphql(deprecation = "Use `messagesConnection` for paginated results")]
     async fn messages(&self, ctx: &Context<'_>, chat_id: ID) -> Result<Vec<Message>> {
         message::get_messages(ctx, chat_id).await
     }
+
+    /// Get messages for a chat as a Relay connection (oldest first; use `last` for the most recent)
+    async fn messages_connection(
+        &self,
+        ctx: &Context<'_>,
+        chat_id: ID,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<PageCursor, Message>> {
+        message::get_messages_connection(ctx, chat_id, after, before, first, last).await
+    }
 }
 
 pub struct MutationRoot;
@@ -143,6 +201,8 @@ impl MutationRoot {
     // ===== Document Management =====
 
     /// Add a document to a session (triggers PDF processing with vision AI)
+    /// Only paths issued by `createUploadUrl` are accepted; will be removed
+    #[graphql(deprecation = "Use `createUploadUrl` and `completeUpload`")]
     async fn add_document(
         &self,
         ctx: &Context<'_>,
@@ -153,6 +213,44 @@ impl MutationRoot {
         document::add_document(ctx, session_id, file_path, file_name).await
     }
 
+    /// Get a signed URL to upload a PDF directly to storage
+    async fn create_upload_url(
+        &self,
+        ctx: &Context<'_>,
+        session_id: ID,
+        file_name: String,
+        file_size: i64,
+    ) -> Result<document::UploadTicket> {
+        document::create_upload_url(ctx, session_id, file_name, file_size).await
+    }
+
+    /// Confirm a direct upload (verifies the stored object and starts processing)
+    async fn complete_upload(
+        &self,
+        ctx: &Context<'_>,
+        session_id: ID,
+        file_path: String,
+        file_name: String,
+    ) -> Result<Document> {
+        document::complete_upload(ctx, session_id, file_path, file_name).await
+    }
+
+    /// Re-run text extraction for a document from its stored file
+    async fn reprocess_document(&self, ctx: &Context<'_>, id: ID) -> Result<Document> {
+        document::reprocess_document(ctx, id).await
+    }
+
+    /// Correct the extracted text of one page of a document
+    async fn update_document_content(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        page_number: i32,
+        text: String,
+    ) -> Result<Document> {
+        document::update_document_content(ctx, id, page_number, text).await
+    }
+
     /// Delete a document
     async fn delete_document(&self, ctx: &Context<'_>, id: ID) -> Result<bool> {
         document::delete_document(ctx, id).await
@@ -205,14 +303,68 @@ impl MutationRoot {
 
     // ===== Chat & Messages =====
 
+    /// Issue a signed URL to upload an image to attach to the next message in a chat
+    async fn create_attachment_upload(
+        &self,
+        ctx: &Context<'_>,
+        chat_id: ID,
+        file_name: String,
+        file_size: i64,
+    ) -> Result<message::AttachmentUpload> {
+        message::create_attachment_upload(ctx, chat_id, file_name, file_size).await
+    }
+
     /// Send a message to a chat and get AI response
+    /// (`attachmentIds` come from `createAttachmentUpload`, after the images are uploaded)
+    ///
+    /// Returns the finished reply. It is generated in the background, so it is still
+    /// saved if the client disconnects, and is `PENDING` in `messages` meanwhile; if
+    /// generation fails the mutation errors and the reply is left `FAILED`. The same
+    /// goes for the other mutations that generate a reply.
     async fn send_message(
         &self,
         ctx: &Context<'_>,
         chat_id: ID,
         content: String,
+        #[graphql(default)] attachment_ids: Vec<ID>,
+    ) -> Result<Message> {
+        message::send_message(ctx, chat_id, content, attachment_ids).await
+    }
+
+    /// Replace an assistant reply with a new one (the old one is kept as a branch)
+    async fn regenerate_message(&self, ctx: &Context<'_>, message_id: ID) -> Result<Message> {
+        message::regenerate_message(ctx, message_id).await
+    }
+
+    /// Edit a student message and get a new response (the original is kept as a branch)
+    async fn edit_message(
+        &self,
+        ctx: &Context<'_>,
+        message_id: ID,
+        content: String,
+    ) -> Result<Message> {
+        message::edit_message(ctx, message_id, content).await
+    }
+
+    /// Generate a failed reply again
+    async fn retry_message(&self, ctx: &Context<'_>, message_id: ID) -> Result<Message> {
+        message::retry_message(ctx, message_id).await
+    }
+
+    /// Switch a chat to another version of a message; returns the chat's messages
+    async fn select_branch(&self, ctx: &Context<'_>, message_id: ID) -> Result<Vec<Message>> {
+        message::select_branch(ctx, message_id).await
+    }
+
+    /// Rate an assistant message up or down, optionally saying why
+    async fn rate_message(
+        &self,
+        ctx: &Context<'_>,
+        message_id: ID,
+        rating: MessageRating,
+        reason: Option<String>,
     ) -> Result<Message> {
-        message::send_message(ctx, chat_id, content).await
+        message::rate_message(ctx, message_id, rating, reason).await
     }
 
     /// Clear all messages in a chat
@@ -225,3 +377,17 @@ impl MutationRoot {
         message::generate_welcome(ctx, chat_id).await
     }
 }
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Live processing status and page progress for a session's documents
+    async fn document_progress(
+        &self,
+        ctx: &Context<'_>,
+        session_id: ID,
+    ) -> Result<impl Stream<Item = DocumentProgress>> {
+        document::document_progress(ctx, session_id).await
+    }
+}
@@ -1,13 +1,18 @@
 use async_graphql::{Context, Result, ID};
 use sqlx::PgPool;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::config::Config;
 use crate::graphql::context::GraphQLContext;
 use crate::graphql::types::Session;
+use crate::metrics;
+use crate::services::documents::problems;
 use crate::services::planning;
-use crate::services::messages::chat as chat_service;
+use crate::services::messages::chat::ChatScope;
+use crate::services::messages::replies;
 use crate::storage::{sessions, documents, topics, chats, messages};
+use crate::storage::messages::ReplyStart;
 use crate::storage::sessions::{SessionStatus, DraftPlan, DraftPlanTopic};
 
 /// Generate the initial study plan from documents (stores as draft_plan)
@@ -236,6 +241,17 @@ pub async fn start_studying(ctx: &Context<'_>, session_id: ID) -> Result<Session
         created_topics.len()
     );
 
+    // Link problems extracted from exams to the new topics
+    let task_guard = metrics::BackgroundTaskGuard::new("problem_linking");
+    let (link_pool, link_config) = (pool.clone(), config.clone());
+    tokio::spawn(async move {
+        let _task_guard = task_guard;
+        match problems::link_session_problems(&link_pool, &link_config, profile_id, session_uuid).await {
+            Ok(linked) => tracing::info!("Linked {} problems to topics", linked),
+            Err(e) => tracing::error!("Failed to link problems to topics: {:?}", e),
+        }
+    }.instrument(tracing::info_span!("link_problems", session_id = %session_uuid)));
+
     // Spawn background tasks to generate welcome messages for all chats
     let pool_clone = pool.clone();
     let config_clone = config.clone();
@@ -250,12 +266,14 @@ pub async fn start_studying(ctx: &Context<'_>, session_id: ID) -> Result<Session
             let config = config_clone.clone();
             let language = language.clone();
             
+            let task_guard = metrics::BackgroundTaskGuard::new("welcome_message");
             let handle = tokio::spawn(async move {
+                let _task_guard = task_guard;
                 match generate_and_save_welcome(&pool, &config, profile_id, session_uuid, chat_id, Some(&topic_title), &language).await {
                     Ok(_) => tracing::info!("Welcome message generated for topic chat {}", chat_id),
                     Err(e) => tracing::error!("Failed to generate welcome for topic chat {}: {:?}", chat_id, e),
                 }
-            });
+            }.instrument(tracing::info_span!("generate_welcome", %chat_id)));
             handles.push(handle);
         }
         
@@ -265,13 +283,16 @@ pub async fn start_studying(ctx: &Context<'_>, session_id: ID) -> Result<Session
             let config = config_clone.clone();
             let review_chat_id = review_chat.id;
             let language = language.clone();
+            let task_guard = metrics::BackgroundTaskGuard::new("welcome_message");
             
             async move {
+                let _task_guard = task_guard;
                 match generate_and_save_welcome(&pool, &config, profile_id, session_uuid, review_chat_id, None, &language).await {
                     Ok(_) => tracing::info!("Welcome message generated for review chat {}", review_chat_id),
                     Err(e) => tracing::error!("Failed to generate welcome for review chat {}: {:?}", review_chat_id, e),
                 }
             }
+            .instrument(tracing::info_span!("generate_welcome", chat_id = %review_chat_id))
         });
         handles.push(review_handle);
         
@@ -281,7 +302,7 @@ pub async fn start_studying(ctx: &Context<'_>, session_id: ID) -> Result<Session
         }
         
         tracing::info!("All welcome messages generated for session {}", session_uuid);
-    });
+    }.instrument(tracing::info_span!("generate_welcome_messages", session_id = %session_uuid)));
 
     Ok(updated.into())
 }
@@ -296,28 +317,15 @@ async fn generate_and_save_welcome(
     topic_name: Option<&str>,
     language: &str,
 ) -> Result<(), async_graphql::Error> {
-    // Generate welcome message from AI
-    let welcome_content = chat_service::generate_welcome_message(
-        pool,
-        config,
-        profile_id,
-        session_id,
-        topic_name,
-        language,
-    )
-    .await?;
-
-    // Save the welcome message as an assistant message
-    messages::create_message(
-        pool,
-        chat_id,
-        "assistant",
-        &welcome_content,
-    )
-    .await?;
+    // Store a pending welcome as the first assistant message
+    let reply = messages::begin_reply(pool, profile_id, chat_id, ReplyStart::Welcome).await?;
 
     // Mark chat as started
     chats::mark_chat_started(pool, profile_id, chat_id).await?;
 
+    // Generate welcome message from AI
+    let scope = ChatScope { profile_id, session_id, chat_id, topic_name, language };
+    replies::run_reply(pool, config, scope, &reply, None).await?;
+
     Ok(())
 }
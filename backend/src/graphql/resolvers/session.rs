@@ -1,9 +1,13 @@
+use async_graphql::connection::Connection;
 use async_graphql::{Context, InputObject, Result, ID};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::config::Config;
 use crate::graphql::context::GraphQLContext;
+use crate::graphql::pagination::{paginate, PageCursor};
 use crate::graphql::types::Session;
+use crate::services::documents::storage_client;
 use crate::storage::sessions;
 
 #[derive(InputObject)]
@@ -22,6 +26,24 @@ pub async fn get_sessions(ctx: &Context<'_>) -> Result<Vec<Session>> {
     Ok(sessions.into_iter().map(Into::into).collect())
 }
 
+/// Get a page of sessions for the authenticated user (newest first)
+pub async fn get_sessions_connection(
+    ctx: &Context<'_>,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+) -> Result<Connection<PageCursor, Session>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    paginate(after, before, first, last, |page| async move {
+        sessions::get_profile_sessions_page(pool, profile_id, &page).await
+    })
+    .await
+}
+
 /// Get a single session by ID
 pub async fn get_session(ctx: &Context<'_>, id: ID) -> Result<Option<Session>> {
     let gql_ctx = ctx.data::<GraphQLContext>()?;
@@ -82,9 +104,25 @@ pub async fn delete_session(ctx: &Context<'_>, id: ID) -> Result<bool> {
     let gql_ctx = ctx.data::<GraphQLContext>()?;
     let profile_id = gql_ctx.require_auth()?;
     let pool = ctx.data::<PgPool>()?;
+    let config = ctx.data::<Config>()?;
 
     let session_id = Uuid::parse_str(&id).map_err(|_| "Invalid session ID")?;
     let deleted = sessions::delete_session(pool, profile_id, session_id).await?;
 
+    // Everything the session stored lives under its folder (don't fail the operation
+    // if storage delete fails)
+    if deleted {
+        match storage_client::delete_folder(
+            &config.supabase_url,
+            &config.supabase_service_key,
+            &session_id.to_string(),
+        )
+        .await
+        {
+            Ok(count) => tracing::info!("Deleted {} stored files of session {}", count, session_id),
+            Err(e) => tracing::warn!("Failed to delete files of session {}: {}", session_id, e),
+        }
+    }
+
     Ok(deleted)
 }
@@ -3,8 +3,8 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::graphql::context::GraphQLContext;
-use crate::graphql::types::Topic;
-use crate::storage::{sessions, topics};
+use crate::graphql::types::{Problem, Topic};
+use crate::storage::{problems, sessions, topics};
 
 /// Get all topics for a session
 pub async fn get_topics(ctx: &Context<'_>, session_id: ID) -> Result<Vec<Topic>> {
@@ -35,6 +35,24 @@ pub async fn get_topic(ctx: &Context<'_>, id: ID) -> Result<Option<Topic>> {
     Ok(topic.map(Into::into))
 }
 
+/// Get the exam problems linked to a topic
+pub async fn get_topic_problems(ctx: &Context<'_>, topic_id: ID) -> Result<Vec<Problem>> {
+    let gql_ctx = ctx.data::<GraphQLContext>()?;
+    let profile_id = gql_ctx.require_auth()?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let topic_uuid = Uuid::parse_str(&topic_id).map_err(|_| "Invalid topic ID")?;
+
+    // Verify topic exists and belongs to user
+    let topic = topics::get_topic_by_id(pool, profile_id, topic_uuid).await?;
+    if topic.is_none() {
+        return Err("Topic not found".into());
+    }
+
+    let problem_list = problems::get_problems_by_topic_ids(pool, profile_id, &[topic_uuid]).await?;
+    Ok(problem_list.into_iter().map(Into::into).collect())
+}
+
 /// Mark a topic as completed or not completed
 pub async fn update_topic_completion(
     ctx: &Context<'_>,
@@ -1,37 +1,64 @@
-use async_graphql::{EmptySubscription, Schema};
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql::extensions::apollo_persisted_queries::{ApolloPersistedQueries, LruCacheStorage};
+use async_graphql::{extensions::Tracing, Data, Schema};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 use axum::{
-    extract::State,
+    extract::{State, WebSocketUpgrade},
     http::HeaderMap,
-    response::{Html, IntoResponse},
+    response::{Html, IntoResponse, Response},
 };
 use sqlx::PgPool;
+use std::time::Instant;
+use tracing::Instrument;
 
 use crate::config::Config;
+use crate::metrics;
 use crate::services::auth::jwt::verify_jwt;
+use crate::services::documents::progress::ProgressBus;
 
 use super::context::GraphQLContext;
-use super::resolvers::{MutationRoot, QueryRoot};
+use super::loaders;
+use super::operation_label::{OperationLabel, OperationLabels};
+use super::resolvers::{MutationRoot, QueryRoot, SubscriptionRoot};
 
-pub type AppSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+/// Number of parsed queries kept for Automatic Persisted Queries
+const PERSISTED_QUERY_CACHE_SIZE: usize = 1024;
+
+pub type AppSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub schema: AppSchema,
     pub config: Config,
     pub db_pool: PgPool,
+    pub progress: ProgressBus,
 }
 
 pub fn create_schema(pool: PgPool, config: Config) -> AppState {
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+    let progress = ProgressBus::new(pool.clone());
+
+    let mut builder = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(pool.clone())
         .data(config.clone())
-        .finish();
+        .data(progress.clone())
+        .extension(Tracing)
+        .extension(OperationLabels)
+        .extension(ApolloPersistedQueries::new(LruCacheStorage::new(
+            PERSISTED_QUERY_CACHE_SIZE,
+        )))
+        .limit_depth(config.graphql_max_depth)
+        .limit_complexity(config.graphql_max_complexity);
+
+    if !config.graphql_introspection {
+        builder = builder.disable_introspection();
+    }
+
+    let schema = builder.finish();
 
     AppState { 
         schema, 
         config,
         db_pool: pool,
+        progress,
     }
 }
 
@@ -43,19 +70,77 @@ pub async fn graphql_handler(
     // Extract JWT from Authorization header
     let ctx = extract_context(&headers, &state.config);
 
-    state
-        .schema
-        .execute(req.into_inner().data(ctx))
-        .await
-        .into()
+    let mut req = req.into_inner();
+    if let Some(profile_id) = ctx.user_id {
+        req = loaders::attach(req, &state.db_pool, profile_id);
+    }
+
+    let operation = req.operation_name.clone();
+
+    let span = tracing::info_span!(
+        "graphql_operation",
+        operation = %operation.as_deref().unwrap_or("anonymous")
+    );
+    let label = OperationLabel::default();
+    let started = Instant::now();
+    let response = state.schema.execute(req.data(ctx).data(label.clone())).instrument(span).await;
+    metrics::observe_graphql_request(label.get().as_deref(), response.is_ok(), started.elapsed());
+
+    response.into()
 }
 
-fn extract_context(headers: &HeaderMap, config: &Config) -> GraphQLContext {
-    let language = headers
+/// GET /graphql/ws — GraphQL subscriptions over WebSocket
+///
+/// Browsers can't set headers on WebSocket requests, so the JWT is read from the
+/// `connection_init` payload (`{"Authorization": "Bearer <JWT>"}`) and the
+/// `x-language` header from the upgrade request.
+pub async fn graphql_ws_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let language = language_from_headers(&headers);
+
+    upgrade
+        .protocols(async_graphql::http::ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            let config = state.config.clone();
+            GraphQLWebSocket::new(stream, state.schema.clone(), protocol)
+                .on_connection_init(move |payload| async move {
+                    let token = payload
+                        .get("Authorization")
+                        .or_else(|| payload.get("authorization"))
+                        .and_then(|v| v.as_str())
+                        .and_then(|v| v.strip_prefix("Bearer "));
+
+                    let ctx = match token {
+                        Some(token) => {
+                            let user_id = verify_jwt(token, &config.jwt_secret)
+                                .map_err(|_| async_graphql::Error::new("Invalid or expired token"))?;
+                            GraphQLContext::authenticated(user_id, language)
+                        }
+                        None => GraphQLContext::new(language),
+                    };
+
+                    let mut data = Data::default();
+                    data.insert(ctx);
+                    Ok(data)
+                })
+                .serve()
+        })
+}
+
+fn language_from_headers(headers: &HeaderMap) -> String {
+    headers
         .get("x-language")
         .and_then(|v| v.to_str().ok())
         .unwrap_or("pt")
-        .to_string();
+        .to_string()
+}
+
+fn extract_context(headers: &HeaderMap, config: &Config) -> GraphQLContext {
+    let language = language_from_headers(headers);
 
     let auth_header = headers
         .get("authorization")
@@ -74,7 +159,8 @@ fn extract_context(headers: &HeaderMap, config: &Config) -> GraphQLContext {
 pub async fn graphql_playground() -> impl IntoResponse {
     Html(
         async_graphql::http::playground_source(
-            async_graphql::http::GraphQLPlaygroundConfig::new("/graphql"),
+            async_graphql::http::GraphQLPlaygroundConfig::new("/graphql")
+                .subscription_endpoint("/graphql/ws"),
         ),
     )
 }
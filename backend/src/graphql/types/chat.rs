@@ -1,7 +1,10 @@
-use async_graphql::{Enum, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::graphql::context::GraphQLContext;
+use crate::graphql::loaders::{ChatMessagesLoader, TopicLoader};
 use crate::storage::chats::{ChatRow, ChatType as StorageChatType};
 
 /// The type of chat
@@ -23,7 +26,7 @@ impl From<StorageChatType> for ChatType {
 }
 
 #[derive(SimpleObject, Clone)]
-#[graphql(rename_fields = "camelCase")]
+#[graphql(rename_fields = "camelCase", complex)]
 pub struct Chat {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -47,3 +50,24 @@ impl From<ChatRow> for Chat {
         }
     }
 }
+
+#[ComplexObject]
+impl Chat {
+    /// The topic this chat belongs to (null for the general review chat)
+    async fn topic(&self, ctx: &Context<'_>) -> Result<Option<super::Topic>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let Some(topic_id) = self.topic_id else {
+            return Ok(None);
+        };
+        let loader = ctx.data::<DataLoader<TopicLoader>>()?;
+        Ok(loader.load_one(topic_id).await?.map(Into::into))
+    }
+
+    /// All messages in this chat, oldest first
+    async fn messages(&self, ctx: &Context<'_>) -> Result<Vec<super::Message>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<ChatMessagesLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
@@ -1,8 +1,17 @@
-use async_graphql::{Enum, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::storage::documents::{DocumentRow, ProcessingStatus as StorageStatus};
+use crate::config::Config;
+use crate::graphql::context::GraphQLContext;
+use crate::graphql::loaders::{DocumentPageEditsLoader, DocumentPagesLoader};
+use crate::services::documents::progress::ProgressEvent;
+use crate::services::documents::storage_client;
+use crate::storage::document_pages::DocumentPageEditRow;
+use crate::storage::documents::{
+    DocumentRow, DocumentType as StorageDocumentType, ProcessingStatus as StorageStatus,
+};
 
 /// The processing status of a document
 #[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
@@ -28,15 +37,58 @@ impl From<StorageStatus> for ProcessingStatus {
     }
 }
 
+/// Kind of study material, detected during processing
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DocumentType {
+    /// Lecture slides or presentations
+    Slides,
+    /// Lecture or student notes
+    Notes,
+    /// Past exam, test or quiz
+    PastExam,
+    /// Exercises, homework or problem sheets
+    ProblemSet,
+    /// Chapter or excerpt from a textbook
+    TextbookChapter,
+    /// Course syllabus or schedule
+    Syllabus,
+    /// Anything else
+    Other,
+}
+
+impl From<StorageDocumentType> for DocumentType {
+    fn from(document_type: StorageDocumentType) -> Self {
+        match document_type {
+            StorageDocumentType::Slides => DocumentType::Slides,
+            StorageDocumentType::Notes => DocumentType::Notes,
+            StorageDocumentType::PastExam => DocumentType::PastExam,
+            StorageDocumentType::ProblemSet => DocumentType::ProblemSet,
+            StorageDocumentType::TextbookChapter => DocumentType::TextbookChapter,
+            StorageDocumentType::Syllabus => DocumentType::Syllabus,
+            StorageDocumentType::Other => DocumentType::Other,
+        }
+    }
+}
+
 #[derive(SimpleObject, Clone)]
-#[graphql(rename_fields = "camelCase")]
+#[graphql(rename_fields = "camelCase", complex)]
 pub struct Document {
     pub id: Uuid,
     pub session_id: Uuid,
     pub file_name: String,
     pub file_path: String,
+    /// Size of the uploaded file in bytes (unknown for older documents)
+    pub file_size: Option<i64>,
     pub content_length: Option<i32>,
+    /// Kind of material (unknown until processing completes)
+    pub document_type: Option<DocumentType>,
+    /// Course name as stated in the document
+    pub course_name: Option<String>,
+    /// Year the document is from (e.g. exam year)
+    pub year: Option<i32>,
     pub processing_status: ProcessingStatus,
+    /// Why processing failed, when it did
+    pub processing_error: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -47,9 +99,121 @@ impl From<DocumentRow> for Document {
             session_id: row.session_id,
             file_name: row.file_name,
             file_path: row.file_path,
+            file_size: row.file_size,
             content_length: row.content_length,
+            document_type: row.document_type.map(Into::into),
+            course_name: row.course_name,
+            year: row.year,
             processing_status: ProcessingStatus::from(row.processing_status),
+            processing_error: row.processing_error,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Extracted text and preview image of one page
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct DocumentPage {
+    pub page_number: i32,
+    /// Text the AI tutor sees for this page
+    pub text: String,
+    /// Signed URL of a small preview image of the page (expires in 1 hour)
+    pub thumbnail_url: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A student's correction of one page's extracted text
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct DocumentPageEdit {
+    pub id: Uuid,
+    pub page_number: i32,
+    pub previous_text: String,
+    pub new_text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<DocumentPageEditRow> for DocumentPageEdit {
+    fn from(row: DocumentPageEditRow) -> Self {
+        Self {
+            id: row.id,
+            page_number: row.page_number,
+            previous_text: row.previous_text,
+            new_text: row.new_text,
             created_at: row.created_at,
         }
     }
 }
+
+#[ComplexObject]
+impl Document {
+    /// Extracted pages in order (empty until processing completes)
+    async fn pages(&self, ctx: &Context<'_>) -> Result<Vec<DocumentPage>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let config = ctx.data::<Config>()?;
+        let loader = ctx.data::<DataLoader<DocumentPagesLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+
+        // Sign all thumbnails of the document in one storage request
+        let paths: Vec<String> = rows.iter().filter_map(|p| p.thumbnail_path.clone()).collect();
+        let mut urls = storage_client::create_signed_urls(
+            &config.supabase_url,
+            &config.supabase_service_key,
+            &paths,
+            3600,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to sign page thumbnails: {}", e);
+            vec![None; paths.len()]
+        })
+        .into_iter();
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DocumentPage {
+                page_number: row.page_number,
+                thumbnail_url: row.thumbnail_path.as_ref().and_then(|_| urls.next().flatten()),
+                text: row.content_text,
+                updated_at: row.updated_at,
+            })
+            .collect())
+    }
+
+    /// Corrections made to the extracted text, newest first
+    /// (the newest one per page is kept when the document is reprocessed)
+    async fn page_edits(&self, ctx: &Context<'_>) -> Result<Vec<DocumentPageEdit>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<DocumentPageEditsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A live update on a document being processed
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct DocumentProgress {
+    pub document_id: Uuid,
+    pub session_id: Uuid,
+    pub status: ProcessingStatus,
+    /// Pages extracted so far (only while processing)
+    pub pages_processed: Option<u32>,
+    pub page_count: Option<u32>,
+    /// Why processing failed, when it did
+    pub error: Option<String>,
+}
+
+impl From<ProgressEvent> for DocumentProgress {
+    fn from(event: ProgressEvent) -> Self {
+        Self {
+            document_id: event.document_id,
+            session_id: event.session_id,
+            status: ProcessingStatus::from(event.status),
+            pages_processed: event.pages_processed,
+            page_count: event.page_count,
+            error: event.error,
+        }
+    }
+}
@@ -1,8 +1,18 @@
-use async_graphql::{Enum, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::storage::messages::MessageRow;
+use crate::graphql::context::GraphQLContext;
+use crate::config::Config;
+use crate::graphql::loaders::{
+    MessageAttachmentsLoader, MessageBranchesLoader, MessageCitationsLoader, MessageFeedbackLoader,
+};
+use crate::services::documents::storage_client;
+use crate::storage::message_attachments::AttachmentRow;
+use crate::storage::message_citations::CitationRow;
+use crate::storage::message_feedback::FeedbackRow;
+use crate::storage::messages::{MessageRow, MessageStatus as StorageStatus};
 
 #[derive(Enum, Copy, Clone, Eq, PartialEq)]
 pub enum MessageRole {
@@ -25,6 +35,26 @@ impl From<&str> for MessageRole {
     }
 }
 
+/// Whether a message's content is final
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MessageStatus {
+    /// The reply is still being generated
+    Pending,
+    Complete,
+    /// Generating the reply failed; it can be retried with `retryMessage`
+    Failed,
+}
+
+impl From<StorageStatus> for MessageStatus {
+    fn from(status: StorageStatus) -> Self {
+        match status {
+            StorageStatus::Pending => MessageStatus::Pending,
+            StorageStatus::Complete => MessageStatus::Complete,
+            StorageStatus::Failed => MessageStatus::Failed,
+        }
+    }
+}
+
 impl From<MessageRole> for &'static str {
     fn from(role: MessageRole) -> Self {
         match role {
@@ -36,12 +66,19 @@ impl From<MessageRole> for &'static str {
 }
 
 #[derive(SimpleObject, Clone)]
-#[graphql(rename_fields = "camelCase")]
+#[graphql(rename_fields = "camelCase", complex)]
 pub struct Message {
     pub id: Uuid,
     pub chat_id: Uuid,
     pub role: MessageRole,
     pub content: String,
+    /// Prompt template version that produced an assistant message
+    pub template_version: Option<String>,
+    /// Message this one follows (`null` for the first message of a chat)
+    pub parent_id: Option<Uuid>,
+    /// Whether the message is on the chat's current branch
+    pub is_active: bool,
+    pub status: MessageStatus,
     pub created_at: DateTime<Utc>,
 }
 
@@ -52,6 +89,154 @@ impl From<MessageRow> for Message {
             chat_id: row.chat_id,
             role: MessageRole::from(row.role.as_str()),
             content: row.content,
+            template_version: row.template_version,
+            parent_id: row.parent_id,
+            is_active: row.is_active,
+            status: MessageStatus::from(row.status),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[ComplexObject]
+impl Message {
+    /// Sources cited in the content, one per `[n]` marker
+    async fn citations(&self, ctx: &Context<'_>) -> Result<Vec<Citation>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<MessageCitationsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// All versions of this message (regenerated or edited alternatives), oldest first
+    async fn branches(&self, ctx: &Context<'_>) -> Result<Vec<Message>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<MessageBranchesLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(|b| b.message.into()).collect())
+    }
+
+    /// Images attached to the message, with short-lived URLs to display them
+    async fn attachments(&self, ctx: &Context<'_>) -> Result<Vec<Attachment>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<MessageAttachmentsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        if rows.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let config = ctx.data::<Config>()?;
+        let paths: Vec<String> = rows.iter().map(|a| a.file_path.clone()).collect();
+        let urls = storage_client::create_signed_urls(
+            &config.supabase_url,
+            &config.supabase_service_key,
+            &paths,
+            3600,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create signed URLs for attachments: {}", e);
+            async_graphql::Error::new("Failed to create attachment URLs")
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .zip(urls)
+            .map(|(row, url)| Attachment::from_row(row, url))
+            .collect())
+    }
+
+    /// The student's rating of this message, if any
+    async fn feedback(&self, ctx: &Context<'_>) -> Result<Option<MessageFeedback>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<MessageFeedbackLoader>>()?;
+        Ok(loader.load_one(self.id).await?.map(Into::into))
+    }
+}
+
+/// A document (and page range) an assistant message drew from
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct Citation {
+    /// Number of the `[n]` marker in the message content
+    pub marker: i32,
+    /// Pass to `documentUrl` to open the source
+    pub document_id: Uuid,
+    pub document_name: String,
+    /// First cited page (unset when the whole document is cited)
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+}
+
+impl From<CitationRow> for Citation {
+    fn from(row: CitationRow) -> Self {
+        Self {
+            marker: row.marker,
+            document_id: row.document_id,
+            document_name: row.document_name,
+            page_start: row.page_start,
+            page_end: row.page_end,
+        }
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MessageRating {
+    Up,
+    Down,
+}
+
+impl MessageRating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRating::Up => "up",
+            MessageRating::Down => "down",
+        }
+    }
+}
+
+/// A student's rating of an assistant message
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct MessageFeedback {
+    pub rating: MessageRating,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<FeedbackRow> for MessageFeedback {
+    fn from(row: FeedbackRow) -> Self {
+        Self {
+            rating: if row.rating == "up" { MessageRating::Up } else { MessageRating::Down },
+            reason: row.reason,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// An image attached to a message
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct Attachment {
+    pub id: Uuid,
+    pub file_name: String,
+    pub content_type: String,
+    pub file_size: Option<i64>,
+    /// Signed URL to display the image (expires in 1 hour)
+    pub url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Attachment {
+    fn from_row(row: AttachmentRow, url: Option<String>) -> Self {
+        Self {
+            id: row.id,
+            file_name: row.file_name,
+            content_type: row.content_type,
+            file_size: row.file_size,
+            url,
             created_at: row.created_at,
         }
     }
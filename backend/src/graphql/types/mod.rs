@@ -4,10 +4,12 @@ mod document;
 mod topic;
 mod chat;
 mod message;
+mod problem;
 
 pub use user::User;
 pub use session::Session;
-pub use document::Document;
+pub use document::{Document, DocumentProgress};
 pub use topic::Topic;
 pub use chat::Chat;
-pub use message::Message;
+pub use message::{Message, MessageRating};
+pub use problem::Problem;
@@ -0,0 +1,49 @@
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::storage::problems::ProblemRow;
+
+/// A problem extracted from a past exam or problem set
+#[derive(SimpleObject, Clone)]
+#[graphql(rename_fields = "camelCase")]
+pub struct Problem {
+    pub id: Uuid,
+    pub document_id: Uuid,
+    /// File name of the document the problem comes from
+    pub document_name: String,
+    /// Topic the problem assesses (unset until the study plan has topics)
+    pub topic_id: Option<Uuid>,
+    /// Order within the source document
+    pub position: i32,
+    /// Number or name as printed (e.g. "3", "Exercise 2.4")
+    pub label: Option<String>,
+    pub statement: String,
+    /// Sub-questions, in order, with their printed labels
+    pub sub_parts: Vec<String>,
+    /// Solution given in the document, if any
+    pub solution: Option<String>,
+    pub points: Option<f64>,
+    /// Page the problem starts on
+    pub page_number: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ProblemRow> for Problem {
+    fn from(row: ProblemRow) -> Self {
+        Self {
+            id: row.id,
+            document_id: row.document_id,
+            document_name: row.document_name,
+            topic_id: row.topic_id,
+            position: row.position,
+            label: row.label,
+            statement: row.statement,
+            sub_parts: row.sub_parts,
+            solution: row.solution,
+            points: row.points,
+            page_number: row.page_number,
+            created_at: row.created_at,
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use async_graphql::{Enum, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::graphql::context::GraphQLContext;
+use crate::graphql::loaders::{SessionDocumentsLoader, SessionTopicsLoader};
 use crate::storage::sessions::{SessionRow, SessionStatus as StorageStatus, DraftPlan as StorageDraftPlan, DraftPlanTopic as StorageDraftPlanTopic};
 
 /// The status of a study session
@@ -60,6 +63,7 @@ impl From<StorageDraftPlan> for DraftPlan {
 }
 
 #[derive(SimpleObject, Clone)]
+#[graphql(complex)]
 pub struct Session {
     pub id: Uuid,
     pub title: String,
@@ -90,3 +94,22 @@ impl From<SessionRow> for Session {
         }
     }
 }
+
+#[ComplexObject]
+impl Session {
+    /// Topics of this session, in curriculum order (empty until studying starts)
+    async fn topics(&self, ctx: &Context<'_>) -> Result<Vec<super::Topic>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<SessionTopicsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// Documents uploaded to this session, newest first
+    async fn documents(&self, ctx: &Context<'_>) -> Result<Vec<super::Document>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<SessionDocumentsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
@@ -1,10 +1,14 @@
-use async_graphql::SimpleObject;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Result, SimpleObject};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::graphql::context::GraphQLContext;
+use crate::graphql::loaders::{TopicChatLoader, TopicProblemsLoader};
 use crate::storage::topics::TopicRow;
 
 #[derive(SimpleObject, Clone)]
+#[graphql(complex)]
 pub struct Topic {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -30,3 +34,21 @@ impl From<TopicRow> for Topic {
         }
     }
 }
+
+#[ComplexObject]
+impl Topic {
+    /// The dedicated chat for this topic
+    async fn chat(&self, ctx: &Context<'_>) -> Result<Option<super::Chat>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<TopicChatLoader>>()?;
+        Ok(loader.load_one(self.id).await?.map(Into::into))
+    }
+
+    /// Exam problems that assess this topic, newest exams first
+    async fn problems(&self, ctx: &Context<'_>) -> Result<Vec<super::Problem>> {
+        ctx.data::<GraphQLContext>()?.require_auth()?;
+        let loader = ctx.data::<DataLoader<TopicProblemsLoader>>()?;
+        let rows = loader.load_one(self.id).await?.unwrap_or_default();
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+}
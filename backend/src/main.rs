@@ -2,19 +2,27 @@ mod api;
 mod config;
 
 mod graphql;
+mod metrics;
 mod prompts;
+mod templates;
 mod services;
 mod storage;
+mod telemetry;
 
 use axum::{
-    http::{HeaderValue, Method},
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue, Method},
     routing::{get, post},
     Router,
 };
 use sqlx::postgres::PgPoolOptions;
 use std::net::SocketAddr;
-use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tower::ServiceBuilder;
+use tower_http::{
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 use crate::config::Config;
 
@@ -23,15 +31,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file
     dotenvy::dotenv().ok();
 
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     // Load configuration
     let config = Config::from_env().expect("Failed to load configuration");
-    tracing::info!("Configuration loaded");
+
+    // Initialize tracing (and the OTLP exporter if configured)
+    let tracer_provider = telemetry::init(&config);
+    tracing::info!("Configuration loaded (environment: {})", config.environment);
 
     // Create database connection pool
     let pool = PgPoolOptions::new()
@@ -44,6 +49,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Build GraphQL schema and app state
     let app_state = graphql::create_schema(pool.clone(), config.clone());
 
+    // Relay document progress published by other instances to local subscribers
+    app_state.progress.spawn_listener();
+
+    // Load prompt template overrides (files and database) and keep them fresh
+    templates::spawn_reloader(pool.clone(), config.clone());
+
+    // Delete images uploaded for messages that were never sent
+    services::messages::attachments::spawn_cleanup(pool.clone(), config.clone());
+
     // Configure CORS
     let allowed_origins: Vec<HeaderValue> = config
         .allowed_origins
@@ -54,29 +68,100 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cors = CorsLayer::new()
         .allow_origin(allowed_origins)
         .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([HeaderName::from_static(telemetry::REQUEST_ID_HEADER)]);
+
+    // Assign (or keep the client's) x-request-id, open a span per request and echo the ID back
+    let request_id_header = HeaderName::from_static(telemetry::REQUEST_ID_HEADER);
+    let tracing_layers = ServiceBuilder::new()
+        .layer(SetRequestIdLayer::new(request_id_header.clone(), MakeRequestUuid))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::make_request_span))
+        .layer(PropagateRequestIdLayer::new(request_id_header));
+
+    // The playground is only served when enabled (development by default)
+    let graphql_route = if config.graphql_playground {
+        get(graphql::graphql_playground).post(graphql::graphql_handler)
+    } else {
+        post(graphql::graphql_handler)
+    };
 
     // Build router
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/graphql", graphql_route)
+        .route("/graphql/ws", get(graphql::graphql_ws_handler))
+        .route("/api/admin/feedback.jsonl", get(api::export_feedback))
         .route(
-            "/graphql",
-            get(graphql::graphql_playground).post(graphql::graphql_handler),
+            "/api/upload",
+            post(api::upload_file)
+                .layer(DefaultBodyLimit::max(services::documents::uploads::MAX_UPLOAD_BODY_SIZE)),
         )
-        .route("/api/upload", post(api::upload_file))
+        .layer(tracing_layers)
         .layer(cors)
-        .with_state(app_state);
+        .with_state(app_state.clone());
+
+    // Metrics are served on their own port so they can be kept off the public network
+    if config.metrics_port != 0 {
+        let metrics_app = Router::new()
+            .route("/metrics", get(api::metrics))
+            .with_state(app_state);
+        let metrics_addr = SocketAddr::from(([0, 0, 0, 0], config.metrics_port));
+        let metrics_listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        tracing::info!("Metrics listening on {}", metrics_addr);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(metrics_listener, metrics_app).await {
+                tracing::error!("Metrics server failed: {}", e);
+            }
+        });
+    }
 
     // Start server
     let addr = SocketAddr::from(([0, 0, 0, 0], config.server_port));
     tracing::info!("Server listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // Flush any spans still buffered for export
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to shut down tracer provider: {}", e);
+        }
+    }
 
     Ok(())
 }
 
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => tracing::error!("Failed to listen for SIGTERM: {}", e),
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received");
+}
+
 async fn health_check() -> &'static str {
     "OK"
 }
@@ -0,0 +1,150 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+
+/// Registry holding every metric exposed on `GET /metrics`
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// Latency buckets (seconds) shared by GraphQL and LLM histograms
+const LATENCY_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+static GRAPHQL_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("graphql_requests_total", "GraphQL requests by operation and outcome"),
+        &["operation", "status"],
+    ))
+});
+
+static GRAPHQL_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("graphql_request_duration_seconds", "GraphQL request latency by operation")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        &["operation"],
+    ))
+});
+
+static INGESTION_PAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("ingestion_pages_total", "PDF pages sent to vision extraction by outcome"),
+        &["status"],
+    ))
+});
+
+static LLM_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("llm_request_duration_seconds", "OpenRouter request latency by task")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        &["task", "model", "status"],
+    ))
+});
+
+static LLM_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("llm_tokens_total", "Tokens reported by OpenRouter by task and kind"),
+        &["task", "model", "kind"],
+    ))
+});
+
+static DB_POOL_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new("db_pool_connections", "Open connections in the database pool"))
+});
+
+static DB_POOL_IDLE: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new("db_pool_idle_connections", "Idle connections in the database pool"))
+});
+
+static BACKGROUND_TASKS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("background_tasks_in_flight", "Spawned background tasks that have not finished yet"),
+        &["kind"],
+    ))
+});
+
+fn register<M>(metric: Result<M, prometheus::Error>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+/// Record a finished GraphQL request, labelled by the root field it selected
+/// (`None` for requests rejected before that is known, e.g. invalid queries)
+pub fn observe_graphql_request(root_field: Option<&str>, is_ok: bool, elapsed: Duration) {
+    let operation = root_field.unwrap_or("invalid");
+    let status = if is_ok { "ok" } else { "error" };
+    GRAPHQL_REQUESTS.with_label_values(&[operation, status]).inc();
+    GRAPHQL_LATENCY
+        .with_label_values(&[operation])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Record the outcome of a single page sent to vision extraction
+pub fn observe_ingestion_page(is_ok: bool) {
+    let status = if is_ok { "processed" } else { "failed" };
+    INGESTION_PAGES.with_label_values(&[status]).inc();
+}
+
+/// Record an OpenRouter call (latency and, when reported, token usage)
+pub fn observe_llm_request(
+    task: &str,
+    model: &str,
+    is_ok: bool,
+    elapsed: Duration,
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+) {
+    let status = if is_ok { "ok" } else { "error" };
+    LLM_LATENCY
+        .with_label_values(&[task, model, status])
+        .observe(elapsed.as_secs_f64());
+
+    if let Some(tokens) = prompt_tokens {
+        LLM_TOKENS.with_label_values(&[task, model, "prompt"]).inc_by(tokens);
+    }
+    if let Some(tokens) = completion_tokens {
+        LLM_TOKENS.with_label_values(&[task, model, "completion"]).inc_by(tokens);
+    }
+}
+
+/// Guard that counts a background task as in flight until it is dropped
+pub struct BackgroundTaskGuard {
+    kind: &'static str,
+}
+
+impl BackgroundTaskGuard {
+    pub fn new(kind: &'static str) -> Self {
+        BACKGROUND_TASKS.with_label_values(&[kind]).inc();
+        Self { kind }
+    }
+}
+
+impl Drop for BackgroundTaskGuard {
+    fn drop(&mut self) {
+        BACKGROUND_TASKS.with_label_values(&[self.kind]).dec();
+    }
+}
+
+/// Render all metrics in the Prometheus text format
+/// Pool gauges are sampled at scrape time
+pub fn render(pool: &PgPool) -> String {
+    DB_POOL_CONNECTIONS.set(pool.size() as i64);
+    DB_POOL_IDLE.set(pool.num_idle() as i64);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {}", e);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -0,0 +1,85 @@
+use crate::config::Config;
+use crate::templates::{self, TemplateName, TemplateVars};
+use crate::services::messages::ai_client::{AiTask, OpenRouterClient};
+use crate::storage::documents::DocumentClassification;
+
+const CLASSIFICATION_MODEL: &str = "google/gemini-2.5-flash";
+
+/// Only the start of a document is needed to tell what it is
+const MAX_CLASSIFICATION_CHARS: usize = 12_000;
+
+/// Classify a document (slides, past exam, ...) and extract course name and year
+pub async fn classify_document(
+    config: &Config,
+    file_name: &str,
+    extracted_text: &str,
+) -> Result<DocumentClassification, async_graphql::Error> {
+    let excerpt: String = extracted_text.chars().take(MAX_CLASSIFICATION_CHARS).collect();
+    let prompt = templates::render(
+        TemplateName::ClassifyDocument,
+        None,
+        &TemplateVars::new().set("file_name", file_name).set("content", excerpt),
+    )?
+    .text;
+
+    let ai_client = OpenRouterClient::new(config);
+    let response = ai_client
+        .chat(
+            AiTask::Classification,
+            CLASSIFICATION_MODEL,
+            "You classify academic documents. Output valid JSON only.",
+            &prompt,
+        )
+        .await?
+        .content;
+
+    parse_classification(&response)
+}
+
+/// Parse the model's JSON answer (tolerating a markdown code block around it)
+fn parse_classification(response: &str) -> Result<DocumentClassification, async_graphql::Error> {
+    let json_str = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => response.trim(),
+    };
+
+    let mut classification: DocumentClassification = serde_json::from_str(json_str).map_err(|e| {
+        tracing::warn!("Failed to parse classification: {}\nResponse was: {}", e, response);
+        async_graphql::Error::new(format!("Failed to parse classification: {}", e))
+    })?;
+
+    // Drop empty strings and implausible years rather than storing noise
+    classification.course_name = classification
+        .course_name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    classification.year = classification.year.filter(|y| (1900..=2100).contains(y));
+
+    Ok(classification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::documents::DocumentType;
+
+    #[test]
+    fn parses_fenced_answer() {
+        let answer = "```json\n{\"document_type\": \"PAST_EXAM\", \"course_name\": \" \", \"year\": 2023}\n```";
+        let classification = parse_classification(answer).unwrap();
+
+        assert_eq!(classification.document_type, Some(DocumentType::PastExam));
+        assert_eq!(classification.course_name, None);
+        assert_eq!(classification.year, Some(2023));
+    }
+
+    #[test]
+    fn maps_unknown_type_to_other() {
+        let answer = r#"{"document_type": "LAB_REPORT", "course_name": "Physics 1", "year": 3000}"#;
+        let classification = parse_classification(answer).unwrap();
+
+        assert_eq!(classification.document_type, Some(DocumentType::Other));
+        assert_eq!(classification.course_name.as_deref(), Some("Physics 1"));
+        assert_eq!(classification.year, None);
+    }
+}
@@ -1,68 +1,86 @@
 use sqlx::PgPool;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use tempfile::TempDir;
 use tokio::fs;
 use uuid::Uuid;
 use futures::stream::{self, StreamExt};
 
 use crate::config::Config;
-use crate::services::documents::storage_client;
+use crate::metrics;
+use crate::services::documents::{classification, pdf_tools, problems, scanner, storage_client, uploads};
+use crate::services::documents::storage_client::StorageClient;
+use crate::services::documents::progress::{PageReporter, ProgressBus};
+use crate::services::documents::uploads::ProcessingJob;
+use crate::services::documents::pdf_tools::ToolLimits;
 use crate::services::messages::ai_client::{encode_base64, OpenRouterClient};
+use crate::storage::document_pages;
 use crate::storage::documents as doc_storage;
-use crate::storage::documents::ProcessingStatus;
+use crate::storage::documents::{DocumentOrigin, ProcessingStatus};
+use crate::storage::problems as problem_storage;
 
 const VISION_MODEL: &str = "google/gemini-2.5-flash";
 
+/// Longest side, in pixels, of the page previews kept in storage
+const THUMBNAIL_SIZE: u32 = 400;
+
+/// A document refused before extraction; the message is shown to the user
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct Rejected(pub String);
+
 /// Result of processing a PDF document
 pub struct ProcessedDocument {
     pub extracted_text: String,
+    /// Extracted text of each page, in order
+    pub pages: Vec<String>,
+    /// Storage path of each page thumbnail, when one was stored
+    pub thumbnails: Vec<Option<String>>,
     pub page_count: i32,
 }
 
 /// Process a PDF file: convert to images and extract text using vision AI
+///
+/// Each finished page is reported to `progress` when given. With a
+/// `thumbnail_prefix`, a small preview of each page is stored as
+/// `{prefix}/{page}.jpg`.
 pub async fn process_pdf(
     pdf_path: &Path,
     config: &Config,
+    progress: Option<&PageReporter<'_>>,
+    thumbnail_prefix: Option<&str>,
 ) -> Result<ProcessedDocument, async_graphql::Error> {
     // Create a temporary directory for images
     let temp_dir = TempDir::new()
         .map_err(|e| async_graphql::Error::new(format!("Failed to create temp dir: {}", e)))?;
 
-    // Convert PDF to PNG images using pdftoppm
+    // Convert PDF to PNG images using pdftoppm (never past the page limit)
     let output_prefix = temp_dir.path().join("page");
-    let status = Command::new("pdftoppm")
-        .args([
+    let last_page = config.max_pdf_pages.to_string();
+    let output = pdf_tools::run_limited(
+        "pdftoppm",
+        &[
             "-png",
             "-r",
             "150", // 150 DPI - good balance of quality and size
+            "-l",
+            &last_page,
             pdf_path.to_str().unwrap(),
             output_prefix.to_str().unwrap(),
-        ])
-        .status()
-        .map_err(|e| async_graphql::Error::new(format!("Failed to run pdftoppm: {}", e)))?;
+        ],
+        ToolLimits::from_config(config),
+    )
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Failed to run pdftoppm: {}", e)))?;
 
-    if !status.success() {
+    if !output.status.success() {
         return Err(async_graphql::Error::new("pdftoppm failed to convert PDF"));
     }
 
-    // Find all generated PNG files
-    let mut page_files: Vec<_> = std::fs::read_dir(temp_dir.path())
-        .map_err(|e| async_graphql::Error::new(format!("Failed to read temp dir: {}", e)))?
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| {
-            entry
-                .path()
-                .extension()
-                .map(|ext| ext == "png")
-                .unwrap_or(false)
-        })
-        .collect();
-
-    // Sort by filename to ensure correct page order
-    page_files.sort_by_key(|entry| entry.path());
+    let page_paths = rendered_pages(temp_dir.path(), "png")
+        .map_err(|e| async_graphql::Error::new(format!("Failed to read temp dir: {}", e)))?;
 
-    let page_count = page_files.len() as i32;
+    let page_count = page_paths.len() as i32;
 
     if page_count == 0 {
         return Err(async_graphql::Error::new("No pages extracted from PDF"));
@@ -71,17 +89,34 @@ pub async fn process_pdf(
     tracing::info!("Extracted {} pages from PDF", page_count);
 
     // Create OpenRouter client
-    let ai_client = OpenRouterClient::new(config.openrouter_api_key.clone());
+    let ai_client = OpenRouterClient::new(config);
 
     // Process pages in parallel
     tracing::info!("Starting parallel processing of {} pages", page_count);
 
-    // Collect paths first to avoid borrowing issues with DirEntry
-    let page_paths: Vec<_> = page_files.iter().map(|e| e.path()).collect();
+    // Small previews for the client, rendered apart from the full-size extraction images
+    let thumbnail_paths = match thumbnail_prefix {
+        Some(_) => match render_thumbnails(pdf_path, config, temp_dir.path()).await {
+            Ok(paths) if paths.len() == page_paths.len() => paths,
+            Ok(paths) => {
+                tracing::warn!("Rendered {} thumbnails for {} pages, skipping them", paths.len(), page_count);
+                Vec::new()
+            }
+            Err(e) => {
+                tracing::warn!("Failed to render page thumbnails: {}", e);
+                Vec::new()
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let pages_done = AtomicU32::new(0);
 
     let mut results = stream::iter(page_paths.into_iter().enumerate())
         .map(|(i, page_path)| {
             let ai_client = ai_client.clone();
+            let pages_done = &pages_done;
+            let thumbnail_path = thumbnail_paths.get(i);
             async move {
                 tracing::info!("Processing page {}", i + 1);
 
@@ -96,9 +131,37 @@ pub async fn process_pdf(
                 // Extract text using vision AI
                 let page_text = ai_client
                     .extract_text_from_image(VISION_MODEL, &base64_image, "image/png")
-                    .await?;
-
-                Ok::<_, async_graphql::Error>((i, page_text))
+                    .await;
+                metrics::observe_ingestion_page(page_text.is_ok());
+                let page_text = page_text?.content;
+
+                // A missing thumbnail shouldn't fail the extraction
+                let mut thumbnail = None;
+                if let (Some(prefix), Some(local_path)) = (thumbnail_prefix, thumbnail_path) {
+                    let path = format!("{}/{}.jpg", prefix, i + 1);
+                    let stored = match fs::read(local_path).await {
+                        Ok(data) => storage_client::upload_bytes(
+                            &config.supabase_url,
+                            &config.supabase_service_key,
+                            &path,
+                            data,
+                            "image/jpeg",
+                        )
+                        .await,
+                        Err(e) => Err(e.into()),
+                    };
+                    match stored {
+                        Ok(()) => thumbnail = Some(path),
+                        Err(e) => tracing::warn!("Failed to store page {} thumbnail: {}", i + 1, e),
+                    }
+                }
+
+                if let Some(progress) = progress {
+                    let done = pages_done.fetch_add(1, Ordering::Relaxed) + 1;
+                    progress.report(done, page_count as u32).await;
+                }
+
+                Ok::<_, async_graphql::Error>((i, page_text, thumbnail))
             }
         })
         .buffer_unordered(20) // Process up to 20 pages concurrently
@@ -107,33 +170,109 @@ pub async fn process_pdf(
 
     // Sort results by page index since they complete out of order
     results.sort_by_key(|res| match res {
-        Ok((i, _)) => *i,
+        Ok((i, _, _)) => *i,
         Err(_) => 0,
     });
 
+    let mut pages = Vec::new();
+    let mut thumbnails = Vec::new();
     let mut all_text = Vec::new();
     for res in results {
-        let (i, text) = res?;
+        let (i, text, thumbnail) = res?;
         all_text.push(format!("--- Page {} ---\n{}", i + 1, text));
+        pages.push(text);
+        thumbnails.push(thumbnail);
     }
 
     let extracted_text = all_text.join("\n\n");
 
     Ok(ProcessedDocument {
         extracted_text,
+        pages,
+        thumbnails,
         page_count,
     })
 }
 
 
 
-/// Process a document: download, extract text, update database
-pub async fn process_document(
+/// Render a small JPEG preview of every page (up to the page limit) into `dir/thumbnails`
+async fn render_thumbnails(
+    pdf_path: &Path,
+    config: &Config,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+    let thumbnail_dir = dir.join("thumbnails");
+    fs::create_dir(&thumbnail_dir).await?;
+
+    let size = THUMBNAIL_SIZE.to_string();
+    let last_page = config.max_pdf_pages.to_string();
+    let output = pdf_tools::run_limited(
+        "pdftoppm",
+        &[
+            "-jpeg",
+            "-scale-to",
+            &size,
+            "-l",
+            &last_page,
+            pdf_path.to_str().unwrap(),
+            thumbnail_dir.join("page").to_str().unwrap(),
+        ],
+        ToolLimits::from_config(config),
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err("pdftoppm failed to render thumbnails".into());
+    }
+
+    Ok(rendered_pages(&thumbnail_dir, "jpg")?)
+}
+
+/// Images rendered by pdftoppm into a directory, in page order
+fn rendered_pages(dir: &Path, extension: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == extension).unwrap_or(false))
+        .collect();
+
+    // pdftoppm pads page numbers, so names sort in page order
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Remove a direct upload that duplicates a document of its session, so its bytes
+/// don't count against the quotas (like the proxied upload path, which never stores one)
+async fn discard_duplicate(
     pool: &PgPool,
     config: &Config,
+    origin: &DocumentOrigin,
     document_id: Uuid,
     storage_path: &str,
+) {
+    if let Err(e) = doc_storage::delete_document(pool, origin.profile_id, document_id).await {
+        tracing::error!("Failed to delete duplicate document {}: {:?}", document_id, e);
+    }
+    let storage = StorageClient::new(config);
+    if let Err(e) = storage.delete(&format!("documents/{}", storage_path)).await {
+        tracing::warn!("Failed to delete duplicate upload {}: {:?}", storage_path, e);
+    }
+}
+
+/// Process a document: download, extract text, update database
+///
+/// When the caller still has the file on disk (e.g. a proxied upload),
+/// the job's `local_pdf` is used instead of downloading it again.
+pub async fn process_document(
+    pool: &PgPool,
+    config: &Config,
+    progress: &ProgressBus,
+    job: &ProcessingJob,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let document_id = job.document_id;
+    let storage_path = job.storage_path.as_str();
     tracing::info!("Processing document {}: {}", document_id, storage_path);
 
     // 1. Update status to processing
@@ -141,23 +280,107 @@ pub async fn process_document(
         .await
         .map_err(|e| format!("Failed to update status: {:?}", e))?;
 
-    // 2. Download file from storage
-    let pdf_data = storage_client::download_file(
-        &config.supabase_url,
-        &config.supabase_service_key,
-        storage_path,
-    )
-    .await?;
+    let origin = doc_storage::get_document_origin(pool, document_id)
+        .await
+        .map_err(|e| format!("Failed to load document: {:?}", e))?
+        .ok_or("Document not found")?;
 
-    tracing::info!("Downloaded {} bytes", pdf_data.len());
+    progress
+        .status(document_id, origin.session_id, ProcessingStatus::Processing)
+        .await;
+
+    // Proxied uploads are hashed on arrival, so identical files skip the download too
+    if let Some(hash) = origin.content_hash.as_ref().filter(|_| job.reuse_extraction) {
+        if reuse_extraction(pool, config, &origin, document_id, hash).await? {
+            link_problems(pool, config, &origin).await;
+            progress
+                .status(document_id, origin.session_id, ProcessingStatus::Completed)
+                .await;
+            return Ok(());
+        }
+    }
 
-    // 3. Save to temp file
+    // 2-3. Download file from storage to a temp file (unless already on disk)
     let temp_dir = TempDir::new()?;
-    let temp_pdf_path = temp_dir.path().join("document.pdf");
-    fs::write(&temp_pdf_path, &pdf_data).await?;
+    let pdf_path = match job.local_pdf.as_ref().map(|f| f.path()) {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let temp_pdf_path = temp_dir.path().join("document.pdf");
+            let size = storage_client::download_to_file(
+                &config.supabase_url,
+                &config.supabase_service_key,
+                storage_path,
+                &temp_pdf_path,
+            )
+            .await?;
+
+            tracing::info!("Downloaded {} bytes", size);
+            temp_pdf_path
+        }
+    };
+
+    // Refuse files that aren't safe to hand to the PDF tools or the model
+    let info = pdf_tools::validate_pdf(&pdf_path, config)
+        .await
+        .map_err(|e| Rejected(e.to_string()))?;
+    tracing::info!("Validated PDF with {} pages", info.page_count);
+
+    if !job.scanned {
+        let verdict = scanner::from_config(config).scan(&pdf_path).await?;
+        if let Some(reason) = scanner::rejection_message(&verdict) {
+            tracing::warn!("Scanner rejected document {}: {:?}", document_id, verdict);
+            return Err(Rejected(reason).into());
+        }
+    }
+
+    // Direct uploads are hashed once the file is on disk
+    if origin.content_hash.is_none() {
+        let hash = uploads::hash_file(&pdf_path).await?;
+
+        let duplicate = doc_storage::find_session_document_by_hash(
+            pool,
+            origin.profile_id,
+            origin.session_id,
+            &hash,
+            Some(document_id),
+        )
+        .await
+        .map_err(|e| format!("Duplicate check failed: {:?}", e))?;
+
+        // The client learns why from the document's failed progress event
+        if let Some(existing) = duplicate {
+            tracing::info!("Document {} duplicates {} in the same session", document_id, existing.id);
+            discard_duplicate(pool, config, &origin, document_id, storage_path).await;
+            let reason = format!("This file was already uploaded as \"{}\"", existing.file_name);
+            return Err(Rejected(reason).into());
+        }
+
+        // An identical upload may have been hashed since the check above
+        let stored = doc_storage::set_document_content_hash(pool, document_id, &hash)
+            .await
+            .map_err(|e| format!("Failed to store content hash: {:?}", e))?;
+        if !stored {
+            discard_duplicate(pool, config, &origin, document_id, storage_path).await;
+            return Err(Rejected(doc_storage::DUPLICATE_CONTENT.to_string()).into());
+        }
+
+        if reuse_extraction(pool, config, &origin, document_id, &hash).await? {
+            link_problems(pool, config, &origin).await;
+            progress
+                .status(document_id, origin.session_id, ProcessingStatus::Completed)
+                .await;
+            return Ok(());
+        }
+    }
 
     // 4. Process PDF (extract text using vision)
-    let result = process_pdf(&temp_pdf_path, config)
+    let reporter = PageReporter {
+        bus: progress,
+        document_id,
+        session_id: origin.session_id,
+    };
+    let thumbnail_prefix = uploads::page_images_prefix(origin.session_id, document_id);
+    let result = process_pdf(&pdf_path, config, Some(&reporter), Some(&thumbnail_prefix))
         .await
         .map_err(|e| format!("PDF processing failed: {:?}", e))?;
 
@@ -167,17 +390,137 @@ pub async fn process_document(
         result.page_count
     );
 
-    // 5. Update database with extracted content
-    doc_storage::update_document_content(
-        pool,
-        document_id,
-        &result.extracted_text,
-        ProcessingStatus::Completed,
-    )
-    .await
-    .map_err(|e| format!("Database update failed: {:?}", e))?;
+    // 5. Classify the document so prompts can label it (best effort)
+    let document_type =
+        match classification::classify_document(config, &origin.file_name, &result.extracted_text).await {
+            Ok(classification) => {
+                tracing::info!("Classified document {}: {:?}", document_id, classification);
+                doc_storage::set_document_classification(pool, document_id, &classification)
+                    .await
+                    .map_err(|e| format!("Failed to store classification: {:?}", e))?;
+                classification.document_type
+            }
+            Err(e) => {
+                tracing::warn!("Classification failed for {}: {:?}", document_id, e);
+                None
+            }
+        };
+
+    // 6. Update database with extracted content (per page and joined)
+    document_pages::save_extraction(pool, document_id, &result.pages, &result.thumbnails)
+        .await
+        .map_err(|e| format!("Database update failed: {:?}", e))?;
+
+    // 7. Build the problem bank from exams and problem sets (best effort)
+    if problems::has_problems(document_type) {
+        match problems::extract_problems(config, &result.pages).await {
+            Ok(extracted) => {
+                tracing::info!("Extracted {} problems from document {}", extracted.len(), document_id);
+                problem_storage::replace_document_problems(pool, document_id, &extracted)
+                    .await
+                    .map_err(|e| format!("Failed to store problems: {:?}", e))?;
+                link_problems(pool, config, &origin).await;
+            }
+            Err(e) => tracing::warn!("Problem extraction failed for {}: {:?}", document_id, e),
+        }
+    } else {
+        // A reprocessed document may no longer be classified as an exam
+        problem_storage::replace_document_problems(pool, document_id, &[])
+            .await
+            .map_err(|e| format!("Failed to clear problems: {:?}", e))?;
+    }
+
+    progress
+        .status(document_id, origin.session_id, ProcessingStatus::Completed)
+        .await;
 
     tracing::info!("Document processing complete: {}", document_id);
 
     Ok(())
 }
+
+/// Copy the extraction of an identical, already processed document owned by
+/// the same profile instead of running vision extraction again
+async fn reuse_extraction(
+    pool: &PgPool,
+    config: &Config,
+    origin: &DocumentOrigin,
+    document_id: Uuid,
+    content_hash: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let extraction = doc_storage::find_extraction_by_hash(pool, origin.profile_id, content_hash, document_id)
+        .await
+        .map_err(|e| format!("Extraction lookup failed: {:?}", e))?;
+
+    let Some(source_id) = extraction else {
+        return Ok(false);
+    };
+
+    let thumbnails = copy_thumbnails(pool, config, source_id, origin.session_id, document_id).await;
+
+    document_pages::copy_extraction(pool, source_id, document_id, &thumbnails)
+        .await
+        .map_err(|e| format!("Database update failed: {:?}", e))?;
+    problem_storage::copy_problems(pool, source_id, document_id)
+        .await
+        .map_err(|e| format!("Database update failed: {:?}", e))?;
+
+    tracing::info!("Reused extraction of {} for document {}", source_id, document_id);
+
+    Ok(true)
+}
+
+/// Copy the page thumbnails of one document into another's storage folder,
+/// returning the page number and path of each copy (best effort)
+async fn copy_thumbnails(
+    pool: &PgPool,
+    config: &Config,
+    source_id: Uuid,
+    session_id: Uuid,
+    document_id: Uuid,
+) -> Vec<(i32, String)> {
+    let sources = match document_pages::get_thumbnail_paths(pool, source_id).await {
+        Ok(sources) => sources,
+        Err(e) => {
+            tracing::warn!("Failed to load thumbnails of {}: {:?}", source_id, e);
+            return Vec::new();
+        }
+    };
+
+    let prefix = uploads::page_images_prefix(session_id, document_id);
+    stream::iter(sources)
+        .map(|(page_number, source)| {
+            let prefix = &prefix;
+            async move {
+                let extension = source.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("jpg");
+                let path = format!("{}/{}.{}", prefix, page_number, extension);
+                match storage_client::copy_object(
+                    &config.supabase_url,
+                    &config.supabase_service_key,
+                    &source,
+                    &path,
+                )
+                .await
+                {
+                    Ok(()) => Some((page_number, path)),
+                    Err(e) => {
+                        tracing::warn!("Failed to copy page {} thumbnail: {}", page_number, e);
+                        None
+                    }
+                }
+            }
+        })
+        .buffer_unordered(20)
+        .filter_map(|copied| async move { copied })
+        .collect()
+        .await
+}
+
+/// Link the session's new problems to its topics, if it has any yet (best effort)
+async fn link_problems(pool: &PgPool, config: &Config, origin: &DocumentOrigin) {
+    match problems::link_session_problems(pool, config, origin.profile_id, origin.session_id).await {
+        Ok(0) => {}
+        Ok(linked) => tracing::info!("Linked {} problems to topics", linked),
+        Err(e) => tracing::warn!("Failed to link problems for session {}: {:?}", origin.session_id, e),
+    }
+}
@@ -1,3 +1,9 @@
+pub mod classification;
 pub mod ingestion;
+pub mod pdf_tools;
+pub mod problems;
+pub mod progress;
+pub mod quota;
+pub mod scanner;
 pub mod storage_client;
-
+pub mod uploads;
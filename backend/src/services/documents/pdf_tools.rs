@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+use crate::config::Config;
+
+/// Error code for uploads that are not usable PDFs
+pub const INVALID_PDF: &str = "INVALID_PDF";
+
+/// Bytes that must start every PDF file
+const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// Why a file was refused before ingestion
+#[derive(Debug, thiserror::Error)]
+pub enum PdfRejection {
+    #[error("File is not a PDF")]
+    NotPdf,
+    #[error("Password-protected PDFs are not supported")]
+    Encrypted,
+    #[error("PDF has {pages} pages; the limit is {max}")]
+    TooManyPages { pages: u32, max: u32 },
+    #[error("PDF is corrupt or unreadable")]
+    Corrupt,
+    #[error("Timed out while reading the PDF")]
+    TimedOut,
+    #[error("Failed to inspect PDF: {0}")]
+    Tool(String),
+}
+
+/// Facts about a PDF read with `pdfinfo`
+#[derive(Debug, Clone, Copy)]
+pub struct PdfInfo {
+    pub page_count: u32,
+}
+
+/// Limits applied to the poppler tools (`pdfinfo`, `pdftoppm`)
+#[derive(Debug, Clone, Copy)]
+pub struct ToolLimits {
+    pub timeout: Duration,
+    pub max_memory_bytes: u64,
+}
+
+impl ToolLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            timeout: Duration::from_secs(config.pdf_tool_timeout_secs),
+            max_memory_bytes: config.pdf_tool_max_memory_mb * 1024 * 1024,
+        }
+    }
+}
+
+/// Whether the file starts with the PDF magic bytes
+pub async fn has_pdf_magic(path: &Path) -> std::io::Result<bool> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut header = [0u8; PDF_MAGIC.len()];
+    match file.read_exact(&mut header).await {
+        Ok(_) => Ok(header == PDF_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check that a file is a readable, unencrypted PDF within the page limit
+pub async fn validate_pdf(path: &Path, config: &Config) -> Result<PdfInfo, PdfRejection> {
+    if !has_pdf_magic(path)
+        .await
+        .map_err(|e| PdfRejection::Tool(e.to_string()))?
+    {
+        return Err(PdfRejection::NotPdf);
+    }
+
+    let path_arg = path.to_str().ok_or(PdfRejection::Corrupt)?;
+    let output = run_limited("pdfinfo", &[path_arg], ToolLimits::from_config(config)).await?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !output.status.success() {
+        // pdfinfo refuses encrypted files it can't open without a password
+        if stderr.contains("Incorrect password") {
+            return Err(PdfRejection::Encrypted);
+        }
+        return Err(PdfRejection::Corrupt);
+    }
+
+    let field = |name: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .map(|value| value.trim_start_matches(':').trim().to_string())
+    };
+
+    if field("Encrypted").is_some_and(|v| v.starts_with("yes")) {
+        return Err(PdfRejection::Encrypted);
+    }
+
+    let page_count = field("Pages")
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|pages| *pages > 0)
+        .ok_or(PdfRejection::Corrupt)?;
+
+    if page_count > config.max_pdf_pages {
+        return Err(PdfRejection::TooManyPages {
+            pages: page_count,
+            max: config.max_pdf_pages,
+        });
+    }
+
+    Ok(PdfInfo { page_count })
+}
+
+/// Run a PDF tool with a timeout and memory/CPU limits; the process is killed on timeout
+pub async fn run_limited(
+    program: &str,
+    args: &[&str],
+    limits: ToolLimits,
+) -> Result<Output, PdfRejection> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    #[cfg(unix)]
+    {
+        let memory = libc::rlimit {
+            rlim_cur: limits.max_memory_bytes as libc::rlim_t,
+            rlim_max: limits.max_memory_bytes as libc::rlim_t,
+        };
+        // CPU time can't usefully exceed the wall-clock timeout
+        let cpu_secs = limits.timeout.as_secs().max(1) as libc::rlim_t;
+        let cpu = libc::rlimit {
+            rlim_cur: cpu_secs,
+            rlim_max: cpu_secs,
+        };
+
+        // SAFETY: only async-signal-safe calls (setrlimit) run between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setrlimit(libc::RLIMIT_AS, &memory) != 0
+                    || libc::setrlimit(libc::RLIMIT_CPU, &cpu) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let child = command
+        .spawn()
+        .map_err(|e| PdfRejection::Tool(format!("failed to run {}: {}", program, e)))?;
+
+    match tokio::time::timeout(limits.timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|e| PdfRejection::Tool(format!("{} failed: {}", program, e))),
+        // Dropping the future kills the child (kill_on_drop)
+        Err(_) => Err(PdfRejection::TimedOut),
+    }
+}
@@ -0,0 +1,214 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::templates::{self, TemplateName, TemplateVars};
+use crate::services::messages::ai_client::{AiTask, OpenRouterClient};
+use crate::storage::documents::DocumentType;
+use crate::storage::problems::{self, NewProblem};
+use crate::storage::topics;
+
+const PROBLEMS_MODEL: &str = "google/gemini-2.5-flash";
+
+/// Upper bound on the document text sent for extraction
+const MAX_EXTRACTION_CHARS: usize = 120_000;
+
+/// Problem statements are shortened to this length when linking to topics
+const MAX_LINK_STATEMENT_CHARS: usize = 600;
+
+/// Most problems sent to the model in one linking request
+const MAX_LINK_PROBLEMS: usize = 40;
+
+#[derive(Debug, Deserialize)]
+struct ExtractedProblems {
+    problems: Vec<ExtractedProblem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExtractedProblem {
+    label: Option<String>,
+    statement: String,
+    #[serde(default)]
+    sub_parts: Vec<String>,
+    solution: Option<String>,
+    points: Option<f64>,
+    page: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProblemLinks {
+    links: Vec<ProblemLink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProblemLink {
+    problem: usize,
+    topic: Option<usize>,
+}
+
+/// Whether problems should be extracted from documents of this type
+pub fn has_problems(document_type: Option<DocumentType>) -> bool {
+    matches!(
+        document_type,
+        Some(DocumentType::PastExam) | Some(DocumentType::ProblemSet)
+    )
+}
+
+/// Split a document's pages into individual problems
+pub async fn extract_problems(
+    config: &Config,
+    pages: &[String],
+) -> Result<Vec<NewProblem>, async_graphql::Error> {
+    let content = pages
+        .iter()
+        .enumerate()
+        .map(|(i, text)| format!("[Page {}]\n{}", i + 1, text))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let total_chars = content.chars().count();
+    if total_chars > MAX_EXTRACTION_CHARS {
+        tracing::warn!(
+            "Document text has {} chars, only the first {} are searched for problems",
+            total_chars,
+            MAX_EXTRACTION_CHARS
+        );
+    }
+    let content: String = content.chars().take(MAX_EXTRACTION_CHARS).collect();
+
+    let prompt = templates::render(
+        TemplateName::ExtractProblems,
+        None,
+        &TemplateVars::new().set("content", content),
+    )?
+    .text;
+
+    let ai_client = OpenRouterClient::new(config);
+    let response = ai_client
+        .chat(
+            AiTask::ProblemExtraction,
+            PROBLEMS_MODEL,
+            "You extract exam problems from documents. Output valid JSON only.",
+            &prompt,
+        )
+        .await?
+        .content;
+
+    let extracted: ExtractedProblems = parse_json(&response)?;
+    let page_count = pages.len() as i32;
+
+    Ok(extracted
+        .problems
+        .into_iter()
+        .filter(|p| !p.statement.trim().is_empty())
+        .map(|p| NewProblem {
+            label: p.label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty()),
+            statement: p.statement.trim().to_string(),
+            sub_parts: p
+                .sub_parts
+                .into_iter()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            solution: p.solution.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
+            points: p.points.filter(|points| *points > 0.0),
+            page_number: p.page.filter(|page| (1..=page_count).contains(page)),
+        })
+        .collect())
+}
+
+/// Link the session's problems that were never checked against its topics
+///
+/// Does nothing until the session has topics. Problems are sent in batches of
+/// `MAX_LINK_PROBLEMS`, and each one sent is recorded as checked even when no
+/// topic fits. Returns the number of problems that were linked.
+pub async fn link_session_problems(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    session_id: Uuid,
+) -> Result<usize, async_graphql::Error> {
+    let session_topics = topics::get_session_topics(pool, profile_id, session_id).await?;
+    if session_topics.is_empty() {
+        return Ok(0);
+    }
+
+    let topic_list = session_topics
+        .iter()
+        .enumerate()
+        .map(|(i, t)| match &t.description {
+            Some(description) => format!("{}. {}: {}", i + 1, t.title, description),
+            None => format!("{}. {}", i + 1, t.title),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let ai_client = OpenRouterClient::new(config);
+    let mut linked = 0;
+    loop {
+        let unchecked =
+            problems::get_unchecked_problems(pool, profile_id, session_id, MAX_LINK_PROBLEMS as i64).await?;
+        if unchecked.is_empty() {
+            return Ok(linked);
+        }
+
+        let problem_list = unchecked
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let statement: String = p.statement.chars().take(MAX_LINK_STATEMENT_CHARS).collect();
+                format!("{}. {}", i + 1, statement)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let prompt = templates::render(
+            TemplateName::LinkProblems,
+            None,
+            &TemplateVars::new().set("topics", topic_list.as_str()).set("problems", problem_list),
+        )?
+        .text;
+
+        let response = ai_client
+            .chat(
+                AiTask::ProblemExtraction,
+                PROBLEMS_MODEL,
+                "You match exam problems to study topics. Output valid JSON only.",
+                &prompt,
+            )
+            .await?
+            .content;
+
+        let parsed: ProblemLinks = parse_json(&response)?;
+
+        // The model answers with 1-based list positions; ignore anything out of range
+        let mut topic_ids: Vec<Option<Uuid>> = vec![None; unchecked.len()];
+        for link in parsed.links {
+            let Some(index) = link.problem.checked_sub(1).filter(|i| *i < unchecked.len()) else {
+                continue;
+            };
+            if let Some(topic) = link.topic.and_then(|t| session_topics.get(t.checked_sub(1)?)) {
+                topic_ids[index] = Some(topic.id);
+            }
+        }
+
+        linked += topic_ids.iter().flatten().count();
+        let links: Vec<(Uuid, Option<Uuid>)> = unchecked.iter().map(|p| p.id).zip(topic_ids).collect();
+        problems::set_problem_topics(pool, &links).await?;
+    }
+}
+
+/// Parse the model's JSON answer (tolerating a markdown code block around it)
+fn parse_json<T: DeserializeOwned>(response: &str) -> Result<T, async_graphql::Error> {
+    let json_str = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => response.trim(),
+    };
+
+    serde_json::from_str(json_str).map_err(|e| {
+        tracing::warn!("Failed to parse problems response: {}\nResponse was: {}", e, response);
+        async_graphql::Error::new(format!("Failed to parse problems response: {}", e))
+    })
+}
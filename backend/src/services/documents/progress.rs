@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::storage::documents::ProcessingStatus;
+
+/// Postgres channel carrying progress events between instances
+const CHANNEL: &str = "document_progress";
+
+/// Events buffered per subscriber before it starts lagging
+const BUS_CAPACITY: usize = 256;
+
+/// A status transition or page-level progress update for one document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressEvent {
+    pub document_id: Uuid,
+    pub session_id: Uuid,
+    pub status: ProcessingStatus,
+    /// Pages extracted so far (set while processing)
+    pub pages_processed: Option<u32>,
+    pub page_count: Option<u32>,
+    /// Failure reason shown to the user
+    pub error: Option<String>,
+}
+
+/// Envelope sent over NOTIFY so instances can skip their own events
+#[derive(Serialize, Deserialize)]
+struct Notification {
+    origin: Uuid,
+    event: ProgressEvent,
+}
+
+/// In-process fan-out of document progress, bridged across instances with LISTEN/NOTIFY
+#[derive(Clone)]
+pub struct ProgressBus {
+    sender: broadcast::Sender<ProgressEvent>,
+    pool: PgPool,
+    instance_id: Uuid,
+}
+
+impl ProgressBus {
+    pub fn new(pool: PgPool) -> Self {
+        let (sender, _) = broadcast::channel(BUS_CAPACITY);
+        Self {
+            sender,
+            pool,
+            instance_id: Uuid::new_v4(),
+        }
+    }
+
+    /// Receive every event published from now on (callers filter by session)
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Deliver an event to local subscribers and notify other instances
+    pub async fn publish(&self, event: ProgressEvent) {
+        let payload = serde_json::to_string(&Notification {
+            origin: self.instance_id,
+            event: event.clone(),
+        });
+
+        // No receivers is fine: nobody is watching this session
+        let _ = self.sender.send(event);
+
+        match payload {
+            Ok(payload) => {
+                if let Err(e) = sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(CHANNEL)
+                    .bind(payload)
+                    .execute(&self.pool)
+                    .await
+                {
+                    tracing::warn!("Failed to notify document progress: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to encode document progress: {}", e),
+        }
+    }
+
+    /// Publish a status transition
+    pub async fn status(&self, document_id: Uuid, session_id: Uuid, status: ProcessingStatus) {
+        self.publish(ProgressEvent {
+            document_id,
+            session_id,
+            status,
+            pages_processed: None,
+            page_count: None,
+            error: None,
+        })
+        .await;
+    }
+
+    /// Publish page-level progress of a document being processed
+    pub async fn page(&self, document_id: Uuid, session_id: Uuid, processed: u32, total: u32) {
+        self.publish(ProgressEvent {
+            document_id,
+            session_id,
+            status: ProcessingStatus::Processing,
+            pages_processed: Some(processed),
+            page_count: Some(total),
+            error: None,
+        })
+        .await;
+    }
+
+    /// Publish a failure with the reason shown to the user
+    pub async fn failed(&self, document_id: Uuid, session_id: Uuid, reason: &str) {
+        self.publish(ProgressEvent {
+            document_id,
+            session_id,
+            status: ProcessingStatus::Failed,
+            pages_processed: None,
+            page_count: None,
+            error: Some(reason.to_string()),
+        })
+        .await;
+    }
+
+    /// Forward events published by other instances to local subscribers
+    /// (`PgListener` reconnects on its own if the connection drops)
+    pub fn spawn_listener(&self) {
+        let bus = self.clone();
+
+        tokio::spawn(async move {
+            let mut listener = match PgListener::connect_with(&bus.pool).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!("Failed to start document progress listener: {}", e);
+                    return;
+                }
+            };
+
+            if let Err(e) = listener.listen(CHANNEL).await {
+                tracing::error!("Failed to LISTEN on {}: {}", CHANNEL, e);
+                return;
+            }
+
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        match serde_json::from_str::<Notification>(notification.payload()) {
+                            Ok(n) if n.origin != bus.instance_id => {
+                                let _ = bus.sender.send(n.event);
+                            }
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!("Invalid document progress payload: {}", e),
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("Document progress listener error: {}", e);
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Reports page progress for one document while it is being extracted
+pub struct PageReporter<'a> {
+    pub bus: &'a ProgressBus,
+    pub document_id: Uuid,
+    pub session_id: Uuid,
+}
+
+impl PageReporter<'_> {
+    pub async fn report(&self, processed: u32, total: u32) {
+        self.bus
+            .page(self.document_id, self.session_id, processed, total)
+            .await;
+    }
+}
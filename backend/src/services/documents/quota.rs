@@ -0,0 +1,170 @@
+use async_graphql::ErrorExtensions;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::config::{Config, StorageQuota};
+use crate::storage::documents::{DocumentRow, NewDocument};
+use crate::storage::{documents, profiles};
+
+/// Error code attached to quota rejections (GraphQL `extensions.code`, REST `code`)
+pub const QUOTA_EXCEEDED: &str = "QUOTA_EXCEEDED";
+
+/// Current storage usage of a profile against its tier's quota
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub tier: String,
+    pub quota: StorageQuota,
+    pub profile_bytes: i64,
+    pub session_bytes: i64,
+}
+
+/// Which limit an upload would break
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaScope {
+    File,
+    Session,
+    Profile,
+}
+
+impl QuotaScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaScope::File => "FILE",
+            QuotaScope::Session => "SESSION",
+            QuotaScope::Profile => "PROFILE",
+        }
+    }
+}
+
+/// An upload rejected by a storage quota
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded {
+    pub scope: QuotaScope,
+    pub limit_bytes: i64,
+    pub used_bytes: i64,
+    pub file_size: i64,
+}
+
+impl QuotaExceeded {
+    pub fn message(&self) -> String {
+        let limit_mb = self.limit_bytes / (1024 * 1024);
+        match self.scope {
+            QuotaScope::File => format!("File size exceeds {}MB limit", limit_mb),
+            QuotaScope::Session => format!("Session storage limit of {}MB reached", limit_mb),
+            QuotaScope::Profile => format!("Account storage limit of {}MB reached", limit_mb),
+        }
+    }
+}
+
+impl From<QuotaExceeded> for async_graphql::Error {
+    fn from(e: QuotaExceeded) -> Self {
+        async_graphql::Error::new(e.message()).extend_with(|_, ext| {
+            ext.set("code", QUOTA_EXCEEDED);
+            ext.set("scope", e.scope.as_str());
+            ext.set("limitBytes", e.limit_bytes);
+            ext.set("usedBytes", e.used_bytes);
+            ext.set("fileSize", e.file_size);
+        })
+    }
+}
+
+/// Load a profile's tier and current usage (session usage is 0 without a session)
+pub async fn get_usage(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    session_id: Option<Uuid>,
+) -> Result<Usage, async_graphql::Error> {
+    let tier = profiles::get_profile_tier(pool, profile_id).await?;
+    let (profile_bytes, session_bytes) =
+        documents::get_storage_usage(pool, profile_id, session_id).await?;
+
+    Ok(Usage {
+        quota: config.storage_quota(&tier),
+        tier,
+        profile_bytes,
+        session_bytes,
+    })
+}
+
+impl Usage {
+    /// Check whether a file of `file_size` bytes still fits in every quota
+    pub fn check(&self, file_size: i64) -> Result<(), QuotaExceeded> {
+        let limits = [
+            (QuotaScope::File, self.quota.max_file_bytes, 0),
+            (QuotaScope::Session, self.quota.max_session_bytes, self.session_bytes),
+            (QuotaScope::Profile, self.quota.max_profile_bytes, self.profile_bytes),
+        ];
+
+        for (scope, limit_bytes, used_bytes) in limits {
+            if used_bytes + file_size > limit_bytes {
+                return Err(QuotaExceeded {
+                    scope,
+                    limit_bytes,
+                    used_bytes,
+                    file_size,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reject an upload to a session before it reaches storage if it would exceed a quota
+/// (the document is recorded with `create_document`, which checks again)
+pub async fn check_upload(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    session_id: Uuid,
+    file_size: i64,
+) -> Result<(), async_graphql::Error> {
+    get_usage(pool, config, profile_id, Some(session_id))
+        .await?
+        .check(file_size)
+        .map_err(Into::into)
+}
+
+/// Reject sending images that would exceed a quota
+///
+/// Unlike documents this is not atomic with recording the attachments: concurrent
+/// sends can overshoot a quota by at most their own images (4 × 10MB per message).
+pub async fn check_attachments(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    session_id: Uuid,
+    sizes: &[i64],
+) -> Result<(), async_graphql::Error> {
+    let mut usage = get_usage(pool, config, profile_id, Some(session_id)).await?;
+    for &size in sizes {
+        usage.check(size)?;
+        usage.profile_bytes += size;
+        usage.session_bytes += size;
+    }
+
+    Ok(())
+}
+
+/// Record an uploaded document if it still fits every quota
+///
+/// The check and the insert happen atomically, so this is the authoritative
+/// check; `check_upload` only rejects early before anything is uploaded.
+pub async fn create_document(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    document: &NewDocument<'_>,
+) -> Result<Result<DocumentRow, QuotaExceeded>, async_graphql::Error> {
+    documents::create_document_within_quota(pool, profile_id, document, |tier, profile_bytes, session_bytes| {
+        Usage {
+            tier: tier.to_string(),
+            quota: config.storage_quota(tier),
+            profile_bytes,
+            session_bytes,
+        }
+        .check(document.file_size)
+    })
+    .await
+}
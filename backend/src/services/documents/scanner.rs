@@ -0,0 +1,151 @@
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::Config;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcome of scanning a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Infected, with the signature name reported by the scanner
+    Infected(String),
+    /// Larger than the scanner accepts, so it could not be checked
+    TooLarge,
+}
+
+/// A malware scanner run on every upload before ingestion
+#[async_trait]
+pub trait MalwareScanner: Send + Sync {
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict, String>;
+}
+
+/// Default scanner: accepts everything
+pub struct NoopScanner;
+
+#[async_trait]
+impl MalwareScanner for NoopScanner {
+    async fn scan(&self, _path: &Path) -> Result<ScanVerdict, String> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// Scanner backed by a clamd daemon (`INSTREAM` command)
+///
+/// `address` is either a Unix socket path (`/run/clamav/clamd.ctl`) or `host:port`.
+pub struct ClamAvScanner {
+    address: String,
+    /// clamd's `StreamMaxLength`: longer streams are refused
+    stream_max_bytes: u64,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: String, stream_max_bytes: u64) -> Self {
+        Self { address, stream_max_bytes }
+    }
+}
+
+#[async_trait]
+impl MalwareScanner for ClamAvScanner {
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict, String> {
+        let size = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| format!("Failed to read file size: {}", e))?
+            .len();
+        if size > self.stream_max_bytes {
+            return Ok(ScanVerdict::TooLarge);
+        }
+
+        let scan = async {
+            #[cfg(unix)]
+            if self.address.starts_with('/') {
+                let stream = tokio::net::UnixStream::connect(&self.address)
+                    .await
+                    .map_err(|e| format!("Failed to connect to clamd: {}", e))?;
+                return instream(stream, path).await;
+            }
+
+            let stream = tokio::net::TcpStream::connect(&self.address)
+                .await
+                .map_err(|e| format!("Failed to connect to clamd: {}", e))?;
+            instream(stream, path).await
+        };
+
+        tokio::time::timeout(SCAN_TIMEOUT, scan)
+            .await
+            .map_err(|_| "clamd scan timed out".to_string())?
+    }
+}
+
+/// Stream a file to clamd as length-prefixed chunks and parse the reply
+async fn instream<S>(mut stream: S, path: &Path) -> Result<ScanVerdict, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_error = |e: std::io::Error| format!("clamd I/O error: {}", e);
+
+    let mut file = tokio::fs::File::open(path).await.map_err(io_error)?;
+    stream.write_all(b"zINSTREAM\0").await.map_err(io_error)?;
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await.map_err(io_error)?;
+        if read == 0 {
+            break;
+        }
+        stream
+            .write_all(&(read as u32).to_be_bytes())
+            .await
+            .map_err(io_error)?;
+        stream.write_all(&buffer[..read]).await.map_err(io_error)?;
+    }
+
+    // A zero-length chunk ends the stream
+    stream.write_all(&0u32.to_be_bytes()).await.map_err(io_error)?;
+    stream.flush().await.map_err(io_error)?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.map_err(io_error)?;
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+
+    // Replies look like "stream: OK" or "stream: Eicar-Signature FOUND"
+    let status = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if status == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = status.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else if status.starts_with("INSTREAM size limit exceeded") {
+        // clamd's StreamMaxLength is lower than configured
+        Ok(ScanVerdict::TooLarge)
+    } else {
+        Err(format!("Unexpected clamd reply: {}", reply))
+    }
+}
+
+/// Build the scanner selected by configuration (`CLAMAV_ADDRESS`), defaulting to no-op
+pub fn from_config(config: &Config) -> Box<dyn MalwareScanner> {
+    match &config.clamav_address {
+        Some(address) => Box::new(ClamAvScanner::new(address.clone(), config.clamav_stream_max_bytes)),
+        None => Box::new(NoopScanner),
+    }
+}
+
+/// Largest file the configured scanner accepts (`None` when scanning is off)
+pub fn max_scannable_size(config: &Config) -> Option<u64> {
+    config.clamav_address.as_ref().map(|_| config.clamav_stream_max_bytes)
+}
+
+/// Message for a file the scanner rejected
+pub fn rejection_message(verdict: &ScanVerdict) -> Option<String> {
+    match verdict {
+        ScanVerdict::Clean => None,
+        ScanVerdict::Infected(_) => Some("File was flagged by the malware scanner".to_string()),
+        ScanVerdict::TooLarge => Some("File is too large to be scanned for malware".to_string()),
+    }
+}
@@ -1,16 +1,52 @@
-use reqwest::Client;
+use std::path::Path;
+
+use futures::StreamExt;
+use reqwest::{Body, Client};
 use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
 
 use crate::config::Config;
 
 const BUCKET_NAME: &str = "documents";
 
+/// Most entries storage lists or deletes per request
+const LIST_PAGE_SIZE: usize = 1000;
+
 #[derive(Debug, Deserialize)]
 struct SignedUrlResponse {
     #[serde(rename = "signedURL")]
     signed_url: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct BatchSignedUrl {
+    #[serde(rename = "signedURL")]
+    signed_url: Option<String>,
+}
+
+/// An entry of a folder listing (`id` is null for subfolders)
+#[derive(Debug, Deserialize)]
+struct ListedObject {
+    name: String,
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedUploadUrlResponse {
+    url: String,
+    token: Option<String>,
+}
+
+/// A signed URL a client can upload a single object to without credentials
+#[derive(Debug, Clone)]
+pub struct SignedUpload {
+    /// Absolute URL accepting a PUT with the file body
+    pub url: String,
+    /// Upload token (also accepted as `x-signature` by the resumable endpoint)
+    pub token: String,
+}
+
 /// Client for interacting with Supabase Storage
 pub struct StorageClient {
     client: Client,
@@ -84,21 +120,55 @@ impl StorageClient {
 
 // ============ Standalone Functions ============
 
-/// Upload a file to Supabase Storage
+/// Upload a local file to Supabase Storage, streaming it from disk
 pub async fn upload_file(
     supabase_url: &str,
     service_key: &str,
     file_path: &str,
-    data: &[u8],
+    local_path: &Path,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let client = Client::new();
     let url = format!("{}/storage/v1/object/{}/{}", supabase_url, BUCKET_NAME, file_path);
 
+    let file = tokio::fs::File::open(local_path).await?;
+    let size = file.metadata().await?.len();
+
     let response = client
         .post(&url)
         .header("Authorization", format!("Bearer {}", service_key))
         .header("Content-Type", "application/pdf")
-        .body(data.to_vec())
+        .header("Content-Length", size)
+        .body(Body::wrap_stream(ReaderStream::new(file)))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Storage upload failed ({}): {}", status, error_text).into());
+    }
+
+    Ok(())
+}
+
+/// Upload an in-memory object (e.g. a rendered page image) to Supabase Storage
+pub async fn upload_bytes(
+    supabase_url: &str,
+    service_key: &str,
+    file_path: &str,
+    data: Vec<u8>,
+    content_type: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let url = format!("{}/storage/v1/object/{}/{}", supabase_url, BUCKET_NAME, file_path);
+
+    // Reprocessing overwrites previously rendered pages
+    let response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", service_key))
+        .header("Content-Type", content_type)
+        .header("x-upsert", "true")
+        .body(data)
         .send()
         .await?;
 
@@ -111,12 +181,13 @@ pub async fn upload_file(
     Ok(())
 }
 
-/// Download a file from Supabase Storage (standalone version)
-pub async fn download_file(
+/// Download a file from Supabase Storage straight to disk, returning its size
+pub async fn download_to_file(
     supabase_url: &str,
     service_key: &str,
     file_path: &str,
-) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    local_path: &Path,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
     let client = Client::new();
     let url = format!("{}/storage/v1/object/{}/{}", supabase_url, BUCKET_NAME, file_path);
 
@@ -132,11 +203,126 @@ pub async fn download_file(
         return Err(format!("Storage download failed ({}): {}", status, error_text).into());
     }
 
-    let bytes = response.bytes().await?;
-    Ok(bytes.to_vec())
+    let mut file = tokio::fs::File::create(local_path).await?;
+    let mut written = 0u64;
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    Ok(written)
+}
+
+/// Copy a stored object to another path in the same bucket
+pub async fn copy_object(
+    supabase_url: &str,
+    service_key: &str,
+    from_path: &str,
+    to_path: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let url = format!("{}/storage/v1/object/copy", supabase_url);
+
+    let response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", service_key))
+        .json(&serde_json::json!({
+            "bucketId": BUCKET_NAME,
+            "sourceKey": from_path,
+            "destinationKey": to_path,
+        }))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Storage copy failed ({}): {}", status, error_text).into());
+    }
+
+    Ok(())
 }
 
+/// Delete every object under a folder, including its subfolders, returning how many were deleted
+pub async fn delete_folder(
+    supabase_url: &str,
+    service_key: &str,
+    folder: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let list_url = format!("{}/storage/v1/object/list/{}", supabase_url, BUCKET_NAME);
+
+    // Listing is one level deep: entries without an id are subfolders
+    let mut objects = Vec::new();
+    let mut folders = vec![folder.trim_end_matches('/').to_string()];
+    while let Some(prefix) = folders.pop() {
+        let mut offset = 0;
+        loop {
+            let response = client
+                .post(&list_url)
+                .header("Authorization", format!("Bearer {}", service_key))
+                .json(&serde_json::json!({
+                    "prefix": prefix,
+                    "limit": LIST_PAGE_SIZE,
+                    "offset": offset,
+                }))
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                return Err(format!("Storage list failed ({}): {}", status, error_text).into());
+            }
 
+            let entries: Vec<ListedObject> = response.json().await?;
+            let count = entries.len();
+            for entry in entries {
+                let path = format!("{}/{}", prefix, entry.name);
+                match entry.id {
+                    Some(_) => objects.push(path),
+                    None => folders.push(path),
+                }
+            }
+
+            if count < LIST_PAGE_SIZE {
+                break;
+            }
+            offset += count;
+        }
+    }
+
+    delete_objects(supabase_url, service_key, &objects).await
+}
+
+/// Delete several objects at once, returning how many were requested
+pub async fn delete_objects(
+    supabase_url: &str,
+    service_key: &str,
+    objects: &[String],
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let delete_url = format!("{}/storage/v1/object/{}", supabase_url, BUCKET_NAME);
+    for chunk in objects.chunks(LIST_PAGE_SIZE) {
+        let response = client
+            .delete(&delete_url)
+            .header("Authorization", format!("Bearer {}", service_key))
+            .json(&serde_json::json!({ "prefixes": chunk }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(format!("Storage delete failed ({}): {}", status, error_text).into());
+        }
+    }
+
+    Ok(objects.len())
+}
 
 /// Create a signed URL for viewing a file (expires in 1 hour)
 pub async fn create_signed_url(
@@ -174,3 +360,129 @@ pub async fn create_signed_url(
     
     Ok(full_url)
 }
+
+/// Create signed URLs for several files in one request (same order as `file_paths`;
+/// `None` for files storage could not sign)
+pub async fn create_signed_urls(
+    supabase_url: &str,
+    service_key: &str,
+    file_paths: &[String],
+    expires_in_seconds: u64,
+) -> Result<Vec<Option<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    if file_paths.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let client = Client::new();
+    let url = format!("{}/storage/v1/object/sign/{}", supabase_url, BUCKET_NAME);
+
+    let response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", service_key))
+        .header("Content-Type", "application/json")
+        .json(&serde_json::json!({
+            "expiresIn": expires_in_seconds,
+            "paths": file_paths,
+        }))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to create signed URLs ({}): {}", status, error_text).into());
+    }
+
+    let signed: Vec<BatchSignedUrl> = response.json().await?;
+
+    Ok(signed
+        .into_iter()
+        .map(|s| {
+            s.signed_url
+                .map(|relative| format!("{}/storage/v1{}", supabase_url, relative))
+        })
+        .collect())
+}
+
+/// Create a signed URL the client can upload a file to directly (valid for 2 hours)
+pub async fn create_signed_upload_url(
+    supabase_url: &str,
+    service_key: &str,
+    file_path: &str,
+) -> Result<SignedUpload, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let url = format!(
+        "{}/storage/v1/object/upload/sign/{}/{}",
+        supabase_url, BUCKET_NAME, file_path
+    );
+
+    let response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", service_key))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to create signed upload URL ({}): {}", status, error_text).into());
+    }
+
+    let signed: SignedUploadUrlResponse = response.json().await?;
+
+    // Older Storage versions only return the token inside the URL query string
+    let token = match signed.token {
+        Some(token) => token,
+        None => signed
+            .url
+            .split_once("token=")
+            .map(|(_, token)| token.split('&').next().unwrap_or(token).to_string())
+            .ok_or("Signed upload URL has no token")?,
+    };
+
+    Ok(SignedUpload {
+        url: format!("{}/storage/v1{}", supabase_url, signed.url),
+        token,
+    })
+}
+
+/// URL of the TUS resumable upload endpoint (authorize with `x-signature: <token>`)
+pub fn resumable_upload_endpoint(supabase_url: &str) -> String {
+    format!("{}/storage/v1/upload/resumable", supabase_url)
+}
+
+/// Size in bytes of a stored file, or `None` if it does not exist
+pub async fn get_file_size(
+    supabase_url: &str,
+    service_key: &str,
+    file_path: &str,
+) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = Client::new();
+    let url = format!("{}/storage/v1/object/{}/{}", supabase_url, BUCKET_NAME, file_path);
+
+    let response = client
+        .head(&url)
+        .header("Authorization", format!("Bearer {}", service_key))
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND
+        || response.status() == reqwest::StatusCode::BAD_REQUEST
+    {
+        // Storage answers 400 for missing objects on some versions
+        return Ok(None);
+    }
+
+    if !response.status().is_success() {
+        return Err(format!("Storage HEAD failed ({})", response.status()).into());
+    }
+
+    let size = response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or("Storage did not report a content length")?;
+
+    Ok(Some(size))
+}
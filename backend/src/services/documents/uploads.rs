@@ -0,0 +1,166 @@
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncReadExt;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::metrics;
+use crate::services::documents::{ingestion, scanner};
+use crate::services::documents::progress::ProgressBus;
+use crate::storage::documents;
+
+pub const MAX_FILE_SIZE: usize = 50 * 1024 * 1024; // 50MB
+
+/// Largest file accepted: `MAX_FILE_SIZE`, or less if the malware scanner can't take that much
+pub fn max_file_size(config: &Config) -> usize {
+    match scanner::max_scannable_size(config) {
+        Some(limit) => MAX_FILE_SIZE.min(limit as usize),
+        None => MAX_FILE_SIZE,
+    }
+}
+
+/// Message for a file over `max_file_size`
+pub fn file_too_large_message(config: &Config) -> String {
+    format!("File size exceeds {}MB limit", max_file_size(config) / (1024 * 1024))
+}
+
+/// Request body limit for proxied uploads: the file plus multipart overhead
+pub const MAX_UPLOAD_BODY_SIZE: usize = MAX_FILE_SIZE + 1024 * 1024;
+
+/// Error code for an upload whose content already exists in the session
+pub const DUPLICATE_DOCUMENT: &str = "DUPLICATE_DOCUMENT";
+
+/// Hex-encoded SHA-256 of a file on disk, read in chunks
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Whether a file name looks like a PDF
+pub fn is_pdf_file_name(file_name: &str) -> bool {
+    file_name.to_lowercase().ends_with(".pdf")
+}
+
+/// Build a unique storage path for a new upload: `{session_id}/{uuid}-{sanitized name}`
+pub fn storage_path_for(session_id: Uuid, file_name: &str) -> String {
+    let safe_name = file_name.replace(|c: char| !c.is_alphanumeric() && c != '.' && c != '-', "_");
+    format!("{}/{}-{}", session_id, Uuid::new_v4(), safe_name)
+}
+
+/// Storage folder for the rendered page images of a document
+pub fn page_images_prefix(session_id: Uuid, document_id: Uuid) -> String {
+    format!("{}/pages/{}", session_id, document_id)
+}
+
+/// Whether a storage path has the exact shape `storage_path_for` gives uploads to
+/// the session (so it can't name a page image, an attachment or another session's file)
+pub fn is_upload_path(file_path: &str, session_id: Uuid) -> bool {
+    let Some(name) = file_path.strip_prefix(&format!("{}/", session_id)) else {
+        return false;
+    };
+    let Some((id, safe_name)) = name.split_at_checked(36) else {
+        return false;
+    };
+    Uuid::parse_str(id).is_ok()
+        && safe_name
+            .strip_prefix('-')
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-' || c == '_'))
+        && !name.contains("..")
+}
+
+/// A document queued for text extraction
+pub struct ProcessingJob {
+    pub document_id: Uuid,
+    pub session_id: Uuid,
+    /// Path in the documents bucket
+    pub storage_path: String,
+    /// Local copy of the file, used instead of downloading and removed once processing ends
+    pub local_pdf: Option<NamedTempFile>,
+    /// Copy the extraction of an identical document instead of extracting again
+    pub reuse_extraction: bool,
+    /// The file was already scanned for malware before it was stored
+    pub scanned: bool,
+}
+
+impl ProcessingJob {
+    /// Job for a freshly uploaded document
+    pub fn new(document_id: Uuid, session_id: Uuid, storage_path: String) -> Self {
+        Self {
+            document_id,
+            session_id,
+            storage_path,
+            local_pdf: None,
+            reuse_extraction: true,
+            scanned: false,
+        }
+    }
+}
+
+/// Spawn the background task that extracts text from an uploaded document
+pub fn spawn_processing(pool: PgPool, config: Config, progress: ProgressBus, job: ProcessingJob) {
+    let document_id = job.document_id;
+    let session_id = job.session_id;
+    let task_guard = metrics::BackgroundTaskGuard::new("document_processing");
+
+    tokio::spawn(async move {
+        let _task_guard = task_guard;
+        tracing::info!("Starting document processing for: {}", document_id);
+
+        match ingestion::process_document(&pool, &config, &progress, &job).await {
+            Ok(_) => {
+                tracing::info!("Document processing completed: {}", document_id);
+            }
+            Err(e) => {
+                tracing::error!("Document processing failed for {}: {:?}", document_id, e);
+                // Only rejections carry a message meant for the user
+                let reason = e
+                    .downcast_ref::<ingestion::Rejected>()
+                    .map(|r| r.0.as_str())
+                    .unwrap_or("Text extraction failed");
+                let _ = documents::mark_document_failed(&pool, document_id, reason).await;
+                progress.failed(document_id, session_id, reason).await;
+            }
+        }
+    }.instrument(tracing::info_span!("process_document", %document_id)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_issued_upload_paths() {
+        let session_id = Uuid::new_v4();
+        let path = storage_path_for(session_id, "Week 1 (notes).pdf");
+
+        assert!(is_upload_path(&path, session_id));
+        assert!(!is_upload_path(&path, Uuid::new_v4()));
+    }
+
+    #[test]
+    fn rejects_other_objects_of_the_session() {
+        let session_id = Uuid::new_v4();
+        let document_id = Uuid::new_v4();
+        let thumbnail = format!("{}/1.jpg", page_images_prefix(session_id, document_id));
+        let attachment = format!("{}/attachments/{}/{}.png", session_id, Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!is_upload_path(&thumbnail, session_id));
+        assert!(!is_upload_path(&attachment, session_id));
+        assert!(!is_upload_path(&format!("{}/{}-..", session_id, Uuid::new_v4()), session_id));
+        assert!(!is_upload_path(&format!("{}/notes.pdf", session_id), session_id));
+    }
+}
@@ -1,15 +1,91 @@
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use crate::prompts::VISION_EXTRACTION_PROMPT;
+use crate::config::Config;
+use crate::metrics;
+use crate::storage::messages::STALE_REPLY_MINUTES;
+use crate::templates::{self, TemplateName, TemplateVars};
 
 const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+/// First retry delay, doubled on each later retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound on any retry delay, including one asked for by `Retry-After`
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Longest deadline for one call: a minute short of when its pending reply counts as failed
+const MAX_CALL_DEADLINE: Duration = Duration::from_secs((STALE_REPLY_MINUTES as u64 - 1) * 60);
+/// Longest error body excerpt written to the logs
+const MAX_LOGGED_ERROR_CHARS: usize = 500;
+
+/// What an AI request is used for (used to label metrics)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiTask {
+    Vision,
+    Chat,
+    Planning,
+    Classification,
+    ProblemExtraction,
+    Summary,
+}
+
+impl AiTask {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AiTask::Vision => "vision",
+            AiTask::Chat => "chat",
+            AiTask::Planning => "planning",
+            AiTask::Classification => "classification",
+            AiTask::ProblemExtraction => "problem_extraction",
+            AiTask::Summary => "summary",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OpenRouterClient {
     client: Client,
     api_key: String,
+    max_retries: u32,
+    /// Limit for a call across all its retries and fallback models
+    call_deadline: Duration,
+    /// Fallback models per task (see `Config::ai_fallback_models`)
+    fallback_models: HashMap<String, Vec<String>>,
+}
+
+/// A model's answer
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    /// Model that produced the answer (a fallback when the requested one failed)
+    pub model: String,
+}
+
+/// A request for JSON matching a schema (see `OpenRouterClient::chat_json`)
+#[derive(Debug, Clone, Copy)]
+pub struct JsonRequest<'a> {
+    pub system_prompt: &'a str,
+    /// Earlier turns as (role, content), e.g. a previous answer that is being repaired
+    pub history: &'a [(String, String)],
+    pub user_message: &'a str,
+    /// Name the schema is sent under
+    pub schema_name: &'a str,
+    pub schema: &'a serde_json::Value,
+}
+
+/// Why a request to one model failed
+#[derive(Debug)]
+enum RequestError {
+    /// Worth retrying the same model (rate limits, server errors, timeouts)
+    Transient { reason: String, retry_after: Option<Duration> },
+    /// The model can't answer this request, but another model might
+    Model(String),
+    /// The request itself was refused as invalid (HTTP 400), e.g. a `response_format`
+    /// the provider doesn't support
+    BadRequest(String),
+    /// No model will succeed (e.g. an invalid API key)
+    Fatal(String),
 }
 
 #[derive(Debug, Serialize)]
@@ -18,6 +94,23 @@ struct ChatRequest {
     messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+}
+
+/// Structured output request (providers that don't support it ignore it)
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum ResponseFormat {
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSchemaFormat {
+    name: String,
+    strict: bool,
+    schema: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,7 +142,22 @@ struct ImageUrl {
 
 #[derive(Debug, Deserialize)]
 struct ChatResponse {
+    #[serde(default)]
     choices: Vec<Choice>,
+    usage: Option<Usage>,
+    /// Set instead of `choices` when the provider failed mid-request
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Usage {
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,24 +167,30 @@ struct Choice {
 
 #[derive(Debug, Deserialize)]
 struct ResponseMessage {
-    content: String,
+    /// `null` when the model produced no text (e.g. only a refusal or tool call)
+    content: Option<String>,
 }
 
 impl OpenRouterClient {
-    pub fn new(api_key: String) -> Self {
+    /// A client for one call site, sharing the HTTP client built with the configuration
+    pub fn new(config: &Config) -> Self {
         Self {
-            client: Client::new(),
-            api_key,
+            client: config.ai_http_client.clone(),
+            api_key: config.openrouter_api_key.clone(),
+            max_retries: config.ai_max_retries,
+            call_deadline: Duration::from_secs(config.ai_call_deadline_secs).min(MAX_CALL_DEADLINE),
+            fallback_models: config.ai_fallback_models.clone(),
         }
     }
 
     /// Send a chat completion request (text only)
     pub async fn chat(
         &self,
+        task: AiTask,
         model: &str,
         system_prompt: &str,
         user_message: &str,
-    ) -> Result<String, async_graphql::Error> {
+    ) -> Result<Completion, async_graphql::Error> {
         let request = ChatRequest {
             model: model.to_string(),
             messages: vec![
@@ -90,19 +204,23 @@ impl OpenRouterClient {
                 },
             ],
             max_tokens: None,
+            response_format: None,
         };
 
-        self.send_request(request).await
+        self.send_request(task, request).await
     }
 
     /// Send a chat completion request with conversation history
+    ///
+    /// `images` are `data:` URLs sent along with the user message (for multimodal models).
     pub async fn chat_with_history(
         &self,
         model: &str,
         system_prompt: &str,
         history: Vec<(String, String)>, // (role, content)
         user_message: &str,
-    ) -> Result<String, async_graphql::Error> {
+        images: &[String],
+    ) -> Result<Completion, async_graphql::Error> {
         let mut messages = vec![Message {
             role: "system".to_string(),
             content: MessageContent::Text(system_prompt.to_string()),
@@ -116,22 +234,73 @@ impl OpenRouterClient {
             });
         }
 
-        // Add current user message
+        // Add current user message, with its images if any
+        let content = if images.is_empty() {
+            MessageContent::Text(user_message.to_string())
+        } else {
+            let mut parts = vec![ContentPart::Text {
+                text: user_message.to_string(),
+            }];
+            parts.extend(images.iter().map(|url| ContentPart::ImageUrl {
+                image_url: ImageUrl { url: url.clone() },
+            }));
+            MessageContent::Parts(parts)
+        };
         messages.push(Message {
             role: "user".to_string(),
-            content: MessageContent::Text(user_message.to_string()),
+            content,
         });
 
         let request = ChatRequest {
             model: model.to_string(),
             messages,
             max_tokens: None,
+            response_format: None,
         };
 
-        self.send_request(request).await
+        self.send_request(AiTask::Chat, request).await
     }
 
-    
+    /// Send a chat completion request asking for JSON matching the request's schema
+    ///
+    /// Models without structured output support may still answer with other text,
+    /// so callers validate the answer.
+    pub async fn chat_json(
+        &self,
+        task: AiTask,
+        model: &str,
+        request: JsonRequest<'_>,
+    ) -> Result<Completion, async_graphql::Error> {
+        let mut messages = vec![Message {
+            role: "system".to_string(),
+            content: MessageContent::Text(request.system_prompt.to_string()),
+        }];
+        for (role, content) in request.history {
+            messages.push(Message {
+                role: role.clone(),
+                content: MessageContent::Text(content.clone()),
+            });
+        }
+        messages.push(Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(request.user_message.to_string()),
+        });
+
+        let chat_request = ChatRequest {
+            model: model.to_string(),
+            messages,
+            max_tokens: None,
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name: request.schema_name.to_string(),
+                    strict: true,
+                    schema: request.schema.clone(),
+                },
+            }),
+        };
+
+        self.send_request(task, chat_request).await
+    }
 
     /// Extract text from an image using vision model
     pub async fn extract_text_from_image(
@@ -139,8 +308,8 @@ impl OpenRouterClient {
         model: &str,
         image_base64: &str,
         mime_type: &str,
-    ) -> Result<String, async_graphql::Error> {
-        let prompt = VISION_EXTRACTION_PROMPT;
+    ) -> Result<Completion, async_graphql::Error> {
+        let prompt = templates::render(TemplateName::VisionExtraction, None, &TemplateVars::new())?.text;
 
         let data_url = format!("data:{};base64,{}", mime_type, image_base64);
 
@@ -150,7 +319,7 @@ impl OpenRouterClient {
                 role: "user".to_string(),
                 content: MessageContent::Parts(vec![
                     ContentPart::Text {
-                        text: prompt.to_string(),
+                        text: prompt,
                     },
                     ContentPart::ImageUrl {
                         image_url: ImageUrl { url: data_url },
@@ -158,12 +327,132 @@ impl OpenRouterClient {
                 ]),
             }],
             max_tokens: Some(4096),
+            response_format: None,
         };
 
-        self.send_request(request).await
+        self.send_request(AiTask::Vision, request).await
     }
 
-    async fn send_request(&self, request: ChatRequest) -> Result<String, async_graphql::Error> {
+    /// Send a request to its model, retrying transient failures with backoff,
+    /// then to the task's fallback models in order, giving up at the call deadline
+    async fn send_request(&self, task: AiTask, request: ChatRequest) -> Result<Completion, async_graphql::Error> {
+        match tokio::time::timeout(self.call_deadline, self.send_with_fallbacks(task, request)).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!("AI {} request gave up after {:?}", task.as_str(), self.call_deadline);
+                Err(async_graphql::Error::new(
+                    "The AI service took too long to answer, please try again later",
+                ))
+            }
+        }
+    }
+
+    async fn send_with_fallbacks(
+        &self,
+        task: AiTask,
+        mut request: ChatRequest,
+    ) -> Result<Completion, async_graphql::Error> {
+        let mut models = vec![request.model.clone()];
+        for model in self.fallback_models.get(task.as_str()).into_iter().flatten() {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+
+        for (index, model) in models.into_iter().enumerate() {
+            if index > 0 {
+                tracing::warn!("Falling back to model {} for {} request", model, task.as_str());
+            }
+            request.model = model;
+
+            let mut result = self.send_with_retries(task, &request).await;
+            if let Err(RequestError::BadRequest(reason)) = &result {
+                // Callers validate structured answers anyway, so the format is only a hint;
+                // the later models don't get it either, as they likely reject it too
+                if request.response_format.take().is_some() {
+                    tracing::warn!(
+                        "Model {} rejected the {} request, retrying without a response format: {}",
+                        request.model,
+                        task.as_str(),
+                        reason
+                    );
+                    result = self.send_with_retries(task, &request).await;
+                }
+            }
+
+            match result {
+                Ok(content) => {
+                    return Ok(Completion {
+                        content,
+                        model: request.model,
+                    })
+                }
+                Err(RequestError::Fatal(reason)) => {
+                    tracing::error!("AI {} request failed: {}", task.as_str(), reason);
+                    return Err(async_graphql::Error::new("The AI service rejected the request"));
+                }
+                Err(RequestError::Transient { reason, .. })
+                | Err(RequestError::Model(reason))
+                | Err(RequestError::BadRequest(reason)) => {
+                    tracing::warn!("Model {} failed for {} request: {}", request.model, task.as_str(), reason);
+                }
+            }
+        }
+
+        Err(async_graphql::Error::new(
+            "The AI service is unavailable right now, please try again later",
+        ))
+    }
+
+    /// Send a request to one model, retrying transient failures
+    async fn send_with_retries(&self, task: AiTask, request: &ChatRequest) -> Result<String, RequestError> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(task, request).await {
+                Err(RequestError::Transient { reason, retry_after }) if attempt < self.max_retries => {
+                    let delay = retry_after
+                        .unwrap_or(RETRY_BASE_DELAY * 2u32.pow(attempt))
+                        .min(MAX_RETRY_DELAY);
+                    tracing::info!(
+                        "Retrying {} request to {} in {:?} ({})",
+                        task.as_str(),
+                        request.model,
+                        delay,
+                        reason
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_once(&self, task: AiTask, request: &ChatRequest) -> Result<String, RequestError> {
+        let started = Instant::now();
+
+        let result = self.execute_request(request).await;
+
+        let (prompt_tokens, completion_tokens) = match &result {
+            Ok((_, Some(usage))) => (usage.prompt_tokens, usage.completion_tokens),
+            _ => (None, None),
+        };
+        metrics::observe_llm_request(
+            task.as_str(),
+            &request.model,
+            result.is_ok(),
+            started.elapsed(),
+            prompt_tokens,
+            completion_tokens,
+        );
+
+        result.map(|(content, _)| content)
+    }
+
+    async fn execute_request(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<(String, Option<Usage>), RequestError> {
         let response = self
             .client
             .post(OPENROUTER_API_URL)
@@ -171,33 +460,105 @@ impl OpenRouterClient {
             .header("Content-Type", "application/json")
             .header("HTTP-Referer", "https://caky.app")
             .header("X-Title", "Caky")
-            .json(&request)
+            .json(request)
             .send()
             .await
-            .map_err(|e| async_graphql::Error::new(format!("OpenRouter request failed: {}", e)))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(async_graphql::Error::new(format!(
-                "OpenRouter API error: {}",
-                error_text
-            )));
+            .map_err(|e| RequestError::Transient {
+                reason: format!("request failed: {}", e),
+                retry_after: None,
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = retry_after(response.headers());
+            let body = response.text().await.unwrap_or_default();
+            let reason = format!("HTTP {}: {}", status, body.chars().take(MAX_LOGGED_ERROR_CHARS).collect::<String>());
+
+            return Err(match status {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT => {
+                    RequestError::Transient { reason, retry_after }
+                }
+                s if s.is_server_error() => RequestError::Transient { reason, retry_after },
+                StatusCode::UNAUTHORIZED | StatusCode::PAYMENT_REQUIRED | StatusCode::FORBIDDEN => {
+                    RequestError::Fatal(reason)
+                }
+                StatusCode::BAD_REQUEST => RequestError::BadRequest(reason),
+                _ => RequestError::Model(reason),
+            });
         }
 
-        let chat_response: ChatResponse = response
-            .json()
-            .await
-            .map_err(|e| async_graphql::Error::new(format!("Failed to parse response: {}", e)))?;
+        let chat_response: ChatResponse = response.json().await.map_err(|e| RequestError::Transient {
+            reason: format!("failed to parse response: {}", e),
+            retry_after: None,
+        })?;
+
+        if let Some(error) = chat_response.error {
+            return Err(RequestError::Transient {
+                reason: format!("provider error: {}", error.message.unwrap_or_default()),
+                retry_after: None,
+            });
+        }
 
-        chat_response
+        let content = chat_response
             .choices
-            .first()
-            .map(|c| c.message.content.clone())
-            .ok_or_else(|| async_graphql::Error::new("No response from AI"))
+            .into_iter()
+            .next()
+            .and_then(|c| c.message.content)
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| RequestError::Model("empty response".to_string()))?;
+
+        Ok((content, chat_response.usage))
+    }
+}
+
+/// Delay asked for by a `Retry-After` header (seconds or an HTTP date)
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
 }
 
 /// Encode bytes to base64
 pub fn encode_base64(data: &[u8]) -> String {
     BASE64.encode(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn reads_retry_after_seconds() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn reads_retry_after_dates() {
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        let delay = retry_after(&headers(&date)).unwrap();
+        assert!(delay > Duration::from_secs(85) && delay <= Duration::from_secs(90));
+
+        let past = (chrono::Utc::now() - chrono::Duration::seconds(90)).to_rfc2822();
+        assert_eq!(retry_after(&headers(&past)), None);
+    }
+
+    #[test]
+    fn ignores_missing_or_invalid_retry_after() {
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&headers("-5")), None);
+    }
+}
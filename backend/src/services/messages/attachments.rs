@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::metrics;
+use crate::services::documents::storage_client::{self, StorageClient};
+use crate::storage::message_attachments::{self, AttachmentRow};
+
+/// Largest image accepted as an attachment
+pub const MAX_ATTACHMENT_SIZE: i64 = 10 * 1024 * 1024; // 10MB
+
+/// Most images that can be attached to one message
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 4;
+
+/// Attachments not sent within this many hours are deleted
+const UNSENT_ATTACHMENT_HOURS: i32 = 24;
+
+/// How often unsent attachments are cleaned up
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// An attachment downloaded and checked, ready to send to the model
+#[derive(Debug, Clone)]
+pub struct LoadedImage {
+    pub attachment_id: Uuid,
+    pub size: i64,
+    /// `data:<type>;base64,...` URL
+    pub data_url: String,
+}
+
+/// Image content type for a file name, or `None` if it isn't a supported image
+pub fn image_content_type(file_name: &str) -> Option<&'static str> {
+    let extension = file_name.rsplit_once('.')?.1.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "gif" => Some("image/gif"),
+        _ => None,
+    }
+}
+
+/// Content type of an image from its first bytes
+fn sniff_content_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
+
+/// Storage path for an attachment: `{session_id}/attachments/{chat_id}/{attachment_id}.{ext}`
+pub fn storage_path_for(session_id: Uuid, chat_id: Uuid, attachment_id: Uuid, file_name: &str) -> String {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    format!("{}/attachments/{}/{}.{}", session_id, chat_id, attachment_id, extension)
+}
+
+/// Download attachments and check they really are images within the size limit
+pub async fn load_images(
+    config: &Config,
+    attachments: &[AttachmentRow],
+) -> Result<Vec<LoadedImage>, async_graphql::Error> {
+    let storage = StorageClient::new(config);
+    let mut images = Vec::with_capacity(attachments.len());
+
+    for attachment in attachments {
+        // Check the size first so an oversized upload is never downloaded
+        let size = storage_client::get_file_size(
+            &config.supabase_url,
+            &config.supabase_service_key,
+            &attachment.file_path,
+        )
+        .await
+        .map_err(|e| {
+            tracing::warn!("Failed to check attachment {}: {:?}", attachment.id, e);
+            async_graphql::Error::new(format!("Failed to load attachment {}", attachment.file_name))
+        })?
+        .ok_or_else(|| format!("Attachment {} has not been uploaded", attachment.file_name))?;
+        if size as i64 > MAX_ATTACHMENT_SIZE {
+            return Err(format!("Attachment {} exceeds the 10MB limit", attachment.file_name).into());
+        }
+
+        let bytes = storage
+            .download(&format!("documents/{}", attachment.file_path))
+            .await
+            .map_err(|e| {
+                tracing::warn!("Failed to download attachment {}: {:?}", attachment.id, e);
+                async_graphql::Error::new(format!("Attachment {} has not been uploaded", attachment.file_name))
+            })?;
+
+        // The object may have been replaced since its size was checked
+        if bytes.len() as i64 > MAX_ATTACHMENT_SIZE {
+            return Err(format!("Attachment {} exceeds the 10MB limit", attachment.file_name).into());
+        }
+        let content_type = sniff_content_type(&bytes)
+            .ok_or_else(|| format!("Attachment {} is not a PNG, JPEG, WebP or GIF image", attachment.file_name))?;
+
+        images.push(LoadedImage {
+            attachment_id: attachment.id,
+            size: bytes.len() as i64,
+            data_url: format!("data:{};base64,{}", content_type, BASE64.encode(&bytes)),
+        });
+    }
+
+    Ok(images)
+}
+
+/// Delete attachment files from storage (best effort: failures are logged)
+pub async fn delete_files(config: &Config, paths: &[String]) {
+    if paths.is_empty() {
+        return;
+    }
+    if let Err(e) =
+        storage_client::delete_objects(&config.supabase_url, &config.supabase_service_key, paths).await
+    {
+        tracing::error!("Failed to delete {} attachment files: {}", paths.len(), e);
+    }
+}
+
+/// Periodically delete attachments that were uploaded but never sent
+pub fn spawn_cleanup(pool: PgPool, config: Config) {
+    tokio::spawn(async move {
+        loop {
+            let task_guard = metrics::BackgroundTaskGuard::new("attachment_cleanup");
+            match message_attachments::delete_unsent_attachments(&pool, UNSENT_ATTACHMENT_HOURS).await {
+                Ok(paths) => {
+                    if !paths.is_empty() {
+                        tracing::info!("Deleting {} unsent attachments", paths.len());
+                    }
+                    delete_files(&config, &paths).await;
+                }
+                Err(e) => tracing::error!("Failed to clean up unsent attachments: {}", e.message),
+            }
+            drop(task_guard);
+
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    });
+}
@@ -2,13 +2,30 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::config::Config;
-use crate::prompts::{REVIEW_SYSTEM_PROMPT, TOPIC_SYSTEM_PROMPT};
-use crate::storage::{documents, messages, topics};
+use crate::storage::message_citations::NewCitation;
+use crate::storage::messages::{Generation, GenerationContext, PromptMessage};
+use crate::storage::pagination::{Cursor, Keyed};
+use crate::storage::problems::{self, ProblemRow};
+use crate::storage::topics::TopicRow;
+use crate::storage::{chats, document_pages, documents, message_attachments, messages, topics};
+use crate::templates::{self, TemplateName, TemplateVars};
 
-use super::ai_client::OpenRouterClient;
+use super::ai_client::{AiTask, OpenRouterClient};
+use super::citations::{self, SourceContext};
+use super::prompt_budget::{Keep, PromptBudget};
 
 const CHAT_MODEL: &str = "google/gemini-2.5-flash";
 const MAX_HISTORY_MESSAGES: i32 = 20;
+/// Messages kept verbatim when older ones are folded into the summary
+const KEEP_RECENT_MESSAGES: usize = 10;
+/// Upper bound on messages folded into the summary at once
+const MAX_SUMMARY_BATCH: i32 = 200;
+/// Long messages are shortened to this length in the summary prompt
+const MAX_SUMMARY_MESSAGE_CHARS: usize = 4_000;
+/// Upper bound on exam problems included in a chat prompt
+const MAX_PROMPT_PROBLEMS: usize = 30;
+/// Tokens set aside per attached image (a large image costs the model about this much)
+const IMAGE_TOKENS: usize = 1_500;
 
 /// Convert language code to full language name
 fn language_name(code: &str) -> &str {
@@ -22,33 +39,70 @@ fn language_name(code: &str) -> &str {
     }
 }
 
+/// An assistant reply and the sources it cites
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    /// Reply text, with citation markers numbered `[1]`, `[2]`, ...
+    pub content: String,
+    pub citations: Vec<NewCitation>,
+    /// Template, model and context that produced the reply
+    pub generation: Generation,
+}
+
+/// Template variables shared by the topic and review chat prompts
+fn chat_template(topic_name: Option<&str>, language: &str) -> (TemplateName, TemplateVars) {
+    let vars = TemplateVars::new().set("language", language_name(language));
+    match topic_name {
+        Some(topic) => (TemplateName::TopicChat, vars.set("topic_name", topic)),
+        None => (TemplateName::ReviewChat, vars),
+    }
+}
+
+/// Load the session's completed documents as citable prompt context
+async fn load_source_context(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_id: Uuid,
+) -> Result<SourceContext, async_graphql::Error> {
+    let doc_texts = documents::get_session_document_texts(pool, profile_id, session_id).await?;
+    let document_ids: Vec<Uuid> = doc_texts.iter().map(|d| d.id).collect();
+    let pages = document_pages::get_pages_by_document_ids(pool, profile_id, &document_ids).await?;
+
+    Ok(citations::build_context(&doc_texts, &pages))
+}
+
+/// The chat a reply is generated for
+#[derive(Debug, Clone, Copy)]
+pub struct ChatScope<'a> {
+    pub profile_id: Uuid,
+    pub session_id: Uuid,
+    pub chat_id: Uuid,
+    /// Title of the chat's topic (`None` for the review chat)
+    pub topic_name: Option<&'a str>,
+    pub language: &'a str,
+}
+
 /// Process a chat message and get AI response
+///
+/// The history sent along is the chat's current branch, up to `before` when
+/// answering a message that is already stored.
+/// `images` are `data:` URLs of the images attached to the message.
 pub async fn process_message(
     pool: &PgPool,
     config: &Config,
-    profile_id: Uuid,
-    session_id: Uuid,
-    chat_id: Uuid,
+    scope: ChatScope<'_>,
     user_message: &str,
-    topic_name: Option<&str>,
-    language: &str,
-) -> Result<String, async_graphql::Error> {
+    images: &[String],
+    before: Option<Cursor>,
+) -> Result<ChatReply, async_graphql::Error> {
+    let ChatScope { profile_id, session_id, chat_id, topic_name, language } = scope;
+
     // 1. Fetch document context
     tracing::info!("Fetching documents for session {} by profile {}", session_id, profile_id);
-    let doc_texts = documents::get_session_document_texts(pool, profile_id, session_id).await?;
+    let mut source_context = load_source_context(pool, profile_id, session_id).await?;
+    let document_blocks = std::mem::take(&mut source_context.blocks);
     
-    tracing::info!("Found {} documents with completed extraction", doc_texts.len());
-    
-    let context = if doc_texts.is_empty() {
-        tracing::warn!("No documents found with processing_status='COMPLETED' for session {}", session_id);
-        "No study materials have been uploaded yet. Please upload your course materials (slides, past exams, notes) to get personalized help.".to_string()
-    } else {
-        doc_texts
-            .iter()
-            .map(|(name, content)| format!("=== {} ===\n{}", name, content))
-            .collect::<Vec<_>>()
-            .join("\n\n")
-    };
+    tracing::info!("Found {} documents with completed extraction", source_context.sources.len());
 
     // 2. Fetch topics for study plan context
     let all_topics = topics::get_session_topics(pool, profile_id, session_id).await?;
@@ -58,46 +112,226 @@ pub async fn process_message(
         .collect::<Vec<_>>()
         .join("\n");
 
-    // 3. Build system prompt based on chat type
-    let lang = language_name(language);
-    let system_prompt = if let Some(topic) = topic_name {
-        TOPIC_SYSTEM_PROMPT
-            .replace("{topic_name}", topic)
-            .replace("{context}", &context)
-            .replace("{study_plan}", &study_plan_context)
-            .replace("{language}", lang)
-    } else {
-        REVIEW_SYSTEM_PROMPT
-            .replace("{context}", &context)
-            .replace("{study_plan}", &study_plan_context)
-            .replace("{language}", lang)
-    };
-
-    // Debug logging: show the final system prompt
-    tracing::debug!("Final system prompt for chat {}: {}", chat_id, system_prompt);
+    // 3. Fetch real exam problems (the topic's own in a topic chat, all in review)
+    let chat_topic_id = chats::get_chat_by_id(pool, profile_id, chat_id)
+        .await?
+        .and_then(|c| c.topic_id);
+    let session_problems = problems::get_session_problems(pool, profile_id, session_id).await?;
+    let problem_bank = format_problem_bank(&session_problems, &all_topics, chat_topic_id);
 
-    // 4. Fetch recent conversation history for this specific chat
+    // 4. Fetch the chat's rolling summary and the recent messages it doesn't cover
+    // (a summary reaching past `before` describes messages being replaced, so it is skipped)
+    let summary = chats::get_chat_summary(pool, profile_id, chat_id)
+        .await?
+        .filter(|s| match (s.through(), before) {
+            (Some(through), Some(before)) => through < before,
+            _ => true,
+        });
     let recent_messages = messages::get_recent_messages(
         pool, 
         profile_id, 
         chat_id, 
+        summary.as_ref().and_then(|s| s.through()),
+        before,
         MAX_HISTORY_MESSAGES
     ).await?;
+    let attachments = message_attachments::get_attachments_by_message_ids(
+        pool,
+        profile_id,
+        &recent_messages.iter().map(|m| m.id).collect::<Vec<_>>(),
+    )
+    .await?;
+    let summary_text = summary
+        .and_then(|s| s.summary)
+        .map(|summary| format!("Summary of the earlier conversation:\n{}", summary));
 
-    // 5. Build conversation history for the AI
-    let history: Vec<(String, String)> = recent_messages
-        .iter()
-        .map(|m| (m.role.clone(), m.content.clone()))
+    // 5. Fit everything into the model's budget, most important first
+    let (template, vars) = chat_template(topic_name, language);
+    let rules = templates::render(template, Some(language), &vars.clone().set("context", ""))?;
+    let mut budget = PromptBudget::new(format!("chat {}", chat_id), config.prompt_token_budget(CHAT_MODEL));
+    budget.reserve("rules", &rules.text);
+    budget.reserve("user message", user_message);
+    budget.reserve_tokens("images", images.len() * IMAGE_TOKENS);
+    let summary_text = summary_text.map(|text| budget.fit_text("summary", text));
+    let study_plan_context = budget.fit_text("study plan", study_plan_context);
+    let recent_messages = budget.fit("history", recent_messages, Keep::Last, |m| &mut m.content);
+    let problem_bank = budget.fit_text("problem bank", problem_bank);
+
+    let document_blocks = budget.fit_blocks("documents", document_blocks, Keep::First);
+
+    let generation_context = GenerationContext {
+        documents: source_context.documents_shown(document_blocks.len()),
+        history_messages: recent_messages.len(),
+        summary: summary_text.is_some(),
+        study_plan: !study_plan_context.trim().is_empty(),
+        problem_bank: !problem_bank.trim().is_empty(),
+        images: images.len(),
+        prompt_tokens: budget.used(),
+    };
+
+    let context = if source_context.sources.is_empty() {
+        tracing::warn!("No documents found with processing_status='COMPLETED' for session {}", session_id);
+        "No study materials have been uploaded yet. Please upload your course materials (slides, past exams, notes) to get personalized help.".to_string()
+    } else {
+        document_blocks.join("\n\n")
+    };
+
+    // 6. Build system prompt and conversation history (starting with the summary)
+    let system_prompt = templates::render(
+        template,
+        Some(language),
+        &vars
+            .set("context", context)
+            .set("study_plan", study_plan_context)
+            .set("problems", problem_bank),
+    )?;
+
+    // Debug logging: show the final system prompt
+    tracing::debug!("Final system prompt for chat {} ({}): {}", chat_id, system_prompt.version, system_prompt.text);
+
+    let mut history: Vec<(String, String)> = Vec::new();
+    if let Some(summary) = summary_text {
+        history.push(("system".to_string(), summary));
+    }
+    history.extend(recent_messages.iter().map(|m| {
+        // Earlier images aren't sent again; the model only learns they were there
+        let image_count = attachments.iter().filter(|a| a.message_id == Some(m.id)).count();
+        let content = match image_count {
+            0 => m.content.clone(),
+            n => format!("{}\n\n[The student attached {} image(s) to this message]", m.content, n),
+        };
+        (m.role.clone(), content)
+    }));
+
+    // Kept with the reply so rated answers can be reproduced
+    let prompt: Vec<PromptMessage> = std::iter::once(PromptMessage::new("system", &system_prompt.text))
+        .chain(history.iter().map(|(role, content)| PromptMessage::new(role, content)))
+        .chain(std::iter::once(PromptMessage::new("user", user_message)))
         .collect();
 
-    // 6. Call AI
-    let ai_client = OpenRouterClient::new(config.openrouter_api_key.clone());
+    // 7. Call AI
+    let ai_client = OpenRouterClient::new(config);
     
     let ai_response = ai_client
-        .chat_with_history(CHAT_MODEL, &system_prompt, history, user_message)
+        .chat_with_history(CHAT_MODEL, &system_prompt.text, history, user_message, images)
         .await?;
 
-    Ok(ai_response)
+    // 8. Turn the model's citation markers into numbered citations
+    let (content, citations) = citations::extract_citations(&ai_response.content, &source_context.sources);
+
+    Ok(ChatReply {
+        content,
+        citations,
+        generation: Generation {
+            template_version: system_prompt.version,
+            model: ai_response.model,
+            context: generation_context,
+            prompt,
+        },
+    })
+}
+
+/// Fold messages that no longer fit in the history window into the chat's rolling summary
+///
+/// Only runs once more than `MAX_HISTORY_MESSAGES` messages are unsummarized, and keeps
+/// the newest `KEEP_RECENT_MESSAGES` verbatim, so the summary is not rewritten every turn.
+/// Returns whether the summary was updated.
+pub async fn refresh_summary(
+    pool: &PgPool,
+    config: &Config,
+    profile_id: Uuid,
+    chat_id: Uuid,
+) -> Result<bool, async_graphql::Error> {
+    let summary = chats::get_chat_summary(pool, profile_id, chat_id)
+        .await?
+        .ok_or("Chat not found")?;
+    let previous = summary.through();
+
+    let unsummarized =
+        messages::get_messages_after(pool, profile_id, chat_id, previous, MAX_SUMMARY_BATCH).await?;
+    if unsummarized.len() <= MAX_HISTORY_MESSAGES as usize {
+        return Ok(false);
+    }
+
+    let to_fold = &unsummarized[..unsummarized.len() - KEEP_RECENT_MESSAGES];
+    let transcript = to_fold
+        .iter()
+        .map(|m| {
+            let speaker = if m.role == "user" { "Student" } else { "Tutor" };
+            let content: String = m.content.chars().take(MAX_SUMMARY_MESSAGE_CHARS).collect();
+            format!("{}: {}", speaker, content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let mut vars = TemplateVars::new().set("messages", transcript);
+    if let Some(previous) = &summary.summary {
+        vars = vars.set("summary", previous.as_str());
+    }
+    let prompt = templates::render(TemplateName::SummarizeChat, None, &vars)?.text;
+
+    let ai_client = OpenRouterClient::new(config);
+    let new_summary = ai_client
+        .chat(
+            AiTask::Summary,
+            CHAT_MODEL,
+            "You summarize tutoring conversations.",
+            &prompt,
+        )
+        .await?
+        .content;
+
+    let through = to_fold.last().map(|m| m.cursor()).ok_or("No messages to summarize")?;
+    let stored = chats::update_chat_summary(pool, chat_id, new_summary.trim(), through, &summary).await?;
+    if !stored {
+        tracing::info!("Summary or history of chat {} changed concurrently, discarding", chat_id);
+    }
+
+    Ok(stored)
+}
+
+/// Render exam problems for a chat prompt, with the provided solutions for the tutor
+///
+/// A topic chat gets the topic's problems; the review chat gets all of them,
+/// labelled by topic.
+fn format_problem_bank(problems: &[ProblemRow], topics: &[TopicRow], topic_id: Option<Uuid>) -> String {
+    let selected: Vec<&ProblemRow> = problems
+        .iter()
+        .filter(|p| topic_id.is_none() || p.topic_id == topic_id)
+        .take(MAX_PROMPT_PROBLEMS)
+        .collect();
+
+    if selected.is_empty() {
+        return "No exam problems have been extracted for this yet.".to_string();
+    }
+
+    selected
+        .iter()
+        .map(|p| {
+            let mut header = format!("--- {}", p.document_name);
+            if let Some(label) = &p.label {
+                header.push_str(&format!(", problem {}", label));
+            }
+            if let Some(points) = p.points {
+                header.push_str(&format!(" ({} pts)", points));
+            }
+            if topic_id.is_none() {
+                if let Some(topic) = p.topic_id.and_then(|id| topics.iter().find(|t| t.id == id)) {
+                    header.push_str(&format!(" [Topic: {}]", topic.title));
+                }
+            }
+
+            let mut text = format!("{} ---\n{}", header, p.statement);
+            for part in &p.sub_parts {
+                text.push_str(&format!("\n{}", part));
+            }
+            if let Some(solution) = &p.solution {
+                text.push_str(&format!("\nProvided solution: {}", solution));
+            }
+            text
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
 /// Generate the initial welcome message when a student enters a chat
@@ -108,50 +342,31 @@ pub async fn generate_welcome_message(
     session_id: Uuid,
     topic_name: Option<&str>,
     language: &str,
-) -> Result<String, async_graphql::Error> {
+) -> Result<ChatReply, async_graphql::Error> {
     tracing::info!("Generating welcome message for session {}, topic: {:?}", session_id, topic_name);
 
     // 1. Fetch document context
-    let doc_texts = documents::get_session_document_texts(pool, profile_id, session_id).await?;
-    
-    let context = if doc_texts.is_empty() {
-        "Nenhum material de estudo foi processado ainda.".to_string()
-    } else {
-        doc_texts
-            .iter()
-            .map(|(name, content)| format!("=== {} ===\n{}", name, content))
-            .collect::<Vec<_>>()
-            .join("\n\n")
-    };
+    let mut source_context = load_source_context(pool, profile_id, session_id).await?;
+    let document_blocks = std::mem::take(&mut source_context.blocks);
 
     // 2. Build system prompt and welcome instruction based on chat type
-    let lang = language_name(language);
-    let (system_prompt, welcome_instruction) = if let Some(topic) = topic_name {
-        let prompt = TOPIC_SYSTEM_PROMPT
-            .replace("{topic_name}", topic)
-            .replace("{context}", &context)
-            .replace("{study_plan}", "")
-            .replace("{language}", lang);
-        let instruction = format!(
+    let (template, vars) = chat_template(topic_name, language);
+    let welcome_instruction = if let Some(topic) = topic_name {
+        format!(
             "Generate a welcome message for the student who is starting to study the topic '{}'. \
             1. Briefly introduce what this topic is about and why it's useful. \
             2. Based on the study materials, suggest the first important concept or 'thing' they should learn. \
             3. Ask if they are ready to start with that specific concept. \
             Be direct and focus on getting started.",
             topic
-        );
-        (prompt, instruction)
+        )
     } else {
         // Get all topics to show progress
         let all_topics = topics::get_session_topics(pool, profile_id, session_id).await?;
         let completed_count = all_topics.iter().filter(|t| t.is_completed).count();
         let total_count = all_topics.len();
 
-        let prompt = REVIEW_SYSTEM_PROMPT
-            .replace("{context}", &context)
-            .replace("{study_plan}", "")
-            .replace("{language}", lang);
-        let instruction = format!(
+        format!(
             "Generate a welcome message for the General Review chat. \
             The student has completed {}/{} topics and is now ready for final review. \
             1. Congratulate them on reaching the review phase. \
@@ -159,20 +374,54 @@ pub async fn generate_welcome_message(
             3. Ask what they'd like to focus on: past exam problems, specific topics, or a full practice test. \
             Be direct, enthusiastic and supportive.",
             completed_count, total_count
-        );
-        (prompt, instruction)
+        )
+    };
+
+    // 3. Fit the documents into what's left of the model's budget
+    let mut budget = PromptBudget::new(
+        format!("welcome in session {}", session_id),
+        config.prompt_token_budget(CHAT_MODEL),
+    );
+    let rules = templates::render(template, Some(language), &vars.clone().set("context", ""))?;
+    budget.reserve("rules", &rules.text);
+    budget.reserve("instruction", &welcome_instruction);
+    let document_blocks = budget.fit_blocks("documents", document_blocks, Keep::First);
+    let generation_context = GenerationContext {
+        documents: source_context.documents_shown(document_blocks.len()),
+        prompt_tokens: budget.used(),
+        ..Default::default()
     };
+    let context = if source_context.sources.is_empty() {
+        "Nenhum material de estudo foi processado ainda.".to_string()
+    } else {
+        document_blocks.join("\n\n")
+    };
+    let system_prompt = templates::render(template, Some(language), &vars.set("context", context))?;
 
     // Debug logging: show the final system prompt and welcome instruction
-    tracing::debug!("Final welcome system prompt: {}", system_prompt);
+    tracing::debug!("Final welcome system prompt ({}): {}", system_prompt.version, system_prompt.text);
     tracing::debug!("Final welcome instruction: {}", welcome_instruction);
 
-    // 3. Call AI to generate the welcome message
-    let ai_client = OpenRouterClient::new(config.openrouter_api_key.clone());
+    // 4. Call AI to generate the welcome message
+    let ai_client = OpenRouterClient::new(config);
     
     let welcome_message = ai_client
-        .chat(CHAT_MODEL, &system_prompt, &welcome_instruction)
+        .chat(AiTask::Chat, CHAT_MODEL, &system_prompt.text, &welcome_instruction)
         .await?;
 
-    Ok(welcome_message)
+    let (content, citations) = citations::extract_citations(&welcome_message.content, &source_context.sources);
+
+    Ok(ChatReply {
+        content,
+        citations,
+        generation: Generation {
+            template_version: system_prompt.version,
+            model: welcome_message.model,
+            context: generation_context,
+            prompt: vec![
+                PromptMessage::new("system", &system_prompt.text),
+                PromptMessage::new("user", &welcome_instruction),
+            ],
+        },
+    })
 }
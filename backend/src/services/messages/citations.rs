@@ -0,0 +1,282 @@
+use uuid::Uuid;
+
+use crate::storage::document_pages::DocumentPageRow;
+use crate::storage::documents::DocumentText;
+use crate::storage::message_citations::NewCitation;
+
+/// Markers longer than this are ordinary bracketed text, not citations
+const MAX_MARKER_LEN: usize = 80;
+
+/// A document as presented to the model, referred to as `[D<n>]`
+#[derive(Debug, Clone)]
+pub struct CitationSource {
+    pub document_id: Uuid,
+    /// Number of pages shown to the model (0 when only the joined text was available)
+    pub page_count: i32,
+}
+
+/// Prompt context built from a session's documents, and the documents its
+/// `[D<n>]` identifiers refer to (`sources[n - 1]`)
+#[derive(Debug, Clone)]
+pub struct SourceContext {
+    /// One block per page, most important documents first; a document's header
+    /// is part of its first page so dropping trailing blocks never orphans it
+    pub blocks: Vec<String>,
+    /// Index into `sources` of the document each block belongs to
+    pub block_sources: Vec<usize>,
+    pub sources: Vec<CitationSource>,
+}
+
+impl SourceContext {
+    /// IDs of the documents with at least one of the first `kept` blocks in the prompt
+    pub fn documents_shown(&self, kept: usize) -> Vec<Uuid> {
+        let mut shown: Vec<Uuid> = Vec::new();
+        for &index in self.block_sources.iter().take(kept) {
+            let document_id = self.sources[index].document_id;
+            if !shown.contains(&document_id) {
+                shown.push(document_id);
+            }
+        }
+        shown
+    }
+}
+
+/// Build the context blocks, marking every document and page with an identifier
+/// the model can cite (e.g. `[D2 p.3]`)
+pub fn build_context(documents: &[DocumentText], pages: &[DocumentPageRow]) -> SourceContext {
+    let mut blocks = Vec::with_capacity(pages.len().max(documents.len()));
+    let mut block_sources = Vec::with_capacity(blocks.capacity());
+    let mut sources = Vec::with_capacity(documents.len());
+
+    for (i, document) in documents.iter().enumerate() {
+        let reference = format!("D{}", i + 1);
+        let header = format!("=== [{}] {} ===", reference, document.label());
+        let document_pages: Vec<&DocumentPageRow> =
+            pages.iter().filter(|p| p.document_id == document.id).collect();
+
+        // Documents processed before per-page storage only have the joined text
+        if document_pages.is_empty() {
+            blocks.push(format!("{}\n{}", header, document.content_text));
+            block_sources.push(i);
+        }
+        for (n, page) in document_pages.iter().enumerate() {
+            let page_block = format!("[{} p.{}]\n{}", reference, page.page_number, page.content_text);
+            blocks.push(if n == 0 {
+                format!("{}\n{}", header, page_block)
+            } else {
+                page_block
+            });
+            block_sources.push(i);
+        }
+
+        sources.push(CitationSource {
+            document_id: document.id,
+            page_count: document_pages.len() as i32,
+        });
+    }
+
+    SourceContext { blocks, block_sources, sources }
+}
+
+/// A parsed `D<n>` or `D<n>:p<a>-<b>` reference
+type MarkerRef = (usize, Option<(i32, i32)>);
+
+/// Replace the model's citation markers (`[D1:p3]`, `[D1:p3-4; D2]`) with
+/// numbered markers (`[1]`) and collect the sources they point to
+///
+/// References to unknown documents are dropped; unknown pages fall back to
+/// citing the whole document.
+pub fn extract_citations(response: &str, sources: &[CitationSource]) -> (String, Vec<NewCitation>) {
+    let mut content = String::with_capacity(response.len());
+    let mut citations: Vec<NewCitation> = Vec::new();
+    let mut rest = response;
+
+    while let Some(start) = rest.find('[') {
+        content.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let marker = after
+            .find(']')
+            .filter(|&end| end <= MAX_MARKER_LEN)
+            .and_then(|end| parse_marker(&after[..end]).map(|refs| (end, refs)));
+
+        let Some((end, refs)) = marker else {
+            content.push('[');
+            rest = after;
+            continue;
+        };
+
+        let mut numbers: Vec<i32> = Vec::new();
+        for reference in refs {
+            let Some(citation) = resolve(reference, sources) else {
+                continue;
+            };
+            let existing = citations.iter().find(|c| {
+                c.document_id == citation.document_id
+                    && c.page_start == citation.page_start
+                    && c.page_end == citation.page_end
+            });
+            let number = match existing {
+                Some(c) => c.marker,
+                None => {
+                    let number = citations.len() as i32 + 1;
+                    citations.push(NewCitation { marker: number, ..citation });
+                    number
+                }
+            };
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+
+        if numbers.is_empty() {
+            // Don't leave a dangling space where an invalid marker was
+            if content.ends_with(' ') {
+                content.pop();
+            }
+        } else {
+            for number in numbers {
+                content.push_str(&format!("[{}]", number));
+            }
+        }
+        rest = &after[end + 1..];
+    }
+
+    content.push_str(rest);
+    (content, citations)
+}
+
+/// Parse the inside of a marker; `None` if it is not a citation
+fn parse_marker(inner: &str) -> Option<Vec<MarkerRef>> {
+    inner
+        .split([';', ','])
+        .map(|part| {
+            let part = part.trim();
+            let (document, pages) = match part.split_once(':') {
+                Some((document, pages)) => (document, Some(pages)),
+                None => (part, None),
+            };
+
+            let index = document.trim().strip_prefix('D')?.parse::<usize>().ok()?;
+            let pages = match pages {
+                Some(pages) => Some(parse_pages(pages)?),
+                None => None,
+            };
+            Some((index, pages))
+        })
+        .collect()
+}
+
+/// Parse `p3`, `p.3` or `p3-5`
+fn parse_pages(pages: &str) -> Option<(i32, i32)> {
+    let pages = pages.trim().strip_prefix('p')?;
+    let pages = pages.strip_prefix('.').unwrap_or(pages).trim();
+
+    match pages.split_once(['-', '–']) {
+        Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+        None => {
+            let page = pages.parse().ok()?;
+            Some((page, page))
+        }
+    }
+}
+
+/// Map a reference onto the sources shown to the model
+fn resolve((index, pages): MarkerRef, sources: &[CitationSource]) -> Option<NewCitation> {
+    let source = sources.get(index.checked_sub(1)?)?;
+
+    let pages = pages
+        .map(|(start, end)| (start, end.max(start)))
+        .filter(|&(start, _)| start >= 1 && start <= source.page_count)
+        .map(|(start, end)| (start, end.min(source.page_count)));
+
+    Some(NewCitation {
+        marker: 0,
+        document_id: source.document_id,
+        page_start: pages.map(|(start, _)| start),
+        page_end: pages.map(|(_, end)| end),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> Vec<CitationSource> {
+        vec![
+            CitationSource {
+                document_id: Uuid::from_u128(1),
+                page_count: 5,
+            },
+            CitationSource {
+                document_id: Uuid::from_u128(2),
+                page_count: 0,
+            },
+        ]
+    }
+
+    fn citation(marker: i32, document: u128, pages: Option<(i32, i32)>) -> NewCitation {
+        NewCitation {
+            marker,
+            document_id: Uuid::from_u128(document),
+            page_start: pages.map(|(start, _)| start),
+            page_end: pages.map(|(_, end)| end),
+        }
+    }
+
+    #[test]
+    fn numbers_markers_in_order_of_appearance() {
+        let (content, citations) =
+            extract_citations("Limits [D1:p3] and series [D2]. Again [D1:p.3].", &sources());
+
+        assert_eq!(content, "Limits [1] and series [2]. Again [1].");
+        assert_eq!(citations, vec![citation(1, 1, Some((3, 3))), citation(2, 2, None)]);
+    }
+
+    #[test]
+    fn splits_combined_markers_and_clamps_page_ranges() {
+        let (content, citations) = extract_citations("See [D1:p4-9; D2:p2, D1:p2–3].", &sources());
+
+        assert_eq!(content, "See [1][2][3].");
+        assert_eq!(
+            citations,
+            vec![citation(1, 1, Some((4, 5))), citation(2, 2, None), citation(3, 1, Some((2, 3)))]
+        );
+    }
+
+    #[test]
+    fn drops_references_to_unknown_documents() {
+        let (content, citations) = extract_citations("As shown [D7:p1].", &sources());
+
+        assert_eq!(content, "As shown.");
+        assert!(citations.is_empty());
+    }
+
+    #[test]
+    fn unknown_pages_cite_the_whole_document() {
+        let (_, citations) = extract_citations("[D1:p12]", &sources());
+
+        assert_eq!(citations, vec![citation(1, 1, None)]);
+    }
+
+    #[test]
+    fn leaves_other_brackets_alone() {
+        let text = "An interval [0, 1], a list [a; b] and [Definition 2] stay as written [";
+        let (content, citations) = extract_citations(text, &sources());
+
+        assert_eq!(content, text);
+        assert!(citations.is_empty());
+    }
+
+    #[test]
+    fn lists_documents_shown_in_the_kept_blocks() {
+        let context = SourceContext {
+            blocks: vec!["a".into(), "b".into(), "c".into()],
+            block_sources: vec![0, 0, 1],
+            sources: sources(),
+        };
+
+        assert_eq!(context.documents_shown(2), vec![Uuid::from_u128(1)]);
+        assert_eq!(context.documents_shown(3), vec![Uuid::from_u128(1), Uuid::from_u128(2)]);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod ai_client;
+pub mod attachments;
 pub mod chat;
+pub mod citations;
+pub mod prompt_budget;
+pub mod replies;
+pub mod structured;
@@ -0,0 +1,238 @@
+/// Below this many tokens of room, a block is dropped rather than truncated
+const MIN_TRUNCATED_TOKENS: usize = 200;
+
+const TRUNCATION_NOTE: &str = "\n[... truncated to fit the prompt]";
+
+/// Rough token count (about four characters per token for the models we use)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Which end of a list of blocks to keep when not all of them fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Keep from the start (blocks are ordered by importance)
+    First,
+    /// Keep from the end (e.g. the most recent messages)
+    Last,
+}
+
+/// Token budget shared by the sections of one prompt
+///
+/// Sections are fitted in the order they are added, so add them from the most
+/// to the least important. Whatever doesn't fit is dropped and logged.
+#[derive(Debug)]
+pub struct PromptBudget {
+    label: String,
+    limit: usize,
+    used: usize,
+}
+
+impl PromptBudget {
+    /// `label` identifies the prompt in logs (e.g. `chat 1234`)
+    pub fn new(label: impl Into<String>, limit: usize) -> Self {
+        Self {
+            label: label.into(),
+            limit,
+            used: 0,
+        }
+    }
+
+    /// Estimated tokens taken so far
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.limit.saturating_sub(self.used)
+    }
+
+    /// Account for text that is always sent (rules, the user's message)
+    pub fn reserve(&mut self, section: &str, text: &str) {
+        self.reserve_tokens(section, estimate_tokens(text));
+    }
+
+    /// Account for content that is always sent but isn't text (e.g. images)
+    pub fn reserve_tokens(&mut self, section: &str, tokens: usize) {
+        self.used += tokens;
+        if self.used > self.limit {
+            tracing::warn!(
+                "Prompt for {} exceeds its budget of {} tokens with required section {} alone (~{} tokens)",
+                self.label,
+                self.limit,
+                section,
+                tokens
+            );
+        }
+    }
+
+    /// Keep as many items as fit, truncating the text of the first one that doesn't
+    /// (when enough room is left for it to be useful); the rest are dropped
+    ///
+    /// With `Keep::Last`, an item too large on its own (e.g. a long latest message)
+    /// is cut down to its end rather than dropping everything.
+    pub fn fit<T>(
+        &mut self,
+        section: &str,
+        items: Vec<T>,
+        keep: Keep,
+        text: impl Fn(&mut T) -> &mut String,
+    ) -> Vec<T> {
+        let total = items.len();
+        let mut kept = Vec::with_capacity(total);
+        let mut cut = 0;
+        let mut dropped_tokens = 0;
+
+        let ordered: Box<dyn Iterator<Item = T>> = match keep {
+            Keep::First => Box::new(items.into_iter()),
+            Keep::Last => Box::new(items.into_iter().rev()),
+        };
+
+        for mut item in ordered {
+            let content = text(&mut item);
+            let tokens = estimate_tokens(content);
+            // Once something is cut, later items go too so the kept ones stay contiguous
+            if cut == 0 && tokens <= self.remaining() {
+                self.used += tokens;
+                kept.push(item);
+                continue;
+            }
+
+            if cut == 0 && self.remaining() >= MIN_TRUNCATED_TOKENS {
+                let truncated = self.truncate(content, keep);
+                dropped_tokens += tokens.saturating_sub(estimate_tokens(&truncated));
+                *content = truncated;
+                kept.push(item);
+            } else {
+                dropped_tokens += tokens;
+            }
+            cut += 1;
+        }
+
+        if keep == Keep::Last {
+            kept.reverse();
+        }
+
+        if cut > 0 {
+            tracing::warn!(
+                "Prompt for {} over budget: cut {} of {} items from {} (~{} tokens dropped)",
+                self.label,
+                cut,
+                total,
+                section,
+                dropped_tokens
+            );
+        }
+        kept
+    }
+
+    /// Keep as many text blocks as fit, truncating the first one that doesn't
+    pub fn fit_blocks(&mut self, section: &str, blocks: Vec<String>, keep: Keep) -> Vec<String> {
+        self.fit(section, blocks, keep, |block| block)
+    }
+
+    /// Fit a single text, truncating it if needed
+    pub fn fit_text(&mut self, section: &str, text: String) -> String {
+        self.fit_blocks(section, vec![text], Keep::First)
+            .pop()
+            .unwrap_or_default()
+    }
+
+    /// Cut a block to the remaining room, keeping its start (or its end for `Keep::Last`)
+    fn truncate(&mut self, block: &str, keep: Keep) -> String {
+        let note_tokens = estimate_tokens(TRUNCATION_NOTE);
+        let chars = (self.remaining().saturating_sub(note_tokens)) * 4;
+
+        let truncated = match keep {
+            Keep::First => {
+                let start: String = block.chars().take(chars).collect();
+                format!("{}{}", start, TRUNCATION_NOTE)
+            }
+            Keep::Last => {
+                let skip = block.chars().count().saturating_sub(chars);
+                let end: String = block.chars().skip(skip).collect();
+                format!("{}\n{}", TRUNCATION_NOTE.trim_start(), end)
+            }
+        };
+
+        self.used += estimate_tokens(&truncated);
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_of(tokens: usize) -> String {
+        "abcd".repeat(tokens)
+    }
+
+    #[test]
+    fn keeps_everything_that_fits() {
+        let mut budget = PromptBudget::new("test", 100);
+        let kept = budget.fit_blocks("blocks", vec![text_of(30), text_of(30)], Keep::First);
+
+        assert_eq!(kept, vec![text_of(30), text_of(30)]);
+        assert_eq!(budget.used(), 60);
+    }
+
+    #[test]
+    fn keeps_the_newest_items_contiguous() {
+        let mut budget = PromptBudget::new("test", 100);
+        let items = vec![text_of(10), text_of(50), text_of(60)];
+        let kept = budget.fit("history", items, Keep::Last, |m| m);
+
+        // Only 40 tokens are left for the middle item: too little to truncate it
+        assert_eq!(kept, vec![text_of(60)]);
+        assert_eq!(budget.used(), 60);
+    }
+
+    #[test]
+    fn truncates_an_oversized_newest_item_to_its_end() {
+        let mut budget = PromptBudget::new("test", 1_000);
+        let latest = format!("{}{}", "a".repeat(8_000), "the question");
+        let kept = budget.fit("history", vec![text_of(10), latest], Keep::Last, |m| m);
+
+        assert_eq!(kept.len(), 1);
+        assert!(kept[0].starts_with(TRUNCATION_NOTE.trim_start()));
+        assert!(kept[0].ends_with("the question"));
+        assert!(budget.used() <= 1_000);
+    }
+
+    #[test]
+    fn truncates_the_first_block_that_does_not_fit() {
+        let mut budget = PromptBudget::new("test", 1_000);
+        let blocks = vec![text_of(500), format!("start{}", text_of(1_000)), text_of(10)];
+        let kept = budget.fit_blocks("documents", blocks, Keep::First);
+
+        assert_eq!(kept.len(), 2);
+        assert!(kept[1].starts_with("start"));
+        assert!(kept[1].ends_with(TRUNCATION_NOTE));
+        assert!(budget.used() <= 1_000);
+    }
+
+    #[test]
+    fn reserved_text_counts_against_the_budget() {
+        let mut budget = PromptBudget::new("test", 100);
+        budget.reserve("rules", &text_of(90));
+
+        assert_eq!(budget.remaining(), 10);
+        assert!(budget.fit_blocks("documents", vec![text_of(20)], Keep::First).is_empty());
+    }
+
+    #[test]
+    fn reserved_tokens_count_against_the_budget() {
+        let mut budget = PromptBudget::new("test", 1_000);
+        budget.reserve_tokens("images", 2 * 300);
+
+        assert_eq!(budget.remaining(), 400);
+    }
+
+    #[test]
+    fn estimates_four_characters_per_token() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+}
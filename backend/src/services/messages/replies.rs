@@ -0,0 +1,73 @@
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::storage::message_attachments;
+use crate::storage::messages::{self, MessageRow};
+use crate::storage::pagination::Keyed;
+
+use super::attachments;
+use super::chat::{self, ChatReply, ChatScope};
+
+/// Generate the content of a pending reply (started with `messages::begin_reply`)
+/// and store it, marking the reply failed if generation fails
+///
+/// A reply to a student message answers it with the history before it; a reply
+/// without a parent is the chat's welcome message. `images` are the `data:` URLs
+/// of the student message's images, when the caller already loaded them.
+pub async fn run_reply(
+    pool: &PgPool,
+    config: &Config,
+    scope: ChatScope<'_>,
+    reply: &MessageRow,
+    images: Option<Vec<String>>,
+) -> Result<MessageRow, async_graphql::Error> {
+    let generated = match generate_reply(pool, config, scope, reply, images).await {
+        Ok(generated) => generated,
+        Err(e) => {
+            tracing::warn!("Reply {} in chat {} failed: {}", reply.id, reply.chat_id, e.message);
+            if let Err(fail_error) = messages::fail_reply(pool, reply.id).await {
+                tracing::error!("Failed to mark reply {} as failed: {}", reply.id, fail_error.message);
+            }
+            return Err(e);
+        }
+    };
+
+    messages::complete_reply(pool, reply.id, &generated.content, &generated.generation, &generated.citations)
+        .await?
+        .ok_or_else(|| "The reply was discarded before it was finished".into())
+}
+
+async fn generate_reply(
+    pool: &PgPool,
+    config: &Config,
+    scope: ChatScope<'_>,
+    reply: &MessageRow,
+    images: Option<Vec<String>>,
+) -> Result<ChatReply, async_graphql::Error> {
+    let profile_id = scope.profile_id;
+    let parent = match reply.parent_id {
+        Some(parent_id) => messages::get_message_by_id(pool, profile_id, parent_id).await?,
+        None => None,
+    };
+
+    match parent {
+        Some(parent) if parent.role == "user" => {
+            let images = match images {
+                Some(images) => images,
+                None => {
+                    let rows =
+                        message_attachments::get_attachments_by_message_ids(pool, profile_id, &[parent.id]).await?;
+                    let loaded = attachments::load_images(config, &rows).await?;
+                    loaded.into_iter().map(|i| i.data_url).collect()
+                }
+            };
+
+            chat::process_message(pool, config, scope, &parent.content, &images, Some(parent.cursor())).await
+        }
+        Some(_) => Err("Only replies to the student or welcome messages can be generated".into()),
+        None => {
+            chat::generate_welcome_message(pool, config, profile_id, scope.session_id, scope.topic_name, scope.language)
+                .await
+        }
+    }
+}
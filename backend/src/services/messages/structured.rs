@@ -0,0 +1,255 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::templates::{self, TemplateName, TemplateVars};
+
+use super::ai_client::{AiTask, JsonRequest, OpenRouterClient};
+
+/// Follow-up requests asking the model to fix an answer that doesn't match the schema
+const MAX_REPAIR_ATTEMPTS: usize = 1;
+
+/// Keywords strict structured output modes reject; answers are still checked against them locally
+const UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "$schema",
+    "$defs",
+    "definitions",
+    "title",
+    "format",
+    "default",
+    "examples",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minItems",
+    "maxItems",
+    "minimum",
+    "maximum",
+];
+
+/// Deepest nesting of `$ref`s inlined (recursive types end in an unconstrained schema)
+const MAX_INLINE_DEPTH: usize = 16;
+
+/// Ask the model for JSON matching `T`'s schema, validating the answer and
+/// feeding any problems back to the model for a repaired answer
+pub async fn request_json<T: JsonSchema + DeserializeOwned>(
+    client: &OpenRouterClient,
+    task: AiTask,
+    model: &str,
+    system_prompt: &str,
+    user_message: &str,
+) -> Result<T, async_graphql::Error> {
+    let schema = schemars::schema_for!(T).to_value();
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| async_graphql::Error::new(format!("Invalid response schema: {}", e)))?;
+    let schema_name = T::schema_name();
+    let request_schema = strict_schema(&schema);
+
+    let mut history = Vec::new();
+    let mut message = user_message.to_string();
+    let mut attempt = 0;
+    loop {
+        let request = JsonRequest {
+            system_prompt,
+            history: &history,
+            user_message: &message,
+            schema_name: &schema_name,
+            schema: &request_schema,
+        };
+        let answer = client.chat_json(task, model, request).await?;
+
+        let problems = match parse_answer(&validator, &answer.content) {
+            Ok(parsed) => return Ok(parsed),
+            Err(problems) => problems,
+        };
+
+        if attempt == MAX_REPAIR_ATTEMPTS {
+            tracing::error!(
+                "AI {} answer from {} doesn't match {} after {} repairs: {}",
+                task.as_str(),
+                answer.model,
+                schema_name,
+                attempt,
+                problems
+            );
+            return Err("The AI returned an answer in an unexpected format, please try again".into());
+        }
+        tracing::warn!(
+            "AI {} answer from {} doesn't match {}, asking for a repair: {}",
+            task.as_str(),
+            answer.model,
+            schema_name,
+            problems
+        );
+
+        let schema_text = serde_json::to_string_pretty(&schema)
+            .map_err(|e| async_graphql::Error::new(format!("JSON serialization error: {}", e)))?;
+        let vars = TemplateVars::new().set("errors", problems).set("schema", schema_text);
+        let repair = templates::render(TemplateName::RepairJson, None, &vars)?;
+
+        history.push(("user".to_string(), message));
+        history.push(("assistant".to_string(), answer.content));
+        message = repair.text;
+        attempt += 1;
+    }
+}
+
+/// Parse an answer as JSON (unwrapping a markdown code block if the model added
+/// one) and check it against the schema, describing every problem found
+fn parse_answer<T: DeserializeOwned>(validator: &jsonschema::Validator, answer: &str) -> Result<T, String> {
+    let value: Value = serde_json::from_str(strip_code_fence(answer))
+        .map_err(|e| format!("The answer is not valid JSON: {}", e))?;
+
+    let problems: Vec<String> = validator
+        .iter_errors(&value)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            format!("- at `{}`: {}", if path.is_empty() { "/" } else { &path }, e)
+        })
+        .collect();
+    if !problems.is_empty() {
+        return Err(problems.join("\n"));
+    }
+
+    serde_json::from_value(value).map_err(|e| format!("- {}", e))
+}
+
+fn strip_code_fence(answer: &str) -> &str {
+    let trimmed = answer.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    let rest = rest.strip_prefix("json").unwrap_or(rest);
+    rest.strip_suffix("```").unwrap_or(rest).trim()
+}
+
+/// The schema in the subset strict structured output accepts: `$ref`s inlined,
+/// unsupported keywords removed, and every object closed with all its properties
+/// required (optional fields stay nullable, so the model answers `null`)
+fn strict_schema(schema: &Value) -> Value {
+    let empty = Map::new();
+    let defs = schema
+        .get("$defs")
+        .or_else(|| schema.get("definitions"))
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    strict_subschema(schema, defs, 0)
+}
+
+fn strict_subschema(schema: &Value, defs: &Map<String, Value>, depth: usize) -> Value {
+    let Some(object) = schema.as_object() else {
+        return schema.clone();
+    };
+
+    if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+        let name = reference.rsplit('/').next().unwrap_or_default();
+        return match defs.get(name) {
+            Some(target) if depth < MAX_INLINE_DEPTH => strict_subschema(target, defs, depth + 1),
+            _ => Value::Object(Map::new()),
+        };
+    }
+
+    let mut strict = Map::new();
+    for (key, value) in object {
+        if UNSUPPORTED_KEYWORDS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = match (key.as_str(), value) {
+            // Maps of property names to schemas: keep the names, convert the schemas
+            ("properties", Value::Object(properties)) => Value::Object(
+                properties
+                    .iter()
+                    .map(|(name, property)| (name.clone(), strict_subschema(property, defs, depth)))
+                    .collect(),
+            ),
+            ("items" | "additionalProperties", Value::Object(_)) => strict_subschema(value, defs, depth),
+            ("anyOf" | "oneOf" | "allOf" | "prefixItems", Value::Array(schemas)) => {
+                Value::Array(schemas.iter().map(|s| strict_subschema(s, defs, depth)).collect())
+            }
+            _ => value.clone(),
+        };
+        strict.insert(key.clone(), value);
+    }
+
+    if let Some(Value::Object(properties)) = strict.get("properties") {
+        let required = properties.keys().cloned().map(Value::String).collect();
+        strict.insert("required".to_string(), Value::Array(required));
+        strict.insert("additionalProperties".to_string(), Value::Bool(false));
+    }
+
+    Value::Object(strict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::planning::StudyPlanContent;
+
+    fn plan_validator() -> jsonschema::Validator {
+        jsonschema::validator_for(&schemars::schema_for!(StudyPlanContent).to_value()).unwrap()
+    }
+
+    #[test]
+    fn strips_code_fences() {
+        assert_eq!(strip_code_fence("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(strip_code_fence("```\n[1]\n```"), "[1]");
+        assert_eq!(strip_code_fence("  {\"a\": 1}  "), "{\"a\": 1}");
+        assert_eq!(strip_code_fence("```json\n{}"), "{}");
+    }
+
+    #[test]
+    fn parses_an_answer_matching_the_schema() {
+        let answer = r#"```json
+        {"topics": [{"id": "t1", "title": "Limits", "description": "", "status": "need_review"}]}
+        ```"#;
+        let plan: StudyPlanContent = parse_answer(&plan_validator(), answer).unwrap();
+
+        assert_eq!(plan.topics.len(), 1);
+        assert_eq!(plan.topics[0].title, "Limits");
+    }
+
+    #[test]
+    fn describes_every_problem_with_an_answer() {
+        let answer = r#"{"topics": [{"id": "", "title": "Limits", "description": "", "status": "unknown"}]}"#;
+        let problems = parse_answer::<StudyPlanContent>(&plan_validator(), answer).unwrap_err();
+
+        assert!(problems.contains("/topics/0/id"), "{}", problems);
+        assert!(problems.contains("/topics/0/status"), "{}", problems);
+        assert_eq!(problems.lines().count(), 2);
+    }
+
+    #[test]
+    fn rejects_answers_that_are_not_json() {
+        let problems = parse_answer::<StudyPlanContent>(&plan_validator(), "Here is your plan").unwrap_err();
+
+        assert!(problems.starts_with("The answer is not valid JSON"));
+    }
+
+    #[test]
+    fn strict_schema_inlines_definitions_and_drops_unsupported_keywords() {
+        let schema = strict_schema(&schemars::schema_for!(StudyPlanContent).to_value());
+        let text = schema.to_string();
+
+        for keyword in ["$ref", "$defs", "$schema", "minLength", "minItems"] {
+            assert!(!text.contains(keyword), "{} left in {}", keyword, text);
+        }
+        let topic = &schema["properties"]["topics"]["items"];
+        // A property named like a keyword is kept
+        assert!(topic["properties"]["title"].is_object());
+        assert_eq!(topic["additionalProperties"], Value::Bool(false));
+        assert_eq!(topic["required"].as_array().unwrap().len(), 4);
+        assert_eq!(schema["required"], serde_json::json!(["topics"]));
+    }
+
+    #[test]
+    fn strict_schema_requires_optional_fields() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"note": {"type": ["string", "null"]}, "count": {"type": "integer", "minimum": 0}},
+        });
+        let strict = strict_schema(&schema);
+
+        assert_eq!(strict["required"], serde_json::json!(["count", "note"]));
+        assert_eq!(strict["properties"]["count"], serde_json::json!({"type": "integer"}));
+    }
+}
@@ -1,14 +1,18 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::config::Config;
-use crate::prompts::{GENERATE_PLAN_PROMPT, REVISE_PLAN_PROMPT};
-use crate::services::messages::ai_client::OpenRouterClient;
-use crate::storage::documents;
+use crate::services::messages::ai_client::{AiTask, OpenRouterClient};
+use crate::services::messages::prompt_budget::{Keep, PromptBudget};
+use crate::services::messages::structured;
+use crate::storage::documents::{self, DocumentText};
 use crate::storage::sessions::DraftPlan;
+use crate::templates::{self, TemplateName, TemplateVars};
 
 const PLANNING_MODEL: &str = "google/gemini-2.5-flash";
+const PLANNING_SYSTEM_PROMPT: &str = "You are a helpful academic tutor. Output valid JSON only.";
 
 /// Convert language code to full language name
 fn language_name(code: &str) -> &str {
@@ -22,17 +26,25 @@ fn language_name(code: &str) -> &str {
     }
 }
 
-/// Internal structure for AI responses (matches the JSON format)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A topic of a study plan, as the AI returns it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct StudyPlanTopic {
+    #[schemars(length(min = 1))]
     pub id: String,
+    #[schemars(length(min = 1))]
     pub title: String,
     pub description: String,
-    pub status: String, // "need_to_learn", "need_review", "know_well"
+    #[schemars(extend("enum" = ["need_to_learn", "need_review", "know_well"]))]
+    pub status: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A study plan, as the AI returns it (its JSON schema is sent to the model and
+/// the answer is validated against it)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct StudyPlanContent {
+    #[schemars(length(min = 1))]
     pub topics: Vec<StudyPlanTopic>,
 }
 
@@ -55,34 +67,27 @@ pub async fn generate_study_plan(
         ));
     }
 
-    // Build materials context
-    let context = doc_texts
-        .iter()
-        .map(|(name, content)| format!("=== {} ===\n{}", name, content))
-        .collect::<Vec<_>>()
-        .join("\n\n---\n\n");
-
-    // Build the prompt
-    let lang = language_name(language);
-    let prompt = GENERATE_PLAN_PROMPT
-        .replace("{context}", &context)
-        .replace("{title}", session_title)
-        .replace("{description}", session_description.unwrap_or("No description provided"))
-        .replace("{language}", lang);
+    // Build the prompt, fitting as much material as the model's budget allows
+    let vars = TemplateVars::new()
+        .set("title", session_title)
+        .set("description", session_description.unwrap_or("No description provided"))
+        .set("language", language_name(language));
+    let rules = templates::render(TemplateName::GeneratePlan, Some(language), &vars.clone().set("context", ""))?;
+
+    let mut budget = PromptBudget::new(
+        format!("study plan of session {}", session_id),
+        config.prompt_token_budget(PLANNING_MODEL),
+    );
+    budget.reserve("rules", &rules.text);
+    let context = fit_documents(&mut budget, &doc_texts);
+    let prompt = templates::render(TemplateName::GeneratePlan, Some(language), &vars.set("context", context))?;
 
     // Debug logging: show the final system prompt
-    tracing::debug!("Final plan generation prompt: {}", prompt);
-
-    // Call AI
-    let ai_client = OpenRouterClient::new(config.openrouter_api_key.clone());
-    let ai_response = ai_client
-        .chat(PLANNING_MODEL, "You are a helpful academic tutor. Output valid JSON only.", &prompt)
-        .await?;
-
-    // Parse JSON response
-    let plan_content = parse_json_response(&ai_response)?;
+    tracing::debug!("Final plan generation prompt ({}): {}", prompt.version, prompt.text);
 
-    Ok(plan_content)
+    // Call AI, validating the plan it returns
+    let ai_client = OpenRouterClient::new(config);
+    structured::request_json(&ai_client, AiTask::Planning, PLANNING_MODEL, PLANNING_SYSTEM_PROMPT, &prompt.text).await
 }
 
 /// Revise an existing study plan based on user instruction
@@ -97,16 +102,6 @@ pub async fn revise_study_plan(
 ) -> Result<StudyPlanContent, async_graphql::Error> {
     // Fetch document texts for context
     let doc_texts = documents::get_session_document_texts(pool, profile_id, session_id).await?;
-    
-    let context = if doc_texts.is_empty() {
-        "No study materials available.".to_string()
-    } else {
-        doc_texts
-            .iter()
-            .map(|(name, content)| format!("=== {} ===\n{}", name, content))
-            .collect::<Vec<_>>()
-            .join("\n\n---\n\n")
-    };
 
     // Convert DraftPlan to StudyPlanContent for serialization
     let current_content = StudyPlanContent {
@@ -122,54 +117,42 @@ pub async fn revise_study_plan(
     let current_plan_json = serde_json::to_string_pretty(&current_content)
         .map_err(|e| async_graphql::Error::new(format!("JSON serialization error: {}", e)))?;
 
-    // Build the revision prompt
-    let lang = language_name(language);
-    let prompt = REVISE_PLAN_PROMPT
-        .replace("{current_plan}", &current_plan_json)
-        .replace("{instruction}", instruction)
-        .replace("{context}", &context)
-        .replace("{language}", lang);
+    // Build the revision prompt, fitting as much material as the model's budget allows
+    let vars = TemplateVars::new()
+        .set("current_plan", current_plan_json)
+        .set("instruction", instruction)
+        .set("language", language_name(language));
+    let rules = templates::render(TemplateName::RevisePlan, Some(language), &vars.clone().set("context", ""))?;
+
+    let mut budget = PromptBudget::new(
+        format!("plan revision of session {}", session_id),
+        config.prompt_token_budget(PLANNING_MODEL),
+    );
+    budget.reserve("rules", &rules.text);
+    let context = if doc_texts.is_empty() {
+        "No study materials available.".to_string()
+    } else {
+        fit_documents(&mut budget, &doc_texts)
+    };
+    let prompt = templates::render(TemplateName::RevisePlan, Some(language), &vars.set("context", context))?;
 
     // Debug logging: show the final system prompt
-    tracing::debug!("Final plan revision prompt: {}", prompt);
-
-    // Call AI
-    let ai_client = OpenRouterClient::new(config.openrouter_api_key.clone());
-    let ai_response = ai_client
-        .chat(PLANNING_MODEL, "You are a helpful academic tutor. Output valid JSON only.", &prompt)
-        .await?;
+    tracing::debug!("Final plan revision prompt ({}): {}", prompt.version, prompt.text);
 
-    // Parse JSON response
-    let revised_plan = parse_json_response(&ai_response)?;
-
-    Ok(revised_plan)
+    // Call AI, validating the plan it returns
+    let ai_client = OpenRouterClient::new(config);
+    structured::request_json(&ai_client, AiTask::Planning, PLANNING_MODEL, PLANNING_SYSTEM_PROMPT, &prompt.text).await
 }
 
-/// Helper function to parse JSON response from AI (handles markdown code blocks)
-fn parse_json_response(response: &str) -> Result<StudyPlanContent, async_graphql::Error> {
-    // Try to extract JSON from markdown code blocks if present
-    let json_str = if response.contains("```") {
-        // Extract content between ```json and ``` or just ``` and ```
-        response
-            .split("```")
-            .nth(1)
-            .and_then(|s| {
-                // Remove language identifier if present
-                if s.starts_with("json") {
-                    Some(s.trim_start_matches("json").trim())
-                } else {
-                    Some(s.trim())
-                }
-            })
-            .unwrap_or(response)
-    } else {
-        response.trim()
-    };
+/// Join the session's documents into prompt context, most important first,
+/// cutting whatever doesn't fit the budget
+fn fit_documents(budget: &mut PromptBudget, doc_texts: &[DocumentText]) -> String {
+    let blocks = doc_texts
+        .iter()
+        .map(|d| format!("=== {} ===\n{}", d.label(), d.content_text))
+        .collect();
 
-    // Parse JSON
-    serde_json::from_str::<StudyPlanContent>(json_str)
-        .map_err(|e| {
-            tracing::error!("Failed to parse AI response as JSON: {}\nResponse was: {}", e, json_str);
-            async_graphql::Error::new(format!("Failed to parse AI response as JSON: {}", e))
-        })
+    budget
+        .fit_blocks("documents", blocks, Keep::First)
+        .join("\n\n---\n\n")
 }
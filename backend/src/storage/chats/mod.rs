@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::pagination::Cursor;
+
 /// Chat type enum matching the database
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
 #[sqlx(type_name = "chat_type")]
@@ -34,6 +36,26 @@ pub struct ChatRow {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Rolling summary of a chat's older messages
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ChatSummaryRow {
+    pub summary: Option<String>,
+    pub summary_through_at: Option<DateTime<Utc>>,
+    pub summary_through_id: Option<Uuid>,
+    /// Bumped whenever messages leave the chat's history
+    pub history_generation: i32,
+}
+
+impl ChatSummaryRow {
+    /// Newest message the summary covers
+    pub fn through(&self) -> Option<Cursor> {
+        Some(Cursor {
+            created_at: self.summary_through_at?,
+            id: self.summary_through_id?,
+        })
+    }
+}
+
 /// Create a topic-specific chat
 pub async fn create_topic_chat(
     pool: &PgPool,
@@ -146,6 +168,29 @@ pub async fn get_chat_by_topic(
     Ok(chat)
 }
 
+/// Get the chats for several topics at once (with authorization check)
+pub async fn get_chats_by_topic_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    topic_ids: &[Uuid],
+) -> Result<Vec<ChatRow>, async_graphql::Error> {
+    let chats = sqlx::query_as::<_, ChatRow>(
+        r#"
+        SELECT c.id, c.session_id, c.type, c.topic_id, c.is_started, c.created_at, c.updated_at
+        FROM chats c
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE c.topic_id = ANY($1) AND s.profile_id = $2
+        "#,
+    )
+    .bind(topic_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(chats)
+}
+
 /// Get the review chat for a session (with authorization check)
 pub async fn get_review_chat(
     pool: &PgPool,
@@ -194,3 +239,59 @@ pub async fn mark_chat_started(
 }
 
 
+
+/// Get the rolling summary of a chat (with authorization check)
+pub async fn get_chat_summary(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_id: Uuid,
+) -> Result<Option<ChatSummaryRow>, async_graphql::Error> {
+    let summary = sqlx::query_as::<_, ChatSummaryRow>(
+        r#"
+        SELECT c.summary, c.summary_through_at, c.summary_through_id, c.history_generation
+        FROM chats c
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE c.id = $1 AND s.profile_id = $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(summary)
+}
+
+/// Store a new rolling summary, unless another update moved the summary on or the
+/// messages were cleared since `previous` was read. Returns whether the summary was stored.
+pub async fn update_chat_summary(
+    pool: &PgPool,
+    chat_id: Uuid,
+    summary: &str,
+    through: Cursor,
+    previous: &ChatSummaryRow,
+) -> Result<bool, async_graphql::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE chats
+        SET summary = $2, summary_through_at = $3, summary_through_id = $4
+        WHERE id = $1
+          AND summary_through_at IS NOT DISTINCT FROM $5
+          AND summary_through_id IS NOT DISTINCT FROM $6
+          AND history_generation = $7
+        "#,
+    )
+    .bind(chat_id)
+    .bind(summary)
+    .bind(through.created_at)
+    .bind(through.id)
+    .bind(previous.summary_through_at)
+    .bind(previous.summary_through_id)
+    .bind(previous.history_generation)
+    .execute(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,317 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Rebuilds `documents.content_text` from its pages, in the format produced by extraction
+const REBUILD_CONTENT_SQL: &str = r#"
+    UPDATE documents d
+    SET content_text = p.text, content_length = LENGTH(p.text)
+    FROM (
+        SELECT string_agg('--- Page ' || page_number || E' ---\n' || content_text, E'\n\n' ORDER BY page_number) AS text
+        FROM document_pages
+        WHERE document_id = $1
+    ) p
+    WHERE d.id = $1 AND p.text IS NOT NULL
+"#;
+
+/// Puts each page's newest student correction back over freshly extracted text
+const REAPPLY_EDITS_SQL: &str = r#"
+    UPDATE document_pages p
+    SET content_text = e.new_text
+    FROM (
+        SELECT DISTINCT ON (page_number) page_number, new_text
+        FROM document_page_edits
+        WHERE document_id = $1
+        ORDER BY page_number, created_at DESC, id DESC
+    ) e
+    WHERE p.document_id = $1 AND p.page_number = e.page_number
+"#;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DocumentPageRow {
+    pub document_id: Uuid,
+    pub page_number: i32,
+    pub content_text: String,
+    pub thumbnail_path: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DocumentPageEditRow {
+    pub id: Uuid,
+    pub document_id: Uuid,
+    pub page_number: i32,
+    pub previous_text: String,
+    pub new_text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Replace a document's pages with a fresh extraction and mark it completed
+/// (`thumbnails` holds the storage path of each page thumbnail, when one was stored;
+/// pages a student corrected keep their newest correction)
+pub async fn save_extraction(
+    pool: &PgPool,
+    document_id: Uuid,
+    pages: &[String],
+    thumbnails: &[Option<String>],
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+    let page_numbers: Vec<i32> = (1..=pages.len() as i32).collect();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query("DELETE FROM document_pages WHERE document_id = $1")
+        .bind(document_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO document_pages (document_id, page_number, content_text, thumbnail_path)
+        SELECT $1, page_number, content_text, thumbnail_path
+        FROM UNNEST($2::int[], $3::text[], $4::text[]) AS p(page_number, content_text, thumbnail_path)
+        "#,
+    )
+    .bind(document_id)
+    .bind(&page_numbers)
+    .bind(pages)
+    .bind(thumbnails)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    sqlx::query(REAPPLY_EDITS_SQL)
+        .bind(document_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query(REBUILD_CONTENT_SQL)
+        .bind(document_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        UPDATE documents
+        SET processing_status = 'COMPLETED', processing_error = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(document_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Storage paths of a document's page thumbnails, by page number
+pub async fn get_thumbnail_paths(
+    pool: &PgPool,
+    document_id: Uuid,
+) -> Result<Vec<(i32, String)>, async_graphql::Error> {
+    let paths = sqlx::query_as::<_, (i32, String)>(
+        r#"
+        SELECT page_number, thumbnail_path
+        FROM document_pages
+        WHERE document_id = $1 AND thumbnail_path IS NOT NULL
+        ORDER BY page_number ASC
+        "#,
+    )
+    .bind(document_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(paths)
+}
+
+/// Copy the extraction (pages and text) of one document onto another and mark it completed
+///
+/// Thumbnails belong to the source document's storage folder, so the target only
+/// gets the `thumbnails` (page number and path) already copied into its own.
+pub async fn copy_extraction(
+    pool: &PgPool,
+    source_id: Uuid,
+    target_id: Uuid,
+    thumbnails: &[(i32, String)],
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+    let (page_numbers, paths): (Vec<i32>, Vec<String>) = thumbnails.iter().cloned().unzip();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query("DELETE FROM document_pages WHERE document_id = $1")
+        .bind(target_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO document_pages (document_id, page_number, content_text, thumbnail_path)
+        SELECT $2, p.page_number, p.content_text, t.thumbnail_path
+        FROM document_pages p
+        LEFT JOIN UNNEST($3::int[], $4::text[]) AS t(page_number, thumbnail_path)
+            ON t.page_number = p.page_number
+        WHERE p.document_id = $1
+        "#,
+    )
+    .bind(source_id)
+    .bind(target_id)
+    .bind(&page_numbers)
+    .bind(&paths)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        UPDATE documents t
+        SET content_text = s.content_text, content_length = s.content_length,
+            document_type = s.document_type, course_name = s.course_name, year = s.year,
+            processing_status = 'COMPLETED', processing_error = NULL
+        FROM documents s
+        WHERE t.id = $2 AND s.id = $1
+        "#,
+    )
+    .bind(source_id)
+    .bind(target_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Get the pages of several documents at once, in page order (with authorization check)
+pub async fn get_pages_by_document_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    document_ids: &[Uuid],
+) -> Result<Vec<DocumentPageRow>, async_graphql::Error> {
+    let pages = sqlx::query_as::<_, DocumentPageRow>(
+        r#"
+        SELECT p.document_id, p.page_number, p.content_text, p.thumbnail_path, p.updated_at
+        FROM document_pages p
+        JOIN documents d ON p.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE p.document_id = ANY($1) AND s.profile_id = $2
+        ORDER BY p.page_number ASC
+        "#,
+    )
+    .bind(document_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(pages)
+}
+
+/// Get the page edit history of several documents at once, newest first
+/// (with authorization check)
+pub async fn get_edits_by_document_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    document_ids: &[Uuid],
+) -> Result<Vec<DocumentPageEditRow>, async_graphql::Error> {
+    let edits = sqlx::query_as::<_, DocumentPageEditRow>(
+        r#"
+        SELECT e.id, e.document_id, e.page_number, e.previous_text, e.new_text, e.created_at
+        FROM document_page_edits e
+        JOIN documents d ON e.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE e.document_id = ANY($1) AND s.profile_id = $2
+        ORDER BY e.created_at DESC, e.id DESC
+        "#,
+    )
+    .bind(document_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(edits)
+}
+
+/// Correct the text of one page, recording the edit and rebuilding the document text
+/// (with authorization check; returns false if the page doesn't exist)
+pub async fn update_page_text(
+    pool: &PgPool,
+    profile_id: Uuid,
+    document_id: Uuid,
+    page_number: i32,
+    text: &str,
+) -> Result<bool, async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let previous = sqlx::query_as::<_, (String,)>(
+        r#"
+        SELECT p.content_text
+        FROM document_pages p
+        JOIN documents d ON p.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE p.document_id = $1 AND p.page_number = $2 AND s.profile_id = $3
+        FOR UPDATE OF p
+        "#,
+    )
+    .bind(document_id)
+    .bind(page_number)
+    .bind(profile_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let Some((previous_text,)) = previous else {
+        return Ok(false);
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO document_page_edits (document_id, page_number, profile_id, previous_text, new_text)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(document_id)
+    .bind(page_number)
+    .bind(profile_id)
+    .bind(&previous_text)
+    .bind(text)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        UPDATE document_pages
+        SET content_text = $3, updated_at = NOW()
+        WHERE document_id = $1 AND page_number = $2
+        "#,
+    )
+    .bind(document_id)
+    .bind(page_number)
+    .bind(text)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    sqlx::query(REBUILD_CONTENT_SQL)
+        .bind(document_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(true)
+}
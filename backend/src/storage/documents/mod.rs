@@ -3,6 +3,11 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::pagination::{Cursor, Keyed, Page, PageRequest, SortOrder};
+
+/// Error message when a session already holds a document with the same content
+pub const DUPLICATE_CONTENT: &str = "This file was already uploaded to this session";
+
 /// Processing status enum matching the database
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
 #[sqlx(type_name = "processing_status", rename_all = "UPPERCASE")]
@@ -24,37 +29,204 @@ impl std::fmt::Display for ProcessingStatus {
     }
 }
 
+/// Kind of study material, matching the database enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "document_type", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DocumentType {
+    Slides,
+    Notes,
+    PastExam,
+    ProblemSet,
+    TextbookChapter,
+    Syllabus,
+    /// Also what any type this enum doesn't know deserializes to (e.g. a model's guess)
+    #[serde(other)]
+    Other,
+}
+
+impl DocumentType {
+    /// Human-readable label used in prompts
+    pub fn label(&self) -> &'static str {
+        match self {
+            DocumentType::Slides => "Lecture slides",
+            DocumentType::Notes => "Notes",
+            DocumentType::PastExam => "Past exam",
+            DocumentType::ProblemSet => "Problem set",
+            DocumentType::TextbookChapter => "Textbook chapter",
+            DocumentType::Syllabus => "Syllabus",
+            DocumentType::Other => "Other material",
+        }
+    }
+}
+
+/// Classification and metadata detected during ingestion
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentClassification {
+    pub document_type: Option<DocumentType>,
+    pub course_name: Option<String>,
+    pub year: Option<i32>,
+}
+
+/// Extracted text of a document with the labels used to present it to the AI
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DocumentText {
+    pub id: Uuid,
+    pub file_name: String,
+    pub document_type: Option<DocumentType>,
+    pub course_name: Option<String>,
+    pub year: Option<i32>,
+    pub content_text: String,
+}
+
+impl DocumentText {
+    /// Header naming the document and what kind of source it is,
+    /// e.g. `exam-2022.pdf (Past exam, Calculus I, 2022)`
+    pub fn label(&self) -> String {
+        let details: Vec<String> = [
+            self.document_type.map(|t| t.label().to_string()),
+            self.course_name.clone(),
+            self.year.map(|y| y.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if details.is_empty() {
+            self.file_name.clone()
+        } else {
+            format!("{} ({})", self.file_name, details.join(", "))
+        }
+    }
+}
+
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct DocumentRow {
     pub id: Uuid,
     pub session_id: Uuid,
     pub file_name: String,
     pub file_path: String,
+    pub file_size: Option<i64>,
     pub content_length: Option<i32>,
+    pub document_type: Option<DocumentType>,
+    pub course_name: Option<String>,
+    pub year: Option<i32>,
     pub processing_status: ProcessingStatus,
+    pub processing_error: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
-/// Create a new document (with pending processing status)
-pub async fn create_document(
+impl Keyed for DocumentRow {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+/// Owner, name and content hash of a document
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DocumentOrigin {
+    pub profile_id: Uuid,
+    pub session_id: Uuid,
+    pub file_name: String,
+    pub content_hash: Option<String>,
+}
+
+/// Whether a document already records the file at `file_path`
+pub async fn document_exists_for_path(pool: &PgPool, file_path: &str) -> Result<bool, async_graphql::Error> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM documents WHERE file_path = $1)")
+        .bind(file_path)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(exists)
+}
+
+/// A document to record for an uploaded file
+#[derive(Debug, Clone, Copy)]
+pub struct NewDocument<'a> {
+    pub session_id: Uuid,
+    pub file_name: &'a str,
+    pub file_path: &'a str,
+    pub file_size: i64,
+    pub content_hash: Option<&'a str>,
+}
+
+/// Create a new document (with pending processing status) if `check` accepts the
+/// profile's tier and its profile and session usage in bytes
+///
+/// The profile row stays locked from the usage query to the insert, so concurrent
+/// uploads can't each pass the check and exceed a quota together.
+pub async fn create_document_within_quota<E>(
     pool: &PgPool,
+    profile_id: Uuid,
+    document: &NewDocument<'_>,
+    check: impl FnOnce(&str, i64, i64) -> Result<(), E>,
+) -> Result<Result<DocumentRow, E>, async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let tier: Option<String> = sqlx::query_scalar("SELECT tier FROM profiles WHERE id = $1 FOR UPDATE")
+        .bind(profile_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let tier = tier.unwrap_or_else(|| "free".to_string());
+
+    let (profile_bytes, session_bytes) =
+        get_storage_usage(&mut *tx, profile_id, Some(document.session_id)).await?;
+    if let Err(rejection) = check(&tier, profile_bytes, session_bytes) {
+        return Ok(Err(rejection));
+    }
+
+    let row = insert_document(
+        &mut tx,
+        document.session_id,
+        document.file_name,
+        document.file_path,
+        document.file_size,
+        document.content_hash,
+    )
+    .await?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(Ok(row))
+}
+
+/// Insert a new document (with pending processing status)
+async fn insert_document(
+    conn: &mut sqlx::PgConnection,
     session_id: Uuid,
     file_name: &str,
     file_path: &str,
+    file_size: i64,
+    content_hash: Option<&str>,
 ) -> Result<DocumentRow, async_graphql::Error> {
     let document = sqlx::query_as::<_, DocumentRow>(
         r#"
-        INSERT INTO documents (session_id, file_name, file_path, processing_status)
-        VALUES ($1, $2, $3, 'PENDING')
-        RETURNING id, session_id, file_name, file_path, content_length, processing_status, created_at
+        INSERT INTO documents (session_id, file_name, file_path, file_size, content_hash, processing_status)
+        VALUES ($1, $2, $3, $4, $5, 'PENDING')
+        RETURNING id, session_id, file_name, file_path, file_size, content_length,
+                  document_type, course_name, year, processing_status, processing_error, created_at
         "#,
     )
     .bind(session_id)
     .bind(file_name)
     .bind(file_path)
-    .fetch_one(pool)
+    .bind(file_size)
+    .bind(content_hash)
+    .fetch_one(conn)
     .await
-    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+    .map_err(|e| match e.as_database_error().and_then(|d| d.constraint()) {
+        Some("idx_documents_file_path") => async_graphql::Error::new("This upload was already completed"),
+        Some("idx_documents_session_content_hash") => async_graphql::Error::new(DUPLICATE_CONTENT),
+        _ => async_graphql::Error::new(format!("Database error: {}", e)),
+    })?;
 
     Ok(document)
 }
@@ -81,25 +253,72 @@ pub async fn update_document_status(
     Ok(())
 }
 
-/// Update document after extraction
-pub async fn update_document_content(
+/// Queue a finished (completed or failed) document for processing again
+/// (with authorization check; returns None if not found or still processing)
+pub async fn reset_document_for_reprocessing(
     pool: &PgPool,
+    profile_id: Uuid,
     document_id: Uuid,
-    content_text: &str,
-    status: ProcessingStatus,
-) -> Result<(), async_graphql::Error> {
-    let content_length = content_text.len() as i32;
+) -> Result<Option<DocumentRow>, async_graphql::Error> {
+    let document = sqlx::query_as::<_, DocumentRow>(
+        r#"
+        UPDATE documents d
+        SET processing_status = 'PENDING', processing_error = NULL
+        FROM study_sessions s
+        WHERE d.session_id = s.id AND d.id = $1 AND s.profile_id = $2
+          AND d.processing_status IN ('COMPLETED', 'FAILED')
+        RETURNING d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+                  d.document_type, d.course_name, d.year,
+                  d.processing_status, d.processing_error, d.created_at
+        "#,
+    )
+    .bind(document_id)
+    .bind(profile_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(document)
+}
 
+/// Store the classification detected for a document
+pub async fn set_document_classification(
+    pool: &PgPool,
+    document_id: Uuid,
+    classification: &DocumentClassification,
+) -> Result<(), async_graphql::Error> {
     sqlx::query(
         r#"
-        UPDATE documents 
-        SET content_text = $1, content_length = $2, processing_status = $3
+        UPDATE documents
+        SET document_type = $1, course_name = $2, year = $3
         WHERE id = $4
         "#,
     )
-    .bind(content_text)
-    .bind(content_length)
-    .bind(status)
+    .bind(classification.document_type)
+    .bind(&classification.course_name)
+    .bind(classification.year)
+    .bind(document_id)
+    .execute(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Mark a document as failed, recording the reason shown to the user
+pub async fn mark_document_failed(
+    pool: &PgPool,
+    document_id: Uuid,
+    reason: &str,
+) -> Result<(), async_graphql::Error> {
+    sqlx::query(
+        r#"
+        UPDATE documents
+        SET processing_status = 'FAILED', processing_error = $1
+        WHERE id = $2
+        "#,
+    )
+    .bind(reason)
     .bind(document_id)
     .execute(pool)
     .await
@@ -116,8 +335,9 @@ pub async fn get_session_documents(
 ) -> Result<Vec<DocumentRow>, async_graphql::Error> {
     let documents = sqlx::query_as::<_, DocumentRow>(
         r#"
-        SELECT d.id, d.session_id, d.file_name, d.file_path, d.content_length, 
-               d.processing_status, d.created_at
+        SELECT d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+               d.document_type, d.course_name, d.year,
+               d.processing_status, d.processing_error, d.created_at
         FROM documents d
         JOIN study_sessions s ON d.session_id = s.id
         WHERE d.session_id = $1 AND s.profile_id = $2
@@ -133,6 +353,65 @@ pub async fn get_session_documents(
     Ok(documents)
 }
 
+/// Get documents for several sessions at once, newest first (with authorization check)
+pub async fn get_documents_by_session_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_ids: &[Uuid],
+) -> Result<Vec<DocumentRow>, async_graphql::Error> {
+    let documents = sqlx::query_as::<_, DocumentRow>(
+        r#"
+        SELECT d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+               d.document_type, d.course_name, d.year,
+               d.processing_status, d.processing_error, d.created_at
+        FROM documents d
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.session_id = ANY($1) AND s.profile_id = $2
+        ORDER BY d.created_at DESC
+        "#,
+    )
+    .bind(session_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(documents)
+}
+
+/// Get a page of documents for a session, newest first (with authorization check)
+pub async fn get_session_documents_page(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_id: Uuid,
+    page: &PageRequest,
+) -> Result<Page<DocumentRow>, async_graphql::Error> {
+    let query = format!(
+        r#"
+        SELECT d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+               d.document_type, d.course_name, d.year,
+               d.processing_status, d.processing_error, d.created_at
+        FROM documents d
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.session_id = $1 AND s.profile_id = $2
+        {}
+        "#,
+        page.sql_suffix("d", SortOrder::Desc, 3)
+    );
+
+    let documents = page
+        .bind(
+            sqlx::query_as::<_, DocumentRow>(&query)
+                .bind(session_id)
+                .bind(profile_id),
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(page.page_from_rows(documents))
+}
+
 /// Get a document by ID (with authorization check)
 pub async fn get_document_by_id(
     pool: &PgPool,
@@ -141,8 +420,9 @@ pub async fn get_document_by_id(
 ) -> Result<Option<DocumentRow>, async_graphql::Error> {
     let document = sqlx::query_as::<_, DocumentRow>(
         r#"
-        SELECT d.id, d.session_id, d.file_name, d.file_path, d.content_length,
-               d.processing_status, d.created_at
+        SELECT d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+               d.document_type, d.course_name, d.year,
+               d.processing_status, d.processing_error, d.created_at
         FROM documents d
         JOIN study_sessions s ON d.session_id = s.id
         WHERE d.id = $1 AND s.profile_id = $2
@@ -162,14 +442,14 @@ pub async fn delete_document(
     pool: &PgPool,
     profile_id: Uuid,
     document_id: Uuid,
-) -> Result<Option<String>, async_graphql::Error> {
-    // First get the file_path so we can delete from storage
-    let result = sqlx::query_as::<_, (String,)>(
+) -> Result<Option<(Uuid, String)>, async_graphql::Error> {
+    // Return the session and file_path so we can delete from storage
+    let result = sqlx::query_as::<_, (Uuid, String)>(
         r#"
         DELETE FROM documents d
         USING study_sessions s
         WHERE d.session_id = s.id AND d.id = $1 AND s.profile_id = $2
-        RETURNING d.file_path
+        RETURNING d.session_id, d.file_path
         "#,
     )
     .bind(document_id)
@@ -178,22 +458,181 @@ pub async fn delete_document(
     .await
     .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
 
-    Ok(result.map(|(path,)| path))
+    Ok(result)
+}
+
+/// Get the owner and content hash of a document (for background processing)
+pub async fn get_document_origin(
+    pool: &PgPool,
+    document_id: Uuid,
+) -> Result<Option<DocumentOrigin>, async_graphql::Error> {
+    let origin = sqlx::query_as::<_, DocumentOrigin>(
+        r#"
+        SELECT s.profile_id, d.session_id, d.file_name, d.content_hash
+        FROM documents d
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.id = $1
+        "#,
+    )
+    .bind(document_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(origin)
+}
+
+/// Record the content hash of a document
+///
+/// Returns `false` without changing anything when another document of the
+/// same session already has this hash.
+pub async fn set_document_content_hash(
+    pool: &PgPool,
+    document_id: Uuid,
+    content_hash: &str,
+) -> Result<bool, async_graphql::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE documents
+        SET content_hash = $1
+        WHERE id = $2
+        "#,
+    )
+    .bind(content_hash)
+    .bind(document_id)
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if is_duplicate_content(&e) => Ok(false),
+        Err(e) => Err(async_graphql::Error::new(format!("Database error: {}", e))),
+    }
+}
+
+/// Whether an error is a violation of the one-hash-per-session index
+fn is_duplicate_content(e: &sqlx::Error) -> bool {
+    e.as_database_error().and_then(|d| d.constraint()) == Some("idx_documents_session_content_hash")
+}
+
+/// Find another document with the same content in a session (with authorization check)
+pub async fn find_session_document_by_hash(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_id: Uuid,
+    content_hash: &str,
+    exclude_id: Option<Uuid>,
+) -> Result<Option<DocumentRow>, async_graphql::Error> {
+    let document = sqlx::query_as::<_, DocumentRow>(
+        r#"
+        SELECT d.id, d.session_id, d.file_name, d.file_path, d.file_size, d.content_length,
+               d.document_type, d.course_name, d.year,
+               d.processing_status, d.processing_error, d.created_at
+        FROM documents d
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.session_id = $1 AND s.profile_id = $2 AND d.content_hash = $3
+          AND ($4::uuid IS NULL OR d.id <> $4)
+        ORDER BY d.created_at ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(session_id)
+    .bind(profile_id)
+    .bind(content_hash)
+    .bind(exclude_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(document)
+}
+
+/// Find a completed document with the same content whose extraction can be reused,
+/// from any of the profile's sessions (with authorization check)
+pub async fn find_extraction_by_hash(
+    pool: &PgPool,
+    profile_id: Uuid,
+    content_hash: &str,
+    exclude_id: Uuid,
+) -> Result<Option<Uuid>, async_graphql::Error> {
+    let extraction = sqlx::query_as::<_, (Uuid,)>(
+        r#"
+        SELECT d.id
+        FROM documents d
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE s.profile_id = $1 AND d.content_hash = $2 AND d.id <> $3
+          AND d.processing_status = 'COMPLETED' AND d.content_text IS NOT NULL
+        ORDER BY d.created_at ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(profile_id)
+    .bind(content_hash)
+    .bind(exclude_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(extraction.map(|(id,)| id))
+}
+
+/// Bytes stored across all of a profile's sessions, and within one session
+/// Counts documents and sent message attachments (each file once, as versions of a message share them)
+pub async fn get_storage_usage(
+    executor: impl sqlx::PgExecutor<'_>,
+    profile_id: Uuid,
+    session_id: Option<Uuid>,
+) -> Result<(i64, i64), async_graphql::Error> {
+    let usage = sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        WITH stored AS (
+            SELECT d.session_id, d.file_size
+            FROM documents d
+            JOIN study_sessions s ON d.session_id = s.id
+            WHERE s.profile_id = $1
+            UNION ALL
+            SELECT c.session_id, MAX(a.file_size)
+            FROM message_attachments a
+            JOIN chats c ON a.chat_id = c.id
+            JOIN study_sessions s ON c.session_id = s.id
+            WHERE s.profile_id = $1 AND a.file_size IS NOT NULL
+            GROUP BY c.session_id, a.file_path
+        )
+        SELECT COALESCE(SUM(file_size), 0)::BIGINT,
+               COALESCE(SUM(file_size) FILTER (WHERE session_id = $2), 0)::BIGINT
+        FROM stored
+        "#,
+    )
+    .bind(profile_id)
+    .bind(session_id)
+    .fetch_one(executor)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(usage)
 }
 
 /// Get all document texts for a session (for AI context) - only completed extractions
+/// The syllabus comes first, then exams and problem sets, so the course outline and the
+/// most exam-relevant sources lead the context
 pub async fn get_session_document_texts(
     pool: &PgPool,
     profile_id: Uuid,
     session_id: Uuid,
-) -> Result<Vec<(String, String)>, async_graphql::Error> {
-    let texts = sqlx::query_as::<_, (String, String)>(
+) -> Result<Vec<DocumentText>, async_graphql::Error> {
+    let texts = sqlx::query_as::<_, DocumentText>(
         r#"
-        SELECT d.file_name, d.content_text
+        SELECT d.id, d.file_name, d.document_type, d.course_name, d.year, d.content_text
         FROM documents d
         JOIN study_sessions s ON d.session_id = s.id
         WHERE d.session_id = $1 AND s.profile_id = $2 AND d.processing_status = 'COMPLETED' AND d.content_text IS NOT NULL
-        ORDER BY d.created_at ASC
+        ORDER BY CASE d.document_type
+                     WHEN 'SYLLABUS' THEN 0
+                     WHEN 'PAST_EXAM' THEN 1
+                     WHEN 'PROBLEM_SET' THEN 2
+                     ELSE 3
+                 END,
+                 d.created_at ASC
         "#,
     )
     .bind(session_id)
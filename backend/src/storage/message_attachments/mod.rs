@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AttachmentRow {
+    pub id: Uuid,
+    /// Unset until the message is sent
+    pub message_id: Option<Uuid>,
+    /// Path within the documents bucket
+    pub file_path: String,
+    pub file_name: String,
+    pub content_type: String,
+    /// Size checked when the message is sent
+    pub file_size: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Record an attachment the client is about to upload (caller verifies the chat)
+pub async fn create_attachment(
+    pool: &PgPool,
+    id: Uuid,
+    chat_id: Uuid,
+    file_path: &str,
+    file_name: &str,
+    content_type: &str,
+) -> Result<AttachmentRow, async_graphql::Error> {
+    let attachment = sqlx::query_as::<_, AttachmentRow>(
+        r#"
+        INSERT INTO message_attachments (id, chat_id, file_path, file_name, content_type)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, message_id, file_path, file_name, content_type, file_size, created_at
+        "#,
+    )
+    .bind(id)
+    .bind(chat_id)
+    .bind(file_path)
+    .bind(file_name)
+    .bind(content_type)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(attachment)
+}
+
+/// Get attachments of a chat that haven't been sent yet (with authorization check)
+pub async fn get_pending_attachments(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_id: Uuid,
+    attachment_ids: &[Uuid],
+) -> Result<Vec<AttachmentRow>, async_graphql::Error> {
+    let attachments = sqlx::query_as::<_, AttachmentRow>(
+        r#"
+        SELECT a.id, a.message_id, a.file_path, a.file_name, a.content_type, a.file_size, a.created_at
+        FROM message_attachments a
+        JOIN chats c ON a.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE a.id = ANY($1) AND a.chat_id = $2 AND s.profile_id = $3 AND a.message_id IS NULL
+        ORDER BY a.created_at ASC, a.id ASC
+        "#,
+    )
+    .bind(attachment_ids)
+    .bind(chat_id)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(attachments)
+}
+
+/// Link sent attachments to their message, recording their checked sizes (inside a transaction)
+pub async fn attach_to_message(
+    conn: &mut sqlx::PgConnection,
+    message_id: Uuid,
+    attachments: &[(Uuid, i64)],
+) -> Result<(), async_graphql::Error> {
+    if attachments.is_empty() {
+        return Ok(());
+    }
+
+    let ids: Vec<Uuid> = attachments.iter().map(|(id, _)| *id).collect();
+    let sizes: Vec<i64> = attachments.iter().map(|(_, size)| *size).collect();
+
+    sqlx::query(
+        r#"
+        UPDATE message_attachments a
+        SET message_id = $1, file_size = u.file_size
+        FROM UNNEST($2::uuid[], $3::bigint[]) AS u(id, file_size)
+        WHERE a.id = u.id
+        "#,
+    )
+    .bind(message_id)
+    .bind(&ids)
+    .bind(&sizes)
+    .execute(conn)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Give a new version of a message the same attachments (the files are shared; inside a transaction)
+pub async fn copy_attachments(
+    conn: &mut sqlx::PgConnection,
+    from_message_id: Uuid,
+    to_message_id: Uuid,
+) -> Result<(), async_graphql::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO message_attachments (chat_id, message_id, file_path, file_name, content_type, file_size, created_at)
+        SELECT chat_id, $2, file_path, file_name, content_type, file_size, created_at
+        FROM message_attachments
+        WHERE message_id = $1
+        "#,
+    )
+    .bind(from_message_id)
+    .bind(to_message_id)
+    .execute(conn)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Get the attachments of several messages at once, in upload order (with authorization check)
+pub async fn get_attachments_by_message_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<Vec<AttachmentRow>, async_graphql::Error> {
+    let attachments = sqlx::query_as::<_, AttachmentRow>(
+        r#"
+        SELECT a.id, a.message_id, a.file_path, a.file_name, a.content_type, a.file_size, a.created_at
+        FROM message_attachments a
+        JOIN chats c ON a.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE a.message_id = ANY($1) AND s.profile_id = $2
+        ORDER BY a.message_id, a.created_at ASC, a.id ASC
+        "#,
+    )
+    .bind(message_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(attachments)
+}
+
+/// Delete attachments that were never sent and are older than `hours`, returning their storage paths
+pub async fn delete_unsent_attachments(pool: &PgPool, hours: i32) -> Result<Vec<String>, async_graphql::Error> {
+    let paths = sqlx::query_scalar::<_, String>(
+        r#"
+        DELETE FROM message_attachments
+        WHERE message_id IS NULL AND created_at < NOW() - make_interval(hours => $1)
+        RETURNING file_path
+        "#,
+    )
+    .bind(hours)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(paths)
+}
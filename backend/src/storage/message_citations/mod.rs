@@ -0,0 +1,84 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CitationRow {
+    pub message_id: Uuid,
+    pub marker: i32,
+    pub document_id: Uuid,
+    /// File name of the cited document
+    pub document_name: String,
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+}
+
+/// A source cited by a response, before it is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewCitation {
+    /// Number of the `[n]` marker in the message content
+    pub marker: i32,
+    pub document_id: Uuid,
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+}
+
+/// Store the citations of a message (inside a transaction)
+pub async fn create_citations(
+    conn: &mut sqlx::PgConnection,
+    message_id: Uuid,
+    citations: &[NewCitation],
+) -> Result<(), async_graphql::Error> {
+    if citations.is_empty() {
+        return Ok(());
+    }
+
+    let markers: Vec<i32> = citations.iter().map(|c| c.marker).collect();
+    let document_ids: Vec<Uuid> = citations.iter().map(|c| c.document_id).collect();
+    let page_starts: Vec<Option<i32>> = citations.iter().map(|c| c.page_start).collect();
+    let page_ends: Vec<Option<i32>> = citations.iter().map(|c| c.page_end).collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO message_citations (message_id, marker, document_id, page_start, page_end)
+        SELECT $1, c.marker, c.document_id, c.page_start, c.page_end
+        FROM UNNEST($2::int[], $3::uuid[], $4::int[], $5::int[])
+            AS c(marker, document_id, page_start, page_end)
+        "#,
+    )
+    .bind(message_id)
+    .bind(&markers)
+    .bind(&document_ids)
+    .bind(&page_starts)
+    .bind(&page_ends)
+    .execute(conn)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Get the citations of several messages at once, in marker order (with authorization check)
+pub async fn get_citations_by_message_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<Vec<CitationRow>, async_graphql::Error> {
+    let citations = sqlx::query_as::<_, CitationRow>(
+        r#"
+        SELECT mc.message_id, mc.marker, mc.document_id, d.file_name AS document_name,
+               mc.page_start, mc.page_end
+        FROM message_citations mc
+        JOIN documents d ON mc.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE mc.message_id = ANY($1) AND s.profile_id = $2
+        ORDER BY mc.message_id, mc.marker
+        "#,
+    )
+    .bind(message_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(citations)
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use sqlx::types::JsonValue;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::pagination::Cursor;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FeedbackRow {
+    pub message_id: Uuid,
+    /// "up" or "down"
+    pub rating: String,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A rated exchange with everything needed to evaluate it offline
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FeedbackExportRow {
+    pub message_id: Uuid,
+    pub chat_id: Uuid,
+    pub session_id: Uuid,
+    pub topic_title: Option<String>,
+    pub rating: String,
+    pub reason: Option<String>,
+    pub rated_at: DateTime<Utc>,
+    /// The student message being answered (`None` for welcome messages)
+    pub prompt: Option<String>,
+    pub response: String,
+    pub template_version: Option<String>,
+    pub model: Option<String>,
+    pub generation_context: Option<JsonValue>,
+    pub generation_prompt: Option<JsonValue>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Rate an assistant message, replacing any earlier rating
+/// (with authorization check; `None` if the message isn't the profile's assistant message)
+pub async fn upsert_feedback(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_id: Uuid,
+    rating: &str,
+    reason: Option<&str>,
+) -> Result<Option<FeedbackRow>, async_graphql::Error> {
+    let feedback = sqlx::query_as::<_, FeedbackRow>(
+        r#"
+        INSERT INTO message_feedback (message_id, rating, reason)
+        SELECT m.id, $3, $4
+        FROM messages m
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE m.id = $1 AND s.profile_id = $2 AND m.role = 'assistant'
+        ON CONFLICT (message_id) DO UPDATE
+        SET rating = EXCLUDED.rating, reason = EXCLUDED.reason, updated_at = NOW()
+        RETURNING message_id, rating, reason, created_at, updated_at
+        "#,
+    )
+    .bind(message_id)
+    .bind(profile_id)
+    .bind(rating)
+    .bind(reason)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(feedback)
+}
+
+/// Get the feedback on several messages at once (with authorization check)
+pub async fn get_feedback_by_message_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<Vec<FeedbackRow>, async_graphql::Error> {
+    let feedback = sqlx::query_as::<_, FeedbackRow>(
+        r#"
+        SELECT f.message_id, f.rating, f.reason, f.created_at, f.updated_at
+        FROM message_feedback f
+        JOIN messages m ON f.message_id = m.id
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE f.message_id = ANY($1) AND s.profile_id = $2
+        "#,
+    )
+    .bind(message_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(feedback)
+}
+
+/// Get up to `limit` rated exchanges across all profiles, oldest rating first, starting
+/// after the rating at `after` (`created_at` being when it was rated, `id` the message)
+/// (admin export: no per-profile authorization check)
+pub async fn get_feedback_export(
+    pool: &PgPool,
+    since: Option<DateTime<Utc>>,
+    rating: Option<&str>,
+    after: Option<Cursor>,
+    limit: i64,
+) -> Result<Vec<FeedbackExportRow>, async_graphql::Error> {
+    let rows = sqlx::query_as::<_, FeedbackExportRow>(
+        r#"
+        SELECT m.id AS message_id, m.chat_id, c.session_id, t.title AS topic_title,
+               f.rating, f.reason, f.updated_at AS rated_at,
+               p.content AS prompt, m.content AS response,
+               m.template_version, m.model, m.generation_context, m.generation_prompt, m.created_at
+        FROM message_feedback f
+        JOIN messages m ON f.message_id = m.id
+        JOIN chats c ON m.chat_id = c.id
+        LEFT JOIN topics t ON c.topic_id = t.id
+        LEFT JOIN messages p ON m.parent_id = p.id AND p.role = 'user'
+        WHERE ($1::timestamptz IS NULL OR f.updated_at >= $1)
+          AND ($2::text IS NULL OR f.rating = $2)
+          AND ($3::timestamptz IS NULL OR (f.updated_at, f.message_id) > ($3, $4))
+        ORDER BY f.updated_at ASC, f.message_id ASC
+        LIMIT $5
+        "#,
+    )
+    .bind(since)
+    .bind(rating)
+    .bind(after.map(|c| c.created_at))
+    .bind(after.map(|c| c.id))
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(rows)
+}
@@ -1,41 +1,381 @@
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::message_attachments;
+use super::message_citations::{self, NewCitation};
+use super::pagination::{Cursor, Keyed, Page, PageRequest, SortOrder};
+
+/// Pending replies not finished within this many minutes are treated as failed
+/// (e.g. the server restarted while the model was running)
+pub const STALE_REPLY_MINUTES: i32 = 10;
+
+/// State of a message, matching the database enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "message_status", rename_all = "lowercase")]
+pub enum MessageStatus {
+    /// Reply placeholder while the model runs
+    Pending,
+    Complete,
+    /// The model call failed; the reply can be retried
+    Failed,
+}
+
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct MessageRow {
     pub id: Uuid,
     pub chat_id: Uuid,
     pub role: String,
     pub content: String,
+    /// Prompt template version that produced an assistant message
+    pub template_version: Option<String>,
+    /// Message this one follows; alternatives (regenerated or edited) share a parent
+    pub parent_id: Option<Uuid>,
+    /// Whether the message is on the chat's current branch
+    pub is_active: bool,
+    pub status: MessageStatus,
     pub created_at: DateTime<Utc>,
 }
 
-/// Create a new message
-pub async fn create_message(
+impl Keyed for MessageRow {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
+/// How an assistant message was generated (kept for evaluating rated answers)
+#[derive(Debug, Clone)]
+pub struct Generation {
+    /// Version of the system prompt template
+    pub template_version: String,
+    pub model: String,
+    pub context: GenerationContext,
+    /// Messages sent to the model, system prompt first (images aside)
+    pub prompt: Vec<PromptMessage>,
+}
+
+/// One message of a prompt, stored in `messages.generation_prompt`
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl PromptMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+/// What went into the prompt, stored as `messages.generation_context`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenerationContext {
+    /// Documents with at least part of their content in the prompt
+    pub documents: Vec<Uuid>,
+    /// Earlier messages sent as history
+    pub history_messages: usize,
+    pub summary: bool,
+    pub study_plan: bool,
+    pub problem_bank: bool,
+    /// Images attached to the message being answered
+    pub images: usize,
+    /// Estimated size of the prompt
+    pub prompt_tokens: usize,
+}
+
+/// A student message to store before it is answered
+#[derive(Debug, Clone, Copy)]
+pub struct NewUserMessage<'a> {
+    pub content: &'a str,
+    /// Uploaded attachments with their checked sizes
+    pub attachments: &'a [(Uuid, i64)],
+    /// Earlier version of the message whose attachments are kept (when editing)
+    pub copy_attachments_from: Option<Uuid>,
+}
+
+/// What to store along with the pending reply
+#[derive(Debug, Clone, Copy)]
+pub enum ReplyStart<'a> {
+    /// A student message after the chat's last message
+    Send(NewUserMessage<'a>),
+    /// A new version of a student message, replacing the branch after `after`
+    Edit {
+        after: Option<Cursor>,
+        parent_id: Option<Uuid>,
+        message: NewUserMessage<'a>,
+    },
+    /// A new reply to `parent_id`, replacing the branch after `after`
+    Regenerate { after: Option<Cursor>, parent_id: Option<Uuid> },
+    /// The first message of an empty chat (fails if the chat has messages)
+    Welcome,
+}
+
+/// Store the student's message (if any) and a pending reply in one transaction,
+/// returning the reply (with authorization check)
+///
+/// Fails if a reply is already being generated in the chat, so concurrent
+/// sends cannot interleave.
+pub async fn begin_reply(
     pool: &PgPool,
+    profile_id: Uuid,
     chat_id: Uuid,
-    role: &str,
-    content: &str,
+    start: ReplyStart<'_>,
 ) -> Result<MessageRow, async_graphql::Error> {
-    let message = sqlx::query_as::<_, MessageRow>(
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    lock_chat_for_reply(&mut tx, profile_id, chat_id).await?;
+
+    let reply_parent = match start {
+        ReplyStart::Send(message) => {
+            let last: Option<Uuid> = sqlx::query_scalar(
+                r#"
+                SELECT id FROM messages
+                WHERE chat_id = $1 AND is_active
+                ORDER BY created_at DESC, id DESC
+                LIMIT 1
+                "#,
+            )
+            .bind(chat_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+            Some(insert_user_message(&mut tx, chat_id, last, message).await?)
+        }
+        ReplyStart::Edit { after, parent_id, message } => {
+            deactivate_after(&mut tx, profile_id, chat_id, after).await?;
+            reset_summary_after(&mut tx, profile_id, chat_id, after).await?;
+
+            Some(insert_user_message(&mut tx, chat_id, parent_id, message).await?)
+        }
+        ReplyStart::Regenerate { after, parent_id } => {
+            deactivate_after(&mut tx, profile_id, chat_id, after).await?;
+            reset_summary_after(&mut tx, profile_id, chat_id, after).await?;
+            parent_id
+        }
+        ReplyStart::Welcome => {
+            let has_messages: bool =
+                sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM messages WHERE chat_id = $1)")
+                    .bind(chat_id)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(db_error)?;
+            if has_messages {
+                return Err(
+                    "Chat already has messages. Welcome message can only be generated for empty chats.".into(),
+                );
+            }
+            None
+        }
+    };
+
+    let reply = sqlx::query_as::<_, MessageRow>(
         r#"
-        INSERT INTO messages (chat_id, role, content)
-        VALUES ($1, $2, $3)
-        RETURNING id, chat_id, role, content, created_at
+        INSERT INTO messages (chat_id, role, content, parent_id, status, created_at)
+        VALUES ($1, 'assistant', '', $2, 'pending', clock_timestamp())
+        RETURNING id, chat_id, role, content, template_version, parent_id, is_active, status, created_at
         "#,
     )
     .bind(chat_id)
-    .bind(role)
+    .bind(reply_parent)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(reply)
+}
+
+/// Put a failed reply back to pending so it can be generated again
+/// (with authorization check; `None` if the reply isn't failed)
+pub async fn restart_reply(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_id: Uuid,
+    reply_id: Uuid,
+) -> Result<Option<MessageRow>, async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    lock_chat_for_reply(&mut tx, profile_id, chat_id).await?;
+
+    let reply = sqlx::query_as::<_, MessageRow>(
+        r#"
+        UPDATE messages
+        SET status = 'pending', status_updated_at = NOW()
+        WHERE id = $1 AND chat_id = $2 AND role = 'assistant' AND status = 'failed'
+        RETURNING id, chat_id, role, content, template_version, parent_id, is_active, status, created_at
+        "#,
+    )
+    .bind(reply_id)
+    .bind(chat_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(reply)
+}
+
+/// Store the generated content of a pending reply and the sources it cites
+/// (`None` if the reply is no longer pending, e.g. the chat was cleared)
+pub async fn complete_reply(
+    pool: &PgPool,
+    reply_id: Uuid,
+    content: &str,
+    generation: &Generation,
+    citations: &[NewCitation],
+) -> Result<Option<MessageRow>, async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let json_error = |e: serde_json::Error| async_graphql::Error::new(format!("JSON serialization error: {}", e));
+    let context = serde_json::to_value(&generation.context).map_err(json_error)?;
+    let prompt = serde_json::to_value(&generation.prompt).map_err(json_error)?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let reply = sqlx::query_as::<_, MessageRow>(
+        r#"
+        UPDATE messages
+        SET content = $2, status = 'complete', status_updated_at = NOW(),
+            template_version = $3, model = $4, generation_context = $5, generation_prompt = $6
+        WHERE id = $1 AND status = 'pending'
+        RETURNING id, chat_id, role, content, template_version, parent_id, is_active, status, created_at
+        "#,
+    )
+    .bind(reply_id)
     .bind(content)
-    .fetch_one(pool)
+    .bind(&generation.template_version)
+    .bind(&generation.model)
+    .bind(context)
+    .bind(prompt)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    if reply.is_some() {
+        message_citations::create_citations(&mut tx, reply_id, citations).await?;
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(reply)
+}
+
+/// Mark a pending reply as failed
+pub async fn fail_reply(pool: &PgPool, reply_id: Uuid) -> Result<(), async_graphql::Error> {
+    sqlx::query(
+        r#"
+        UPDATE messages
+        SET status = 'failed', status_updated_at = NOW()
+        WHERE id = $1 AND status = 'pending'
+        "#,
+    )
+    .bind(reply_id)
+    .execute(pool)
     .await
     .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
 
-    Ok(message)
+    Ok(())
+}
+
+/// Lock the chat until the transaction ends, failing stale pending replies and
+/// refusing to start another reply while one is pending
+async fn lock_chat_for_reply(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    profile_id: Uuid,
+    chat_id: Uuid,
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let chat: Option<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT c.id
+        FROM chats c
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE c.id = $1 AND s.profile_id = $2
+        FOR UPDATE OF c
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(db_error)?;
+    if chat.is_none() {
+        return Err("Chat not found".into());
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE messages
+        SET status = 'failed', status_updated_at = NOW()
+        WHERE chat_id = $1 AND status = 'pending'
+          AND status_updated_at < NOW() - make_interval(mins => $2)
+        "#,
+    )
+    .bind(chat_id)
+    .bind(STALE_REPLY_MINUTES)
+    .execute(&mut **tx)
+    .await
+    .map_err(db_error)?;
+
+    let pending: Option<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM messages WHERE chat_id = $1 AND status = 'pending'",
+    )
+    .bind(chat_id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(db_error)?;
+    if pending.is_some() {
+        return Err("A reply is already being generated in this chat".into());
+    }
+
+    Ok(())
+}
+
+/// Insert a student message and its attachments, returning its ID
+async fn insert_user_message(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    chat_id: Uuid,
+    parent_id: Option<Uuid>,
+    message: NewUserMessage<'_>,
+) -> Result<Uuid, async_graphql::Error> {
+    let id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO messages (chat_id, role, content, parent_id, created_at)
+        VALUES ($1, 'user', $2, $3, clock_timestamp())
+        RETURNING id
+        "#,
+    )
+    .bind(chat_id)
+    .bind(message.content)
+    .bind(parent_id)
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    message_attachments::attach_to_message(tx, id, message.attachments).await?;
+    if let Some(previous) = message.copy_attachments_from {
+        message_attachments::copy_attachments(tx, previous, id).await?;
+    }
+
+    Ok(id)
 }
 
-/// Get messages for a chat (with authorization check)
+/// Get the messages on a chat's current branch (with authorization check)
 pub async fn get_chat_messages(
     pool: &PgPool,
     profile_id: Uuid,
@@ -43,12 +383,12 @@ pub async fn get_chat_messages(
 ) -> Result<Vec<MessageRow>, async_graphql::Error> {
     let messages = sqlx::query_as::<_, MessageRow>(
         r#"
-        SELECT m.id, m.chat_id, m.role, m.content, m.created_at
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
         FROM messages m
         JOIN chats c ON m.chat_id = c.id
         JOIN study_sessions s ON c.session_id = s.id
-        WHERE m.chat_id = $1 AND s.profile_id = $2
-        ORDER BY m.created_at ASC
+        WHERE m.chat_id = $1 AND s.profile_id = $2 AND m.is_active
+        ORDER BY m.created_at ASC, m.id ASC
         "#,
     )
     .bind(chat_id)
@@ -60,27 +400,92 @@ pub async fn get_chat_messages(
     Ok(messages)
 }
 
-/// Get recent messages for context (last N messages)
+/// Get messages for several chats at once, oldest first (with authorization check)
+pub async fn get_messages_by_chat_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_ids: &[Uuid],
+) -> Result<Vec<MessageRow>, async_graphql::Error> {
+    let messages = sqlx::query_as::<_, MessageRow>(
+        r#"
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
+        FROM messages m
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE m.chat_id = ANY($1) AND s.profile_id = $2 AND m.is_active
+        ORDER BY m.created_at ASC, m.id ASC
+        "#,
+    )
+    .bind(chat_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(messages)
+}
+
+/// Get a page of messages for a chat, oldest first (with authorization check)
+pub async fn get_chat_messages_page(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_id: Uuid,
+    page: &PageRequest,
+) -> Result<Page<MessageRow>, async_graphql::Error> {
+    let query = format!(
+        r#"
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
+        FROM messages m
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE m.chat_id = $1 AND s.profile_id = $2 AND m.is_active
+        {}
+        "#,
+        page.sql_suffix("m", SortOrder::Asc, 3)
+    );
+
+    let messages = page
+        .bind(
+            sqlx::query_as::<_, MessageRow>(&query)
+                .bind(chat_id)
+                .bind(profile_id),
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(page.page_from_rows(messages))
+}
+
+/// Get recent complete messages for context (last N between two cursors, oldest first)
 pub async fn get_recent_messages(
     pool: &PgPool,
     profile_id: Uuid,
     chat_id: Uuid,
+    after: Option<Cursor>,
+    before: Option<Cursor>,
     limit: i32,
 ) -> Result<Vec<MessageRow>, async_graphql::Error> {
     let messages = sqlx::query_as::<_, MessageRow>(
         r#"
-        SELECT m.id, m.chat_id, m.role, m.content, m.created_at
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
         FROM messages m
         JOIN chats c ON m.chat_id = c.id
         JOIN study_sessions s ON c.session_id = s.id
-        WHERE m.chat_id = $1 AND s.profile_id = $2
-        ORDER BY m.created_at DESC
+        WHERE m.chat_id = $1 AND s.profile_id = $2 AND m.is_active AND m.status = 'complete'
+          AND ($4::timestamptz IS NULL OR (m.created_at, m.id) > ($4, $5))
+          AND ($6::timestamptz IS NULL OR (m.created_at, m.id) < ($6, $7))
+        ORDER BY m.created_at DESC, m.id DESC
         LIMIT $3
         "#,
     )
     .bind(chat_id)
     .bind(profile_id)
     .bind(limit)
+    .bind(after.map(|c| c.created_at))
+    .bind(after.map(|c| c.id))
+    .bind(before.map(|c| c.created_at))
+    .bind(before.map(|c| c.id))
     .fetch_all(pool)
     .await
     .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
@@ -91,48 +496,286 @@ pub async fn get_recent_messages(
     Ok(messages)
 }
 
-/// Clear all messages in a chat (for starting fresh)
-pub async fn clear_chat_messages(
+/// Get the oldest complete messages of a chat after a cursor, oldest first (with authorization check)
+pub async fn get_messages_after(
     pool: &PgPool,
     profile_id: Uuid,
     chat_id: Uuid,
+    after: Option<Cursor>,
+    limit: i32,
+) -> Result<Vec<MessageRow>, async_graphql::Error> {
+    let messages = sqlx::query_as::<_, MessageRow>(
+        r#"
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
+        FROM messages m
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE m.chat_id = $1 AND s.profile_id = $2 AND m.is_active AND m.status = 'complete'
+          AND ($4::timestamptz IS NULL OR (m.created_at, m.id) > ($4, $5))
+        ORDER BY m.created_at ASC, m.id ASC
+        LIMIT $3
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .bind(limit)
+    .bind(after.map(|c| c.created_at))
+    .bind(after.map(|c| c.id))
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(messages)
+}
+
+/// Get a message by ID (with authorization check)
+pub async fn get_message_by_id(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_id: Uuid,
+) -> Result<Option<MessageRow>, async_graphql::Error> {
+    let message = sqlx::query_as::<_, MessageRow>(
+        r#"
+        SELECT m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
+        FROM messages m
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE m.id = $1 AND s.profile_id = $2
+        "#,
+    )
+    .bind(message_id)
+    .bind(profile_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(message)
+}
+
+/// A version of a message, keyed by the message it was looked up for
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MessageBranchRow {
+    pub of_message_id: Uuid,
+    #[sqlx(flatten)]
+    pub message: MessageRow,
+}
+
+/// Get every version of several messages at once (the messages sharing each
+/// one's parent, itself included), oldest first (with authorization check)
+pub async fn get_branches_by_message_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<Vec<MessageBranchRow>, async_graphql::Error> {
+    let branches = sqlx::query_as::<_, MessageBranchRow>(
+        r#"
+        SELECT t.id AS of_message_id,
+               m.id, m.chat_id, m.role, m.content, m.template_version, m.parent_id, m.is_active, m.status, m.created_at
+        FROM messages t
+        JOIN messages m ON m.chat_id = t.chat_id AND m.parent_id IS NOT DISTINCT FROM t.parent_id
+        JOIN chats c ON m.chat_id = c.id
+        JOIN study_sessions s ON c.session_id = s.id
+        WHERE t.id = ANY($1) AND s.profile_id = $2
+        ORDER BY t.id, m.created_at ASC, m.id ASC
+        "#,
+    )
+    .bind(message_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(branches)
+}
+
+/// Make a message and its newest descendants the chat's current branch
+/// (with authorization check)
+///
+/// Takes the same chat lock as `begin_reply`, so it fails while a reply is
+/// being generated instead of moving the branch out from under it.
+pub async fn select_branch(
+    pool: &PgPool,
+    profile_id: Uuid,
+    message: &MessageRow,
+    parent: Option<Cursor>,
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    lock_chat_for_reply(&mut tx, profile_id, message.chat_id).await?;
+
+    // Everything after the parent belongs to the branch being replaced
+    deactivate_after(&mut tx, profile_id, message.chat_id, parent).await?;
+
+    // Follow the newest reply at each step down from the selected message
+    sqlx::query(
+        r#"
+        WITH RECURSIVE path AS (
+            SELECT m.id
+            FROM messages m
+            JOIN chats c ON m.chat_id = c.id
+            JOIN study_sessions s ON c.session_id = s.id
+            WHERE m.id = $1 AND s.profile_id = $2
+            UNION ALL
+            SELECT child.id
+            FROM path
+            CROSS JOIN LATERAL (
+                SELECT id FROM messages
+                WHERE parent_id = path.id
+                ORDER BY created_at DESC, id DESC
+                LIMIT 1
+            ) child
+        )
+        UPDATE messages SET is_active = TRUE WHERE id IN (SELECT id FROM path)
+        "#,
+    )
+    .bind(message.id)
+    .bind(profile_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    reset_summary_after(&mut tx, profile_id, message.chat_id, parent).await?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Take the active messages after the cursor off the current branch
+async fn deactivate_after(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    profile_id: Uuid,
+    chat_id: Uuid,
+    after: Option<Cursor>,
 ) -> Result<u64, async_graphql::Error> {
     let result = sqlx::query(
         r#"
-        DELETE FROM messages m
-        USING chats c, study_sessions s
+        UPDATE messages m
+        SET is_active = FALSE
+        FROM chats c, study_sessions s
         WHERE m.chat_id = c.id AND c.session_id = s.id AND m.chat_id = $1 AND s.profile_id = $2
+          AND m.is_active
+          AND ($3::timestamptz IS NULL OR (m.created_at, m.id) > ($3, $4))
         "#,
     )
     .bind(chat_id)
     .bind(profile_id)
-    .execute(pool)
+    .bind(after.map(|c| c.created_at))
+    .bind(after.map(|c| c.id))
+    .execute(&mut **tx)
     .await
     .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
 
     Ok(result.rows_affected())
 }
 
-/// Check if a chat has any messages (with authorization check)
-pub async fn chat_has_messages(
-    pool: &PgPool,
+/// Discard the chat summary if it covers messages after the cursor, and make
+/// summaries still being written from the old history stale
+async fn reset_summary_after(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     profile_id: Uuid,
     chat_id: Uuid,
-) -> Result<bool, async_graphql::Error> {
-    let count: Option<i64> = sqlx::query_scalar(
+    after: Option<Cursor>,
+) -> Result<(), async_graphql::Error> {
+    sqlx::query(
         r#"
-        SELECT COUNT(m.id)
-        FROM messages m
-        JOIN chats c ON m.chat_id = c.id
-        JOIN study_sessions s ON c.session_id = s.id
-        WHERE m.chat_id = $1 AND s.profile_id = $2
+        UPDATE chats c
+        SET summary = NULL, summary_through_at = NULL, summary_through_id = NULL
+        FROM study_sessions s
+        WHERE c.session_id = s.id AND c.id = $1 AND s.profile_id = $2
+          AND c.summary_through_at IS NOT NULL
+          AND ($3::timestamptz IS NULL OR (c.summary_through_at, c.summary_through_id) > ($3, $4))
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .bind(after.map(|c| c.created_at))
+    .bind(after.map(|c| c.id))
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        UPDATE chats c
+        SET history_generation = c.history_generation + 1
+        FROM study_sessions s
+        WHERE c.session_id = s.id AND c.id = $1 AND s.profile_id = $2
         "#,
     )
     .bind(chat_id)
     .bind(profile_id)
-    .fetch_one(pool)
+    .execute(&mut **tx)
     .await
     .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
 
-    Ok(count.unwrap_or(0) > 0)
+    Ok(())
+}
+
+/// Clear all messages in a chat (for starting fresh)
+/// Returns how many messages were deleted and the storage paths of their attachments
+pub async fn clear_chat_messages(
+    pool: &PgPool,
+    profile_id: Uuid,
+    chat_id: Uuid,
+) -> Result<(u64, Vec<String>), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    // Sent attachments go with their messages; unsent ones stay for the next message
+    let mut attachment_paths: Vec<String> = sqlx::query_scalar(
+        r#"
+        DELETE FROM message_attachments a
+        USING chats c, study_sessions s
+        WHERE a.chat_id = c.id AND c.session_id = s.id AND a.chat_id = $1 AND s.profile_id = $2
+          AND a.message_id IS NOT NULL
+        RETURNING a.file_path
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let result = sqlx::query(
+        r#"
+        DELETE FROM messages m
+        USING chats c, study_sessions s
+        WHERE m.chat_id = c.id AND c.session_id = s.id AND m.chat_id = $1 AND s.profile_id = $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    // The summary described the cleared messages, and summaries in progress did too
+    sqlx::query(
+        r#"
+        UPDATE chats c
+        SET summary = NULL, summary_through_at = NULL, summary_through_id = NULL,
+            history_generation = c.history_generation + 1
+        FROM study_sessions s
+        WHERE c.session_id = s.id AND c.id = $1 AND s.profile_id = $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(profile_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    // Versions of a message share their files
+    attachment_paths.sort();
+    attachment_paths.dedup();
+
+    Ok((result.rows_affected(), attachment_paths))
 }
+
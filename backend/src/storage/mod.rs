@@ -1,6 +1,14 @@
 pub mod profiles;
 pub mod sessions;
 pub mod documents;
+pub mod document_pages;
+pub mod problems;
 pub mod topics;
 pub mod chats;
 pub mod messages;
+pub mod message_citations;
+pub mod message_feedback;
+pub mod message_attachments;
+pub mod prompt_templates;
+pub mod upload_tickets;
+pub mod pagination;
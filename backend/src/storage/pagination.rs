@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryAs;
+use sqlx::Postgres;
+use uuid::Uuid;
+
+/// Position of a row in a `created_at` + `id` keyset (ordered like the keyset)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+/// Rows that can be paginated by keyset
+pub trait Keyed {
+    fn cursor(&self) -> Cursor;
+}
+
+/// Order in which a list is presented to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Oldest first (e.g. chat messages)
+    Asc,
+    /// Newest first (e.g. sessions, documents)
+    Desc,
+}
+
+/// A page request: rows strictly between `after` and `before`,
+/// taking `limit` rows from the start (or from the end when `from_end` is set)
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub after: Option<Cursor>,
+    pub before: Option<Cursor>,
+    pub limit: i64,
+    pub from_end: bool,
+}
+
+/// A page of rows in presentation order
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub rows: Vec<T>,
+    pub has_previous: bool,
+    pub has_next: bool,
+}
+
+impl PageRequest {
+    /// Build the keyset filter, ordering and limit for a query
+    ///
+    /// Placeholders start at `$first_param` and are filled by `bind`.
+    pub fn sql_suffix(&self, alias: &str, order: SortOrder, first_param: usize) -> String {
+        // "after" means further along in presentation order
+        let (after_cmp, before_cmp) = match order {
+            SortOrder::Asc => (">", "<"),
+            SortOrder::Desc => ("<", ">"),
+        };
+
+        // Scanning from the end walks the presentation order backwards
+        let direction = match (order, self.from_end) {
+            (SortOrder::Asc, false) | (SortOrder::Desc, true) => "ASC",
+            (SortOrder::Asc, true) | (SortOrder::Desc, false) => "DESC",
+        };
+
+        let p = first_param;
+        format!(
+            "AND (${p}::timestamptz IS NULL OR ({a}.created_at, {a}.id) {after_cmp} (${p}, ${p1})) \
+             AND (${p2}::timestamptz IS NULL OR ({a}.created_at, {a}.id) {before_cmp} (${p2}, ${p3})) \
+             ORDER BY {a}.created_at {direction}, {a}.id {direction} \
+             LIMIT ${p4}",
+            a = alias,
+            p1 = p + 1,
+            p2 = p + 2,
+            p3 = p + 3,
+            p4 = p + 4,
+        )
+    }
+
+    /// Bind the placeholders produced by `sql_suffix`
+    /// One extra row is fetched to detect whether more pages exist
+    pub fn bind<'q, O>(
+        &self,
+        query: QueryAs<'q, Postgres, O, PgArguments>,
+    ) -> QueryAs<'q, Postgres, O, PgArguments> {
+        query
+            .bind(self.after.map(|c| c.created_at))
+            .bind(self.after.map(|c| c.id))
+            .bind(self.before.map(|c| c.created_at))
+            .bind(self.before.map(|c| c.id))
+            .bind(self.limit + 1)
+    }
+
+    /// Turn the fetched rows (limit + 1, in scan order) into a page in presentation order
+    pub fn page_from_rows<T>(&self, mut rows: Vec<T>) -> Page<T> {
+        let has_more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit.max(0) as usize);
+
+        if self.from_end {
+            rows.reverse();
+            Page {
+                rows,
+                has_previous: has_more,
+                has_next: self.before.is_some(),
+            }
+        } else {
+            Page {
+                rows,
+                has_previous: self.after.is_some(),
+                has_next: has_more,
+            }
+        }
+    }
+}
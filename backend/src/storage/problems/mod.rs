@@ -0,0 +1,219 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ProblemRow {
+    pub id: Uuid,
+    pub document_id: Uuid,
+    /// File name of the source document
+    pub document_name: String,
+    pub topic_id: Option<Uuid>,
+    pub position: i32,
+    pub label: Option<String>,
+    pub statement: String,
+    pub sub_parts: Vec<String>,
+    pub solution: Option<String>,
+    pub points: Option<f64>,
+    pub page_number: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A problem extracted from a document, before it is stored
+#[derive(Debug, Clone)]
+pub struct NewProblem {
+    pub label: Option<String>,
+    pub statement: String,
+    pub sub_parts: Vec<String>,
+    pub solution: Option<String>,
+    pub points: Option<f64>,
+    pub page_number: Option<i32>,
+}
+
+const PROBLEM_COLUMNS: &str = "p.id, p.document_id, d.file_name AS document_name, p.topic_id, p.position, p.label, p.statement, \
+     p.sub_parts, p.solution, p.points, p.page_number, p.created_at";
+
+/// Replace the problems of a document (reprocessing starts from scratch;
+/// new problems are linked to topics separately)
+pub async fn replace_document_problems(
+    pool: &PgPool,
+    document_id: Uuid,
+    problems: &[NewProblem],
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query("DELETE FROM problems WHERE document_id = $1")
+        .bind(document_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    for (position, problem) in problems.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO problems
+                (document_id, position, label, statement, sub_parts, solution, points, page_number)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(document_id)
+        .bind(position as i32)
+        .bind(&problem.label)
+        .bind(&problem.statement)
+        .bind(&problem.sub_parts)
+        .bind(&problem.solution)
+        .bind(problem.points)
+        .bind(problem.page_number)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Copy the problems of an identical document (topic links are not copied,
+/// since topics belong to the source document's session)
+pub async fn copy_problems(
+    pool: &PgPool,
+    source_id: Uuid,
+    target_id: Uuid,
+) -> Result<(), async_graphql::Error> {
+    let db_error = |e: sqlx::Error| async_graphql::Error::new(format!("Database error: {}", e));
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query("DELETE FROM problems WHERE document_id = $1")
+        .bind(target_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO problems
+            (document_id, position, label, statement, sub_parts, solution, points, page_number)
+        SELECT $2, position, label, statement, sub_parts, solution, points, page_number
+        FROM problems
+        WHERE document_id = $1
+        "#,
+    )
+    .bind(source_id)
+    .bind(target_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Get the problems of several topics at once (with authorization check)
+pub async fn get_problems_by_topic_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    topic_ids: &[Uuid],
+) -> Result<Vec<ProblemRow>, async_graphql::Error> {
+    let problems = sqlx::query_as::<_, ProblemRow>(&format!(
+        r#"
+        SELECT {PROBLEM_COLUMNS}
+        FROM problems p
+        JOIN documents d ON p.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE p.topic_id = ANY($1) AND s.profile_id = $2
+        ORDER BY p.topic_id, d.year DESC NULLS LAST, d.created_at, p.position
+        "#
+    ))
+    .bind(topic_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(problems)
+}
+
+/// Get every problem of a session (with authorization check)
+pub async fn get_session_problems(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_id: Uuid,
+) -> Result<Vec<ProblemRow>, async_graphql::Error> {
+    let problems = sqlx::query_as::<_, ProblemRow>(&format!(
+        r#"
+        SELECT {PROBLEM_COLUMNS}
+        FROM problems p
+        JOIN documents d ON p.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.session_id = $1 AND s.profile_id = $2
+        ORDER BY d.year DESC NULLS LAST, d.created_at, p.position
+        "#
+    ))
+    .bind(session_id)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(problems)
+}
+
+/// Get up to `limit` problems of a session that were never checked against its topics
+/// (with authorization check)
+pub async fn get_unchecked_problems(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_id: Uuid,
+    limit: i64,
+) -> Result<Vec<ProblemRow>, async_graphql::Error> {
+    let problems = sqlx::query_as::<_, ProblemRow>(&format!(
+        r#"
+        SELECT {PROBLEM_COLUMNS}
+        FROM problems p
+        JOIN documents d ON p.document_id = d.id
+        JOIN study_sessions s ON d.session_id = s.id
+        WHERE d.session_id = $1 AND s.profile_id = $2
+          AND p.topic_id IS NULL AND p.topic_checked_at IS NULL
+        ORDER BY d.created_at, p.position
+        LIMIT $3
+        "#
+    ))
+    .bind(session_id)
+    .bind(profile_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(problems)
+}
+
+/// Record the topic of checked problems (`None` when no topic fits), so they
+/// aren't checked again
+pub async fn set_problem_topics(
+    pool: &PgPool,
+    links: &[(Uuid, Option<Uuid>)],
+) -> Result<(), async_graphql::Error> {
+    let (problem_ids, topic_ids): (Vec<Uuid>, Vec<Option<Uuid>>) = links.iter().copied().unzip();
+
+    sqlx::query(
+        r#"
+        UPDATE problems p
+        SET topic_id = l.topic_id, topic_checked_at = NOW()
+        FROM UNNEST($1::uuid[], $2::uuid[]) AS l(problem_id, topic_id)
+        WHERE p.id = l.problem_id
+        "#,
+    )
+    .bind(&problem_ids)
+    .bind(&topic_ids)
+    .execute(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
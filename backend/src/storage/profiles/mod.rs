@@ -71,3 +71,20 @@ pub async fn get_profile_by_id(
 
     Ok(profile)
 }
+
+/// Get the account tier of a profile ("free" when the profile is missing)
+pub async fn get_profile_tier(pool: &PgPool, id: Uuid) -> Result<String, async_graphql::Error> {
+    let tier = sqlx::query_as::<_, (String,)>(
+        r#"
+        SELECT tier
+        FROM profiles
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(tier.map(|(tier,)| tier).unwrap_or_else(|| "free".to_string()))
+}
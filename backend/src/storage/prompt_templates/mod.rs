@@ -0,0 +1,27 @@
+use sqlx::PgPool;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PromptTemplateRow {
+    pub name: String,
+    /// Empty for the default variant
+    pub locale: String,
+    pub version: i32,
+    pub body: String,
+}
+
+/// Get the newest active version of every template and locale
+pub async fn get_active_templates(pool: &PgPool) -> Result<Vec<PromptTemplateRow>, async_graphql::Error> {
+    let templates = sqlx::query_as::<_, PromptTemplateRow>(
+        r#"
+        SELECT DISTINCT ON (name, locale) name, locale, version, body
+        FROM prompt_templates
+        WHERE is_active
+        ORDER BY name, locale, version DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(templates)
+}
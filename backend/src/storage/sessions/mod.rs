@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::pagination::{Cursor, Keyed, Page, PageRequest, SortOrder};
+
 /// Session status enum matching the database
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
 #[sqlx(type_name = "session_status", rename_all = "UPPERCASE")]
@@ -48,6 +50,15 @@ pub struct SessionRow {
     pub updated_at: DateTime<Utc>,
 }
 
+impl Keyed for SessionRow {
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
+    }
+}
+
 /// Create a new study session
 pub async fn create_session(
     pool: &PgPool,
@@ -93,6 +104,34 @@ pub async fn get_profile_sessions(
     Ok(sessions)
 }
 
+/// Get a page of sessions for a profile, newest first
+pub async fn get_profile_sessions_page(
+    pool: &PgPool,
+    profile_id: Uuid,
+    page: &PageRequest,
+) -> Result<Page<SessionRow>, async_graphql::Error> {
+    let query = format!(
+        r#"
+        SELECT s.id, s.title, s.description, s.status, s.draft_plan, s.created_at, s.updated_at
+        FROM study_sessions s
+        WHERE s.profile_id = $1
+        {}
+        "#,
+        page.sql_suffix("s", SortOrder::Desc, 2)
+    );
+
+    let sessions = page
+        .bind(
+            sqlx::query_as::<_, SessionRow>(&query)
+                .bind(profile_id),
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(page.page_from_rows(sessions))
+}
+
 /// Get a specific session by ID (with authorization check)
 pub async fn get_session_by_id(
     pool: &PgPool,
@@ -82,6 +82,53 @@ pub async fn get_session_topics(
     Ok(topics)
 }
 
+/// Get topics for several sessions at once (with authorization check)
+pub async fn get_topics_by_session_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    session_ids: &[Uuid],
+) -> Result<Vec<TopicRow>, async_graphql::Error> {
+    let topics = sqlx::query_as::<_, TopicRow>(
+        r#"
+        SELECT t.id, t.session_id, t.title, t.description, t.order_index, t.is_completed, t.created_at, t.updated_at
+        FROM topics t
+        JOIN study_sessions s ON t.session_id = s.id
+        WHERE t.session_id = ANY($1) AND s.profile_id = $2
+        ORDER BY t.session_id, t.order_index ASC
+        "#,
+    )
+    .bind(session_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(topics)
+}
+
+/// Get several topics by ID (with authorization check)
+pub async fn get_topics_by_ids(
+    pool: &PgPool,
+    profile_id: Uuid,
+    topic_ids: &[Uuid],
+) -> Result<Vec<TopicRow>, async_graphql::Error> {
+    let topics = sqlx::query_as::<_, TopicRow>(
+        r#"
+        SELECT t.id, t.session_id, t.title, t.description, t.order_index, t.is_completed, t.created_at, t.updated_at
+        FROM topics t
+        JOIN study_sessions s ON t.session_id = s.id
+        WHERE t.id = ANY($1) AND s.profile_id = $2
+        "#,
+    )
+    .bind(topic_ids)
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(topics)
+}
+
 /// Get a topic by ID (with authorization check)
 pub async fn get_topic_by_id(
     pool: &PgPool,
@@ -0,0 +1,47 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Record a storage path issued for a direct upload (caller verifies the session)
+pub async fn create_upload_ticket(
+    pool: &PgPool,
+    session_id: Uuid,
+    file_path: &str,
+) -> Result<(), async_graphql::Error> {
+    sqlx::query("INSERT INTO upload_tickets (file_path, session_id) VALUES ($1, $2)")
+        .bind(file_path)
+        .bind(session_id)
+        .execute(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Whether a storage path was issued for the session and not completed yet
+pub async fn upload_ticket_exists(
+    pool: &PgPool,
+    session_id: Uuid,
+    file_path: &str,
+) -> Result<bool, async_graphql::Error> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM upload_tickets WHERE file_path = $1 AND session_id = $2)",
+    )
+    .bind(file_path)
+    .bind(session_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(exists)
+}
+
+/// Forget an issued path once its upload was recorded or refused
+pub async fn delete_upload_ticket(pool: &PgPool, file_path: &str) -> Result<(), async_graphql::Error> {
+    sqlx::query("DELETE FROM upload_tickets WHERE file_path = $1")
+        .bind(file_path)
+        .execute(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Database error: {}", e)))?;
+
+    Ok(())
+}
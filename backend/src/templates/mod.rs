@@ -0,0 +1,402 @@
+mod parser;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::prompts;
+use crate::storage::prompt_templates;
+
+pub use parser::{Template, TemplateError};
+
+/// Prompt templates the application renders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TemplateName {
+    TopicChat,
+    ReviewChat,
+    GeneratePlan,
+    RevisePlan,
+    VisionExtraction,
+    ClassifyDocument,
+    ExtractProblems,
+    LinkProblems,
+    SummarizeChat,
+    RepairJson,
+}
+
+impl TemplateName {
+    pub const ALL: [TemplateName; 10] = [
+        TemplateName::TopicChat,
+        TemplateName::ReviewChat,
+        TemplateName::GeneratePlan,
+        TemplateName::RevisePlan,
+        TemplateName::VisionExtraction,
+        TemplateName::ClassifyDocument,
+        TemplateName::ExtractProblems,
+        TemplateName::LinkProblems,
+        TemplateName::SummarizeChat,
+        TemplateName::RepairJson,
+    ];
+
+    /// Name used in template files and the `prompt_templates` table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateName::TopicChat => "topic_chat",
+            TemplateName::ReviewChat => "review_chat",
+            TemplateName::GeneratePlan => "generate_plan",
+            TemplateName::RevisePlan => "revise_plan",
+            TemplateName::VisionExtraction => "vision_extraction",
+            TemplateName::ClassifyDocument => "classify_document",
+            TemplateName::ExtractProblems => "extract_problems",
+            TemplateName::LinkProblems => "link_problems",
+            TemplateName::SummarizeChat => "summarize_chat",
+            TemplateName::RepairJson => "repair_json",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+
+    /// Variables every version of the template must use
+    pub fn required_variables(&self) -> &'static [&'static str] {
+        match self {
+            TemplateName::TopicChat => &["topic_name", "context", "language"],
+            TemplateName::ReviewChat => &["context", "language"],
+            TemplateName::GeneratePlan => &["context", "title", "description", "language"],
+            TemplateName::RevisePlan => &["current_plan", "instruction", "context", "language"],
+            TemplateName::VisionExtraction => &[],
+            TemplateName::ClassifyDocument => &["file_name", "content"],
+            TemplateName::ExtractProblems => &["content"],
+            TemplateName::LinkProblems => &["topics", "problems"],
+            TemplateName::SummarizeChat => &["messages"],
+            TemplateName::RepairJson => &["errors", "schema"],
+        }
+    }
+
+    /// Variables a template may leave out (typically used in `{{#name}}` sections)
+    pub fn optional_variables(&self) -> &'static [&'static str] {
+        match self {
+            TemplateName::TopicChat | TemplateName::ReviewChat => &["study_plan", "problems"],
+            TemplateName::SummarizeChat => &["summary"],
+            _ => &[],
+        }
+    }
+
+    /// Template compiled into the binary, used when no override is loaded
+    fn builtin(&self) -> &'static str {
+        match self {
+            TemplateName::TopicChat => prompts::TOPIC_SYSTEM_PROMPT,
+            TemplateName::ReviewChat => prompts::REVIEW_SYSTEM_PROMPT,
+            TemplateName::GeneratePlan => prompts::GENERATE_PLAN_PROMPT,
+            TemplateName::RevisePlan => prompts::REVISE_PLAN_PROMPT,
+            TemplateName::VisionExtraction => prompts::VISION_EXTRACTION_PROMPT,
+            TemplateName::ClassifyDocument => prompts::CLASSIFY_DOCUMENT_PROMPT,
+            TemplateName::ExtractProblems => prompts::EXTRACT_PROBLEMS_PROMPT,
+            TemplateName::LinkProblems => prompts::LINK_PROBLEMS_PROMPT,
+            TemplateName::SummarizeChat => prompts::SUMMARIZE_CHAT_PROMPT,
+            TemplateName::RepairJson => prompts::REPAIR_JSON_PROMPT,
+        }
+    }
+}
+
+/// Values for a template's variables
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    values: HashMap<&'static str, String>,
+}
+
+impl TemplateVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.values.insert(name, value.into());
+        self
+    }
+}
+
+/// A rendered prompt and the template version that produced it
+#[derive(Debug, Clone)]
+pub struct RenderedPrompt {
+    pub text: String,
+    /// e.g. `topic_chat.pt@db:v3` or `review_chat@builtin`
+    pub version: String,
+}
+
+/// A loaded template with where it came from
+#[derive(Debug)]
+struct LoadedTemplate {
+    template: Template,
+    version: String,
+}
+
+type TemplateKey = (TemplateName, Option<String>);
+
+/// Active templates, keyed by name and locale (`None` for the default variant)
+///
+/// Overrides from the database win over template files, which win over the
+/// built-in templates. Invalid overrides are logged and ignored.
+pub struct TemplateRegistry {
+    templates: RwLock<HashMap<TemplateKey, Arc<LoadedTemplate>>>,
+}
+
+static REGISTRY: LazyLock<TemplateRegistry> = LazyLock::new(|| TemplateRegistry {
+    templates: RwLock::new(builtin_templates()),
+});
+
+/// Render the active template for `locale` (falling back to the default variant)
+pub fn render(
+    name: TemplateName,
+    locale: Option<&str>,
+    vars: &TemplateVars,
+) -> Result<RenderedPrompt, TemplateError> {
+    REGISTRY.render(name, locale, vars)
+}
+
+/// Load template overrides now and keep reloading them in the background
+pub fn spawn_reloader(pool: PgPool, config: Config) {
+    tokio::spawn(async move {
+        let interval = config.prompt_templates_reload_secs;
+        loop {
+            match REGISTRY.reload(&pool, &config).await {
+                Ok(overrides) => tracing::debug!("Loaded {} prompt template overrides", overrides),
+                Err(e) => tracing::error!("Failed to reload prompt templates: {}", e.message),
+            }
+
+            if interval == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+impl TemplateRegistry {
+    fn render(
+        &self,
+        name: TemplateName,
+        locale: Option<&str>,
+        vars: &TemplateVars,
+    ) -> Result<RenderedPrompt, TemplateError> {
+        for variable in vars.values.keys() {
+            if !name.required_variables().contains(variable) && !name.optional_variables().contains(variable) {
+                return Err(TemplateError::UnknownVariable {
+                    template: name.as_str().to_string(),
+                    variable: variable.to_string(),
+                });
+            }
+        }
+        for variable in name.required_variables() {
+            if !vars.values.contains_key(variable) {
+                return Err(TemplateError::MissingVariable {
+                    template: name.as_str().to_string(),
+                    variable: variable.to_string(),
+                });
+            }
+        }
+
+        let loaded = {
+            let templates = self.templates.read().unwrap_or_else(|e| e.into_inner());
+            locale
+                .and_then(|l| templates.get(&(name, Some(l.to_string()))))
+                .or_else(|| templates.get(&(name, None)))
+                .cloned()
+                .expect("built-in templates are always registered")
+        };
+
+        Ok(RenderedPrompt {
+            text: loaded.template.render(&vars.values),
+            version: loaded.version.clone(),
+        })
+    }
+
+    /// Rebuild the active set from the built-ins, template files and the database,
+    /// returning how many overrides were loaded
+    async fn reload(&self, pool: &PgPool, config: &Config) -> Result<usize, async_graphql::Error> {
+        let mut templates = builtin_templates();
+        let mut overrides = 0;
+
+        if let Some(dir) = &config.prompt_templates_dir {
+            for (key, loaded) in load_files(Path::new(dir)).await {
+                templates.insert(key, Arc::new(loaded));
+                overrides += 1;
+            }
+        }
+
+        for row in prompt_templates::get_active_templates(pool).await? {
+            let Some(name) = TemplateName::parse(&row.name) else {
+                tracing::warn!("Ignoring prompt template for unknown name {}", row.name);
+                continue;
+            };
+            let locale = Some(row.locale).filter(|l| !l.is_empty());
+            let version = format!("{}@db:v{}", label(name, locale.as_deref()), row.version);
+
+            match compile(name, &version, &row.body) {
+                Ok(loaded) => {
+                    templates.insert((name, locale), Arc::new(loaded));
+                    overrides += 1;
+                }
+                Err(e) => tracing::error!("Ignoring invalid prompt template: {}", e),
+            }
+        }
+
+        *self.templates.write().unwrap_or_else(|e| e.into_inner()) = templates;
+        Ok(overrides)
+    }
+}
+
+/// `name` or `name.locale`
+fn label(name: TemplateName, locale: Option<&str>) -> String {
+    match locale {
+        Some(locale) => format!("{}.{}", name.as_str(), locale),
+        None => name.as_str().to_string(),
+    }
+}
+
+fn compile(name: TemplateName, version: &str, source: &str) -> Result<LoadedTemplate, TemplateError> {
+    let template = Template::parse(
+        version,
+        source,
+        name.required_variables(),
+        name.optional_variables(),
+    )?;
+
+    Ok(LoadedTemplate {
+        template,
+        version: version.to_string(),
+    })
+}
+
+fn builtin_templates() -> HashMap<TemplateKey, Arc<LoadedTemplate>> {
+    TemplateName::ALL
+        .into_iter()
+        .map(|name| {
+            let version = format!("{}@builtin", name.as_str());
+            let loaded = compile(name, &version, name.builtin())
+                .unwrap_or_else(|e| panic!("Invalid built-in prompt template: {}", e));
+            ((name, None), Arc::new(loaded))
+        })
+        .collect()
+}
+
+/// Load `<name>.tmpl` and `<name>.<locale>.tmpl` files from a directory
+/// (versioned by a hash of their content)
+async fn load_files(dir: &Path) -> Vec<(TemplateKey, LoadedTemplate)> {
+    let mut loaded = Vec::new();
+
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("Cannot read prompt template directory {}: {}", dir.display(), e);
+            return loaded;
+        }
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(stem) = file_name.strip_suffix(".tmpl") else {
+            continue;
+        };
+        let (name, locale) = match stem.split_once('.') {
+            Some((name, locale)) => (name, Some(locale.to_string())),
+            None => (stem, None),
+        };
+        let Some(name) = TemplateName::parse(name) else {
+            tracing::warn!("Ignoring prompt template file for unknown name: {}", file_name);
+            continue;
+        };
+
+        let source = match tokio::fs::read_to_string(entry.path()).await {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::error!("Cannot read prompt template {}: {}", file_name, e);
+                continue;
+            }
+        };
+
+        let hash = hex::encode(Sha256::digest(source.as_bytes()));
+        let version = format!("{}@file:{}", label(name, locale.as_deref()), &hash[..8]);
+
+        match compile(name, &version, &source) {
+            Ok(template) => loaded.push(((name, locale), template)),
+            Err(e) => tracing::error!("Ignoring invalid prompt template: {}", e),
+        }
+    }
+
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values for every variable a template accepts, each marked with its name
+    fn all_vars(name: TemplateName) -> TemplateVars {
+        name.required_variables()
+            .iter()
+            .chain(name.optional_variables())
+            .fold(TemplateVars::new(), |vars, variable| vars.set(variable, format!("<{}>", variable)))
+    }
+
+    #[test]
+    fn every_builtin_template_renders() {
+        for name in TemplateName::ALL {
+            let rendered = render(name, None, &all_vars(name)).unwrap();
+
+            assert_eq!(rendered.version, format!("{}@builtin", name.as_str()));
+            assert!(!rendered.text.trim().is_empty(), "{} rendered empty", name.as_str());
+            for variable in name.required_variables().iter().chain(name.optional_variables()) {
+                assert!(
+                    rendered.text.contains(&format!("<{}>", variable)),
+                    "{} does not render {}",
+                    name.as_str(),
+                    variable
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for name in TemplateName::ALL {
+            assert_eq!(TemplateName::parse(name.as_str()), Some(name));
+        }
+        assert_eq!(TemplateName::parse("unknown"), None);
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_variables() {
+        let missing = render(TemplateName::SummarizeChat, None, &TemplateVars::new());
+        assert!(matches!(missing, Err(TemplateError::MissingVariable { variable, .. }) if variable == "messages"));
+
+        let vars = TemplateVars::new().set("messages", "hi").set("context", "docs");
+        let unknown = render(TemplateName::SummarizeChat, None, &vars);
+        assert!(matches!(unknown, Err(TemplateError::UnknownVariable { variable, .. }) if variable == "context"));
+    }
+
+    #[test]
+    fn prefers_the_locale_variant() {
+        let name = TemplateName::SummarizeChat;
+        let mut templates = builtin_templates();
+        let localized = compile(name, "summarize_chat.fr@test", "Résumé : {{messages}}").unwrap();
+        templates.insert((name, Some("fr".to_string())), Arc::new(localized));
+        let registry = TemplateRegistry {
+            templates: RwLock::new(templates),
+        };
+        let vars = TemplateVars::new().set("messages", "hi");
+
+        let french = registry.render(name, Some("fr"), &vars).unwrap();
+        assert_eq!(french.text, "Résumé : hi");
+        assert_eq!(french.version, "summarize_chat.fr@test");
+
+        let fallback = registry.render(name, Some("de"), &vars).unwrap();
+        assert_eq!(fallback.version, "summarize_chat@builtin");
+    }
+}
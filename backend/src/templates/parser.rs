@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+/// Why a template could not be loaded or rendered
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("template {template}: unknown variable {{{{{variable}}}}}")]
+    UnknownVariable { template: String, variable: String },
+    #[error("template {template}: required variable {{{{{variable}}}}} is never used")]
+    UnusedVariable { template: String, variable: String },
+    #[error("template {template}: variable {variable} was not provided")]
+    MissingVariable { template: String, variable: String },
+    #[error("template {template}: {message}")]
+    Syntax { template: String, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    /// `{{name}}`
+    Variable(String),
+    /// `{{#name}}...{{/name}}`, rendered only when `name` is set and not empty
+    Section(String, Vec<Node>),
+}
+
+/// A parsed template, ready to render
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parse `source`, checking it against the variables the template accepts
+    ///
+    /// Every `{{name}}` must be in `required` or `optional`, and every required
+    /// variable must be used at least once.
+    pub fn parse(
+        label: &str,
+        source: &str,
+        required: &[&str],
+        optional: &[&str],
+    ) -> Result<Self, TemplateError> {
+        let mut stack: Vec<(String, Vec<Node>)> = Vec::new();
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            push_text(&mut nodes, &rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| TemplateError::Syntax {
+                template: label.to_string(),
+                message: "unclosed {{".to_string(),
+            })?;
+            let tag = after[..end].trim();
+            rest = &after[end + 2..];
+
+            if let Some(name) = tag.strip_prefix('#') {
+                check_name(label, name.trim(), required, optional)?;
+                stack.push((name.trim().to_string(), std::mem::take(&mut nodes)));
+                rest = rest.strip_prefix('\n').unwrap_or(rest);
+            } else if let Some(name) = tag.strip_prefix('/') {
+                let (open, parent) = stack.pop().ok_or_else(|| TemplateError::Syntax {
+                    template: label.to_string(),
+                    message: format!("{{{{/{}}}}} without a matching opening tag", name.trim()),
+                })?;
+                if open != name.trim() {
+                    return Err(TemplateError::Syntax {
+                        template: label.to_string(),
+                        message: format!("{{{{#{}}}}} closed by {{{{/{}}}}}", open, name.trim()),
+                    });
+                }
+                let body = std::mem::replace(&mut nodes, parent);
+                nodes.push(Node::Section(open, body));
+                rest = rest.strip_prefix('\n').unwrap_or(rest);
+            } else {
+                check_name(label, tag, required, optional)?;
+                nodes.push(Node::Variable(tag.to_string()));
+            }
+        }
+        push_text(&mut nodes, rest);
+
+        if let Some((open, _)) = stack.pop() {
+            return Err(TemplateError::Syntax {
+                template: label.to_string(),
+                message: format!("{{{{#{}}}}} is never closed", open),
+            });
+        }
+
+        let template = Self { nodes };
+        for variable in required {
+            if !template.uses(variable) {
+                return Err(TemplateError::UnusedVariable {
+                    template: label.to_string(),
+                    variable: variable.to_string(),
+                });
+            }
+        }
+
+        Ok(template)
+    }
+
+    /// Render with the given values; unset optional variables render as empty
+    pub fn render(&self, vars: &HashMap<&'static str, String>) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, vars, &mut output);
+        output
+    }
+
+    fn uses(&self, variable: &str) -> bool {
+        fn walk(nodes: &[Node], variable: &str) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Text(_) => false,
+                Node::Variable(name) => name == variable,
+                Node::Section(_, body) => walk(body, variable),
+            })
+        }
+        walk(&self.nodes, variable)
+    }
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(text.to_string()));
+    }
+}
+
+fn check_name(label: &str, name: &str, required: &[&str], optional: &[&str]) -> Result<(), TemplateError> {
+    if required.contains(&name) || optional.contains(&name) {
+        Ok(())
+    } else {
+        Err(TemplateError::UnknownVariable {
+            template: label.to_string(),
+            variable: name.to_string(),
+        })
+    }
+}
+
+fn render_nodes(nodes: &[Node], vars: &HashMap<&'static str, String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(name) => {
+                if let Some(value) = vars.get(name.as_str()) {
+                    output.push_str(value);
+                }
+            }
+            Node::Section(name, body) => {
+                if vars.get(name.as_str()).is_some_and(|v| !v.trim().is_empty()) {
+                    render_nodes(body, vars, output);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(values: &[(&'static str, &str)]) -> HashMap<&'static str, String> {
+        values.iter().map(|(k, v)| (*k, v.to_string())).collect()
+    }
+
+    #[test]
+    fn renders_variables_and_sections() {
+        let template = Template::parse(
+            "test",
+            "Hello {{ name }}!\n{{#extra}}\nExtra: {{extra}}\n{{/extra}}\nBye",
+            &["name"],
+            &["extra"],
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&vars(&[("name", "Ada"), ("extra", "notes")])),
+            "Hello Ada!\nExtra: notes\nBye"
+        );
+        assert_eq!(template.render(&vars(&[("name", "Ada")])), "Hello Ada!\nBye");
+        assert_eq!(template.render(&vars(&[("name", "Ada"), ("extra", "  ")])), "Hello Ada!\nBye");
+    }
+
+    #[test]
+    fn rejects_unknown_variables() {
+        let result = Template::parse("test", "{{name}} {{other}}", &["name"], &[]);
+        assert!(matches!(result, Err(TemplateError::UnknownVariable { variable, .. }) if variable == "other"));
+
+        let result = Template::parse("test", "{{#other}}x{{/other}}{{name}}", &["name"], &[]);
+        assert!(matches!(result, Err(TemplateError::UnknownVariable { variable, .. }) if variable == "other"));
+    }
+
+    #[test]
+    fn rejects_unused_required_variables() {
+        let result = Template::parse("test", "{{name}}", &["name", "context"], &[]);
+        assert!(matches!(result, Err(TemplateError::UnusedVariable { variable, .. }) if variable == "context"));
+    }
+
+    #[test]
+    fn required_variables_may_be_used_inside_sections() {
+        let template = Template::parse("test", "{{#extra}}{{name}}{{/extra}}", &["name"], &["extra"]);
+        assert!(template.is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_tags() {
+        for source in ["{{name", "{{/name}}", "{{#extra}}{{name}}", "{{#extra}}{{name}}{{/name}}"] {
+            let result = Template::parse("test", source, &["name"], &["extra"]);
+            assert!(matches!(result, Err(TemplateError::Syntax { .. })), "accepted {:?}", source);
+        }
+    }
+}
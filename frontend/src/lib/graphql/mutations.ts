@@ -188,6 +188,20 @@ export const SEND_MESSAGE = gql`
       role
       content
       createdAt
+      status
+    }
+  }
+`;
+
+export const RETRY_MESSAGE = gql`
+  mutation RetryMessage($messageId: ID!) {
+    retryMessage(messageId: $messageId) {
+      id
+      chatId
+      role
+      content
+      createdAt
+      status
     }
   }
 `;
@@ -177,6 +177,7 @@ export const GET_MESSAGES = gql`
       role
       content
       createdAt
+      status
     }
   }
 `;
@@ -5,7 +5,7 @@ import { useTranslation } from 'react-i18next';
 import { toast } from 'sonner';
 import { getAuthToken } from '../lib/auth';
 import { GET_SESSION, GET_TOPICS, GET_CHATS, GET_MESSAGES, GET_REVIEW_CHAT, GET_DOCUMENTS, GET_DOCUMENT_URL } from '../lib/graphql/queries';
-import { SEND_MESSAGE, UPDATE_TOPIC_COMPLETION, DELETE_DOCUMENT } from '../lib/graphql/mutations';
+import { SEND_MESSAGE, RETRY_MESSAGE, UPDATE_TOPIC_COMPLETION, DELETE_DOCUMENT } from '../lib/graphql/mutations';
 import Header from '../components/Header';
 import Markdown from '../components/ui/Markdown';
 import type { Session as SessionType, Topic, Chat, Message, Document } from '../types';
@@ -624,24 +624,29 @@ function TopicChat({
 
   // Mutations
   const [sendMessage, { loading: sending }] = useMutation<{ sendMessage: Message }>(SEND_MESSAGE);
+  const [retryMessage] = useMutation<{ retryMessage: Message }>(RETRY_MESSAGE);
+  const [retryingId, setRetryingId] = useState<string | null>(null);
 
-  const serverMessages: Message[] = messagesData?.messages || [];
+  // Replies are generated in the background and stay pending until they are done
+  const allServerMessages: Message[] = messagesData?.messages || [];
+  const replyPending = allServerMessages.some((msg) => msg.status === 'PENDING');
+  const serverMessages = allServerMessages.filter((msg) => msg.status !== 'PENDING');
   const [optimisticMessages, setOptimisticMessages] = useState<Message[]>([]);
   const [aiTyping, setAiTyping] = useState(false);
   const messages: Message[] = [...serverMessages, ...optimisticMessages];
 
   // Poll for messages while they're being generated in the background
-  const isWaitingForWelcome = !loadingMessages && serverMessages.length === 0;
+  const isWaitingForReply = !loadingMessages && (allServerMessages.length === 0 || replyPending);
   
   useEffect(() => {
-    if (isWaitingForWelcome) {
-      // Poll every 1 second while waiting for the welcome message
+    if (isWaitingForReply) {
+      // Poll every 1 second while waiting for the welcome message or a reply
       const interval = setInterval(() => {
         refetchMessages();
       }, 1000);
       return () => clearInterval(interval);
     }
-  }, [isWaitingForWelcome, refetchMessages]);
+  }, [isWaitingForReply, refetchMessages]);
 
   // Auto-scroll
   useEffect(() => {
@@ -689,12 +694,31 @@ function TopicChat({
     } catch (err: any) {
       console.error('Send error:', err);
       toast.error(err.message || t('session.chat.sendError'));
-      setMessageInput(content);
+      // The message is kept if only the reply failed (it shows as failed, with a retry button)
+      const { data } = await refetchMessages();
+      const lastUserMessage = data?.messages.filter((msg) => msg.role === 'user').at(-1);
+      if (lastUserMessage?.content !== content) {
+        setMessageInput(content);
+      }
       setOptimisticMessages([]);
       setAiTyping(false);
     }
   };
 
+  const handleRetryMessage = async (messageId: string) => {
+    if (retryingId) return;
+    setRetryingId(messageId);
+    try {
+      await retryMessage({ variables: { messageId } });
+    } catch (err: any) {
+      console.error('Retry error:', err);
+      toast.error(err.message || t('session.chat.retryError'));
+    } finally {
+      await refetchMessages();
+      setRetryingId(null);
+    }
+  };
+
   const chatTitle = isReviewChat ? t('session.reviewGeneral') : topic?.title || t('session.chat.title');
 
   // Determine popup styles based on expansion state
@@ -802,9 +826,15 @@ function TopicChat({
             ) : (
               <>
                 {messages.map((msg) => (
-                  <MessageBubble key={msg.id} message={msg} isMobile={isMobile} />
+                  <MessageBubble
+                    key={msg.id}
+                    message={msg}
+                    isMobile={isMobile}
+                    retrying={retryingId === msg.id}
+                    onRetry={() => handleRetryMessage(msg.id)}
+                  />
                 ))}
-                {aiTyping && (
+                {(aiTyping || replyPending || retryingId) && (
                   <div className="flex justify-start mb-4">
                     <div className={`max-w-[85%] md:max-w-2xl rounded-2xl ${isMobile ? 'px-3 py-2' : 'px-4 py-3'} bg-white text-caky-text rounded-tl-none border border-caky-secondary/30 shadow-sm`}>
                       <div className="flex items-center gap-2">
@@ -879,13 +909,30 @@ function TopicChat({
 interface MessageBubbleProps {
   message: Message;
   isMobile: boolean;
+  retrying: boolean;
+  onRetry: () => void;
 }
 
-function MessageBubble({ message, isMobile }: MessageBubbleProps) {
+function MessageBubble({ message, isMobile, retrying, onRetry }: MessageBubbleProps) {
+  const { t } = useTranslation();
   const isUser = message.role === 'user';
 
   const renderContent = () => {
-    if (isUser) {
+    if (message.status === 'FAILED') {
+      return (
+        <div className="flex flex-wrap items-center gap-3 not-prose">
+          <span className="text-red-600 dark:text-red-400">{t('session.chat.replyFailed')}</span>
+          <button
+            type="button"
+            onClick={onRetry}
+            disabled={retrying}
+            className="px-3 py-1 text-sm font-bold text-caky-primary border border-caky-primary/40 rounded-lg hover:bg-caky-primary/10 transition disabled:opacity-50 disabled:cursor-not-allowed"
+          >
+            {t('session.chat.retry')}
+          </button>
+        </div>
+      );
+    } else if (isUser) {
       return (
         <div className="whitespace-pre-wrap break-words">
           {message.content}
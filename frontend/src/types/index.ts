@@ -73,4 +73,5 @@ export interface Message {
   role: 'user' | 'assistant' | 'system';
   content: string;
   createdAt: string;
+  status?: 'PENDING' | 'COMPLETE' | 'FAILED';
 }